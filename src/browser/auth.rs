@@ -0,0 +1,235 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::str;
+use std::sync::{Mutex, Once, ONCE_INIT};
+
+use hyper::header::{Authorization, Basic, Headers};
+use url::Url;
+
+use content::escape;
+
+/// A user name and password given for a protection space, sent with every request below it
+struct Credentials {
+    origin: String,
+    /// The directory of the page that asked for them
+    path: String,
+    username: String,
+    password: String,
+}
+
+/// A sign in page that has been shown, identified by a token only that page knows,
+/// so that no other page can submit credentials for it
+struct Challenge {
+    token: String,
+    url: Url,
+}
+
+/// Credentials given this session, which are never saved
+struct Store {
+    credentials: Vec<Credentials>,
+    challenges: Vec<Challenge>,
+}
+
+static STORE_INIT: Once = ONCE_INIT;
+static mut STORE: *const Mutex<Store> = 0 as *const Mutex<Store>;
+
+fn store() -> &'static Mutex<Store> {
+    unsafe {
+        STORE_INIT.call_once(|| {
+            STORE = Box::into_raw(Box::new(Mutex::new(Store {
+                credentials: Vec::new(),
+                challenges: Vec::new(),
+            })));
+        });
+        &*STORE
+    }
+}
+
+fn directory(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(slash) => path[.. slash + 1].to_string(),
+        None => String::from("/")
+    }
+}
+
+impl Credentials {
+    fn matches(&self, url: &Url) -> bool {
+        self.origin == url.origin().ascii_serialization() && url.path().starts_with(&self.path)
+    }
+}
+
+/// Add the credentials of the URL itself, or those given for a page above it, to the headers of a request
+pub fn request(url: &Url, headers: &mut Headers) {
+    if ! url.username().is_empty() {
+        headers.set(Authorization(Basic {
+            username: url.username().to_string(),
+            password: url.password().map(|password| password.to_string()),
+        }));
+        return;
+    }
+
+    let store = store().lock().unwrap();
+    if let Some(credentials) = store.credentials.iter().find(|credentials| credentials.matches(url)) {
+        headers.set(Authorization(Basic {
+            username: credentials.username.clone(),
+            password: Some(credentials.password.clone()),
+        }));
+    }
+}
+
+/// The realm of a challenge for basic authentication in a response, if it has one
+pub fn challenge(headers: &Headers) -> Option<String> {
+    let values = match headers.get_raw("WWW-Authenticate") {
+        Some(values) => values,
+        None => return None
+    };
+
+    for value in values.iter() {
+        let value = match str::from_utf8(value) {
+            Ok(value) => value.trim(),
+            Err(_) => continue
+        };
+        if ! value.to_lowercase().starts_with("basic") {
+            continue;
+        }
+
+        let realm = match value.find("realm=") {
+            Some(start) => {
+                let rest = &value[start + 6 ..];
+                if rest.starts_with('"') {
+                    rest[1 ..].split('"').next().unwrap_or("")
+                } else {
+                    rest.split(',').next().unwrap_or("").trim()
+                }
+            },
+            None => ""
+        };
+        return Some(realm.to_string());
+    }
+    None
+}
+
+/// The page asking for a user name and password for a URL, with a notice if the ones given before were refused
+pub fn page(url: &Url, realm: &str) -> String {
+    let mut store = store().lock().unwrap();
+
+    // Credentials that were sent and still got a challenge are wrong, and are forgotten
+    let len = store.credentials.len();
+    store.credentials.retain(|credentials| ! credentials.matches(url));
+    let refused = store.credentials.len() != len || ! url.username().is_empty();
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(store.challenges.len());
+    let token = format!("{:016x}", hasher.finish());
+    store.challenges.push(Challenge {
+        token: token.clone(),
+        url: url.clone(),
+    });
+
+    let host = url.host_str().unwrap_or("");
+    let mut html = String::from("<html><head><title>Sign in</title></head><body><h1>Sign in</h1>");
+    if realm.is_empty() {
+        html.push_str(&format!("<p>{} asks for a user name and password.</p>", escape(host)));
+    } else {
+        html.push_str(&format!("<p>{} asks for a user name and password for \"{}\".</p>", escape(host), escape(realm)));
+    }
+    if refused {
+        html.push_str("<p><b>The user name or password was not accepted.</b></p>");
+    }
+    if url.scheme() != "https" {
+        html.push_str("<p>The password will be sent without encryption.</p>");
+    }
+    html.push_str(&format!("<form method=\"post\" action=\"about:login\"><input type=\"hidden\" name=\"token\" value=\"{}\">", token));
+    html.push_str("<p>User name: <input type=\"text\" name=\"username\" size=\"30\"></p>");
+    html.push_str("<p>Password: <input type=\"password\" name=\"password\" size=\"30\"></p>");
+    html.push_str("<p><input type=\"submit\" value=\"Sign in\"></p></form></body></html>");
+    html
+}
+
+/// Remember the credentials submitted from a sign in page, returning the URL to load again with them
+pub fn login(token: &str, username: &str, password: &str) -> Option<Url> {
+    let mut store = store().lock().unwrap();
+
+    let position = store.challenges.iter().position(|challenge| challenge.token == token);
+    let challenge = match position {
+        Some(index) => store.challenges.remove(index),
+        None => return None
+    };
+
+    store.credentials.push(Credentials {
+        origin: challenge.url.origin().ascii_serialization(),
+        path: directory(&challenge.url),
+        username: username.to_string(),
+        password: password.to_string(),
+    });
+    Some(challenge.url)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::{Authorization, Basic, Headers};
+    use url::Url;
+
+    use super::{challenge, directory, login, page, request};
+
+    fn headers(values: &[&str]) -> Headers {
+        let mut headers = Headers::new();
+        headers.set_raw("WWW-Authenticate", values.iter().map(|value| value.as_bytes().to_vec()).collect());
+        headers
+    }
+
+    fn authorization(url: &str) -> Option<Basic> {
+        let mut headers = Headers::new();
+        request(&Url::parse(url).unwrap(), &mut headers);
+        headers.get::<Authorization<Basic>>().map(|authorization| authorization.0.clone())
+    }
+
+    #[test]
+    fn challenges() {
+        assert_eq!(challenge(&headers(&["Basic realm=\"Staff only, please\""])), Some(String::from("Staff only, please")));
+        assert_eq!(challenge(&headers(&["basic realm=intranet, charset=\"UTF-8\""])), Some(String::from("intranet")));
+        assert_eq!(challenge(&headers(&["Basic"])), Some(String::new()));
+        assert_eq!(challenge(&headers(&["Basic realm=\"unterminated"])), Some(String::from("unterminated")));
+
+        // Only basic authentication is supported
+        assert_eq!(challenge(&headers(&["Bearer realm=\"api\"", "Basic realm=\"site\""])), Some(String::from("site")));
+        assert_eq!(challenge(&headers(&["Digest realm=\"site\", nonce=\"1\""])), None);
+        assert_eq!(challenge(&Headers::new()), None);
+    }
+
+    #[test]
+    fn directories() {
+        assert_eq!(directory(&Url::parse("http://example.com/a/b/page.html?q=1").unwrap()), "/a/b/");
+        assert_eq!(directory(&Url::parse("http://example.com/a/b/").unwrap()), "/a/b/");
+        assert_eq!(directory(&Url::parse("http://example.com").unwrap()), "/");
+    }
+
+    #[test]
+    fn sign_in() {
+        let url = Url::parse("http://sign-in.test/private/index.html").unwrap();
+        let html = page(&url, "private");
+        let start = html.find("name=\"token\" value=\"").unwrap() + 20;
+        let token = html[start ..].split('"').next().unwrap().to_string();
+
+        // A token is only accepted once, and only one a page was given
+        assert_eq!(login("0000", "user", "secret"), None);
+        assert_eq!(login(&token, "user", "secret"), Some(url.clone()));
+        assert_eq!(login(&token, "user", "secret"), None);
+
+        // The credentials are sent below the directory of the page on the same origin only
+        let basic = authorization("http://sign-in.test/private/more/page.html").unwrap();
+        assert_eq!((basic.username.as_str(), basic.password), ("user", Some(String::from("secret"))));
+        assert!(authorization("http://sign-in.test/public/page.html").is_none());
+        assert!(authorization("https://sign-in.test/private/index.html").is_none());
+        assert!(authorization("http://sign-in.test:8080/private/index.html").is_none());
+
+        // The user name and password of the URL itself take precedence
+        let basic = authorization("http://admin:pw@sign-in.test/private/index.html").unwrap();
+        assert_eq!((basic.username.as_str(), basic.password), ("admin", Some(String::from("pw"))));
+
+        // Credentials that were refused are forgotten, and the page says so
+        assert!(page(&url, "private").contains("not accepted"));
+        assert!(authorization("http://sign-in.test/private/index.html").is_none());
+    }
+}
@@ -0,0 +1,119 @@
+use std::{fs, slice};
+use std::fs::File;
+use std::io::{stderr, Read, Write};
+use std::path::PathBuf;
+
+use url::Url;
+
+use content::escape;
+use settings;
+
+/// A page the user has marked, to be found again on the bookmarks page
+pub struct Bookmark {
+    pub url: String,
+    pub title: String,
+}
+
+fn bookmarks_path() -> Option<PathBuf> {
+    settings::config_dir().map(|dir| dir.join("bookmarks"))
+}
+
+/// The bookmarks, in the order they were added
+pub struct Bookmarks {
+    bookmarks: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    pub fn load() -> Bookmarks {
+        let mut data = String::new();
+        if let Some(path) = bookmarks_path() {
+            if let Ok(mut file) = File::open(&path) {
+                let _ = file.read_to_string(&mut data);
+            }
+        }
+
+        // Each line is the URL and the title of a bookmark, separated by a tab
+        let mut bookmarks = Vec::new();
+        for line in data.lines() {
+            let mut fields = line.splitn(2, '\t');
+            if let Some(url) = fields.next() {
+                if ! url.is_empty() {
+                    bookmarks.push(Bookmark {
+                        url: url.to_string(),
+                        title: fields.next().unwrap_or("").to_string(),
+                    });
+                }
+            }
+        }
+
+        Bookmarks {
+            bookmarks: bookmarks,
+        }
+    }
+
+    fn save(&self) {
+        let path = match bookmarks_path() {
+            Some(path) => path,
+            None => return
+        };
+
+        let mut data = String::new();
+        for bookmark in self.bookmarks.iter() {
+            data.push_str(&format!("{}\t{}\n", bookmark.url, bookmark.title));
+        }
+
+        let result = path.parent().map_or(Ok(()), |parent| fs::create_dir_all(parent))
+            .and_then(|_| File::create(&path))
+            .and_then(|mut file| file.write_all(data.as_bytes()));
+        if let Err(err) = result {
+            let _ = write!(stderr(), "* Failed to save bookmarks to {}: {}\n", path.display(), err);
+        }
+    }
+
+    pub fn iter(&self) -> slice::Iter<Bookmark> {
+        self.bookmarks.iter()
+    }
+
+    pub fn contains(&self, url: &Url) -> bool {
+        self.bookmarks.iter().any(|bookmark| bookmark.url == url.as_str())
+    }
+
+    /// Bookmark a page, or remove its bookmark if it has one, returning whether it is now bookmarked
+    pub fn toggle(&mut self, url: &Url, title: &str) -> bool {
+        let len = self.bookmarks.len();
+        self.bookmarks.retain(|bookmark| bookmark.url != url.as_str());
+        let added = self.bookmarks.len() == len;
+        if added {
+            self.bookmarks.push(Bookmark {
+                url: url.to_string(),
+                title: title.replace('\t', " ").replace('\n', " "),
+            });
+        }
+
+        self.save();
+        added
+    }
+}
+
+/// The bookmarks page, as an HTML document listing the saved bookmarks
+pub fn page() -> String {
+    let bookmarks = Bookmarks::load();
+
+    let mut html = String::from("<html><head><title>Bookmarks</title></head><body><h1>Bookmarks</h1>");
+    if bookmarks.bookmarks.is_empty() {
+        html.push_str("<p>There are no bookmarks. Press Ctrl+D or the * button to bookmark a page.</p>");
+    } else {
+        html.push_str("<ul>");
+        for bookmark in bookmarks.iter() {
+            let title = if bookmark.title.is_empty() {
+                &bookmark.url
+            } else {
+                &bookmark.title
+            };
+            html.push_str(&format!("<li><a href=\"{}\">{}</a><br>{}</li>", escape(&bookmark.url), escape(title), escape(&bookmark.url)));
+        }
+        html.push_str("</ul>");
+    }
+    html.push_str("</body></html>");
+    html
+}
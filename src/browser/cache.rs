@@ -0,0 +1,390 @@
+use std::{cmp, fs, process, str};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Once, ONCE_INIT};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::header::{Headers, HttpDate};
+use url::Url;
+
+use settings;
+
+/// The most the cache directory may hold before the oldest entries are evicted
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How long a response without an explicit lifetime stays fresh at most, when guessed from `Last-Modified`
+const MAX_HEURISTIC: u64 = 24 * 60 * 60;
+
+/// How many bytes the cache directory holds, counted once and then kept up to date as entries are written
+static SIZE_INIT: Once = ONCE_INIT;
+static SIZE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Numbers the temporary files of this process, so that threads writing the same entry never share one
+static TMP_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// A cached response, fresh until `expires`, after which it is revalidated with its `ETag` or `Last-Modified`
+pub struct Entry {
+    pub headers: Headers,
+    pub data: Vec<u8>,
+    expires: u64,
+}
+
+impl Entry {
+    pub fn is_fresh(&self) -> bool {
+        now() < self.expires
+    }
+
+    /// Add the headers that make a request conditional on the entry having changed
+    pub fn validators(&self, headers: &mut Headers) {
+        if let Some(etag) = header_str(&self.headers, "ETag") {
+            headers.set_raw("If-None-Match", vec![etag.into_bytes()]);
+        }
+        if let Some(last_modified) = header_str(&self.headers, "Last-Modified") {
+            headers.set_raw("If-Modified-Since", vec![last_modified.into_bytes()]);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+fn header_str(headers: &Headers, name: &str) -> Option<String> {
+    headers.get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| str::from_utf8(value).ok())
+        .map(|value| value.trim().to_string())
+}
+
+fn header_date(headers: &Headers, name: &str) -> Option<u64> {
+    header_str(headers, name)
+        .and_then(|value| value.parse::<HttpDate>().ok())
+        .map(|date| cmp::max(0, date.0.to_timespec().sec) as u64)
+}
+
+fn entry_path(url: &Url) -> Option<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    url.as_str().hash(&mut hasher);
+    settings::cache_dir().map(|dir| dir.join(format!("{:016x}", hasher.finish())))
+}
+
+/// When a response stops being fresh, or `None` if it must not be stored
+fn expiry(headers: &Headers, now: u64) -> Option<u64> {
+    let has_validator = headers.get_raw("ETag").is_some() || headers.get_raw("Last-Modified").is_some();
+
+    let mut no_cache = header_str(headers, "Pragma").map_or(false, |pragma| pragma.to_lowercase().contains("no-cache"));
+    let mut max_age = None;
+    if let Some(cache_control) = header_str(headers, "Cache-Control") {
+        for directive in cache_control.split(',') {
+            let directive = directive.trim().to_lowercase();
+            if directive == "no-store" {
+                return None;
+            } else if directive == "no-cache" {
+                no_cache = true;
+            } else if directive.starts_with("max-age=") {
+                max_age = directive["max-age=".len()..].trim_matches('"').parse::<u64>().ok();
+            }
+        }
+    }
+
+    let expires = if no_cache {
+        now
+    } else if let Some(max_age) = max_age {
+        now + max_age
+    } else if let Some(expires) = header_date(headers, "Expires") {
+        expires
+    } else if let Some(last_modified) = header_date(headers, "Last-Modified") {
+        // Without a lifetime, a resource is guessed to stay unchanged for a tenth of its age
+        now + cmp::min(now.saturating_sub(last_modified) / 10, MAX_HEURISTIC)
+    } else {
+        now
+    };
+
+    // A stale entry without a validator would only ever be fetched again
+    if expires <= now && ! has_validator {
+        None
+    } else {
+        Some(expires)
+    }
+}
+
+/// Look up the cached response for a URL, fresh or not
+pub fn get(url: &Url) -> Option<Entry> {
+    let path = match entry_path(url) {
+        Some(path) => path,
+        None => return None
+    };
+
+    let mut data = Vec::new();
+    match File::open(&path) {
+        Ok(mut file) => if file.read_to_end(&mut data).is_err() {
+            return None;
+        },
+        Err(_) => return None
+    }
+
+    // The entry is the URL, the expiry time and the headers, one per line, then an empty line and the body
+    let split = match data.windows(2).position(|window| window == b"\n\n") {
+        Some(split) => split,
+        None => return None
+    };
+    let body = data.split_off(split + 2);
+    let head = match str::from_utf8(&data[..split]) {
+        Ok(head) => head.to_string(),
+        Err(_) => return None
+    };
+
+    let mut lines = head.lines();
+    if lines.next() != Some(url.as_str()) {
+        return None;
+    }
+    let expires = match lines.next().and_then(|line| line.parse::<u64>().ok()) {
+        Some(expires) => expires,
+        None => return None
+    };
+
+    let mut headers = Headers::new();
+    for line in lines {
+        let mut parts = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            headers.set_raw(name.trim().to_string(), vec![value.trim().as_bytes().to_vec()]);
+        }
+    }
+
+    Some(Entry {
+        headers: headers,
+        data: body,
+        expires: expires,
+    })
+}
+
+/// Store a response, unless its headers forbid it, evicting old entries when the cache is over its size
+pub fn put(url: &Url, headers: &Headers, data: &[u8]) {
+    if data.len() as u64 > MAX_SIZE / 8 {
+        return;
+    }
+
+    let expires = match expiry(headers, now()) {
+        Some(expires) => expires,
+        None => return
+    };
+
+    let (dir, path) = match (settings::cache_dir(), entry_path(url)) {
+        (Some(dir), Some(path)) => (dir, path),
+        _ => return
+    };
+
+    if fs::create_dir_all(&dir).is_err() {
+        return;
+    }
+
+    let mut entry = format!("{}\n{}\n", url, expires);
+    for header in headers.iter() {
+        // Cookies are never replayed from the cache
+        match &*header.name().to_lowercase() {
+            "set-cookie" | "content-length" | "transfer-encoding" | "connection" => continue,
+            _ => ()
+        }
+        entry.push_str(&format!("{}: {}\n", header.name(), header.value_string().replace("\n", " ")));
+    }
+    entry.push('\n');
+
+    SIZE_INIT.call_once(|| {
+        SIZE.store(scan(&dir).1 as usize, Ordering::SeqCst);
+    });
+    let replaced = fs::metadata(&path).map(|metadata| metadata.len() as usize).unwrap_or(0);
+
+    // Entries are written to a temporary file of their own first, so that a reader never sees half of one
+    // and two threads storing the same URL never write into the same file
+    let tmp_path = path.with_extension(format!("{}-{}.tmp", process::id(), TMP_COUNT.fetch_add(1, Ordering::SeqCst)));
+    let written = File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(entry.as_bytes())?;
+        file.write_all(data)
+    });
+    if written.is_err() || fs::rename(&tmp_path, &path).is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return;
+    }
+
+    let size = SIZE.fetch_add(entry.len() + data.len(), Ordering::SeqCst) + entry.len() + data.len();
+    SIZE.fetch_sub(cmp::min(replaced, size), Ordering::SeqCst);
+    if size.saturating_sub(replaced) as u64 > MAX_SIZE {
+        evict(&dir);
+    }
+}
+
+/// Refresh a stale entry after the server answered `304 Not Modified`, returning it to be used
+pub fn revalidate(url: &Url, mut entry: Entry, headers: &Headers) -> Entry {
+    for header in headers.iter() {
+        if header.name().to_lowercase() != "content-length" {
+            entry.headers.set_raw(header.name().to_string(), vec![header.value_string().into_bytes()]);
+        }
+    }
+    put(url, &entry.headers, &entry.data);
+    entry
+}
+
+/// The entries of the cache directory with when they were last written and their sizes, and their total size.
+/// Temporary files are left out, as they are still being written.
+fn scan(dir: &Path) -> (Vec<(SystemTime, u64, PathBuf)>, u64) {
+    let mut entries = Vec::new();
+    let mut total = 0;
+    if let Ok(read_dir) = fs::read_dir(dir) {
+        for dir_entry in read_dir {
+            if let Ok(dir_entry) = dir_entry {
+                let path = dir_entry.path();
+                if path.extension().map_or(false, |extension| extension == "tmp") {
+                    continue;
+                }
+                if let Ok(metadata) = dir_entry.metadata() {
+                    if metadata.is_file() {
+                        total += metadata.len();
+                        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+                        entries.push((modified, metadata.len(), path));
+                    }
+                }
+            }
+        }
+    }
+    (entries, total)
+}
+
+/// Remove the entries stored or revalidated longest ago until the cache is within its size,
+/// counting the directory again, as other processes may have written to it too
+fn evict(dir: &Path) {
+    let (mut entries, mut total) = scan(dir);
+
+    if total > MAX_SIZE {
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        for &(_, size, ref path) in entries.iter() {
+            if total <= MAX_SIZE {
+                break;
+            }
+            if fs::remove_file(path).is_ok() {
+                total -= size;
+            }
+        }
+    }
+
+    SIZE.store(total as usize, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::{env, fs, process};
+
+    use hyper::header::Headers;
+
+    use super::{expiry, scan, Entry, MAX_HEURISTIC};
+
+    /// 2015-10-21 07:28:00 GMT
+    const NOW: u64 = 1445412480;
+
+    fn headers(pairs: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::new();
+        for &(name, value) in pairs.iter() {
+            headers.set_raw(name.to_string(), vec![value.as_bytes().to_vec()]);
+        }
+        headers
+    }
+
+    fn expires(pairs: &[(&str, &str)]) -> Option<u64> {
+        expiry(&headers(pairs), NOW)
+    }
+
+    #[test]
+    fn explicit_lifetimes() {
+        assert_eq!(expires(&[("Cache-Control", "max-age=60")]), Some(NOW + 60));
+        assert_eq!(expires(&[("Cache-Control", "public, Max-Age=\"60\"")]), Some(NOW + 60));
+        assert_eq!(expires(&[("Expires", "Wed, 21 Oct 2015 08:28:00 GMT")]), Some(NOW + 3600));
+
+        // Max-age takes precedence over Expires
+        assert_eq!(expires(&[("Cache-Control", "max-age=60"), ("Expires", "Wed, 21 Oct 2015 08:28:00 GMT")]), Some(NOW + 60));
+
+        // An invalid lifetime is ignored
+        assert_eq!(expires(&[("Cache-Control", "max-age=soon"), ("Expires", "Wed, 21 Oct 2015 08:28:00 GMT")]), Some(NOW + 3600));
+        assert_eq!(expires(&[("Expires", "0"), ("ETag", "\"v1\"")]), Some(NOW));
+    }
+
+    #[test]
+    fn heuristic_lifetime() {
+        // A tenth of the time since the resource last changed, up to a day
+        assert_eq!(expires(&[("Last-Modified", "Wed, 21 Oct 2015 07:11:20 GMT")]), Some(NOW + 100));
+        assert_eq!(expires(&[("Last-Modified", "Tue, 21 Oct 2014 07:28:00 GMT")]), Some(NOW + MAX_HEURISTIC));
+
+        // A date in the future gives no lifetime, but still a validator
+        assert_eq!(expires(&[("Last-Modified", "Thu, 22 Oct 2015 07:28:00 GMT")]), Some(NOW));
+    }
+
+    #[test]
+    fn not_stored() {
+        assert_eq!(expires(&[("Cache-Control", "no-store"), ("ETag", "\"v1\"")]), None);
+        assert_eq!(expires(&[("Cache-Control", "max-age=60, No-Store")]), None);
+
+        // Stale responses without a validator would only ever be fetched again
+        assert_eq!(expires(&[]), None);
+        assert_eq!(expires(&[("Cache-Control", "no-cache")]), None);
+        assert_eq!(expires(&[("Cache-Control", "max-age=0")]), None);
+        assert_eq!(expires(&[("Pragma", "no-cache")]), None);
+        assert_eq!(expires(&[("Expires", "Tue, 21 Oct 2014 07:28:00 GMT")]), None);
+    }
+
+    #[test]
+    fn revalidated() {
+        // Responses that are stale at once are kept when they can be revalidated
+        assert_eq!(expires(&[("Cache-Control", "no-cache"), ("ETag", "\"v1\"")]), Some(NOW));
+        assert_eq!(expires(&[("Cache-Control", "max-age=3600, no-cache"), ("ETag", "\"v1\"")]), Some(NOW));
+        assert_eq!(expires(&[("Pragma", "no-cache"), ("Cache-Control", "max-age=60"), ("ETag", "\"v1\"")]), Some(NOW));
+        assert_eq!(expires(&[("ETag", "\"v1\"")]), Some(NOW));
+        assert_eq!(expires(&[("Expires", "Tue, 21 Oct 2014 07:28:00 GMT"), ("Last-Modified", "Tue, 21 Oct 2014 07:28:00 GMT")]), Some(NOW - 365 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn entries() {
+        let entry = Entry {
+            headers: headers(&[("ETag", "\"v1\""), ("Last-Modified", "Tue, 21 Oct 2014 07:28:00 GMT")]),
+            data: Vec::new(),
+            expires: 0,
+        };
+        assert!(! entry.is_fresh());
+
+        let mut request = Headers::new();
+        entry.validators(&mut request);
+        assert_eq!(request.get_raw("If-None-Match"), Some(&[b"\"v1\"".to_vec()][..]));
+        assert_eq!(request.get_raw("If-Modified-Since"), Some(&[b"Tue, 21 Oct 2014 07:28:00 GMT".to_vec()][..]));
+
+        let entry = Entry {
+            headers: Headers::new(),
+            data: Vec::new(),
+            expires: u64::max_value(),
+        };
+        assert!(entry.is_fresh());
+
+        let mut request = Headers::new();
+        entry.validators(&mut request);
+        assert_eq!(request.len(), 0);
+    }
+
+    #[test]
+    fn scan_skips_temporary_files() {
+        let dir = env::temp_dir().join(format!("browser-cache-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("subdirectory")).unwrap();
+        File::create(dir.join("0123456789abcdef")).unwrap().write_all(b"abc").unwrap();
+        File::create(dir.join("0123456789abcdef.42-0.tmp")).unwrap().write_all(b"being written").unwrap();
+
+        let (entries, total) = scan(&dir);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(total, 3);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].1, 3);
+        assert_eq!(entries[0].2, dir.join("0123456789abcdef"));
+    }
+}
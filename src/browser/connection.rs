@@ -0,0 +1,108 @@
+use std::env;
+use std::fs::File;
+use std::io::{stderr, BufReader, Write};
+use std::sync::{Arc, Once, ONCE_INIT};
+
+use hyper::Client;
+use hyper::client::ProxyConfig;
+use hyper::net::{HttpConnector, HttpsConnector};
+use hyper_rustls::TlsClient;
+use rustls::ClientConfig;
+use url::Url;
+
+use settings;
+
+static TLS_INIT: Once = ONCE_INIT;
+static mut TLS: *const Arc<ClientConfig> = 0 as *const Arc<ClientConfig>;
+
+/// A TLS client trusting the usual certificate authorities and those in the files the settings list,
+/// which are loaded the first time a secure connection is made
+fn tls_client() -> TlsClient {
+    unsafe {
+        TLS_INIT.call_once(|| {
+            let mut client = TlsClient::new();
+            if let Some(config) = Arc::get_mut(&mut client.cfg) {
+                for path in settings::get().certificates.iter() {
+                    let result = File::open(path).map_err(|err| format!("{}", err)).and_then(|file| {
+                        config.root_store.add_pem_file(&mut BufReader::new(file)).map_err(|_| format!("Not a PEM file"))
+                    });
+                    let _ = match result {
+                        Ok((added, 0)) => write!(stderr(), "* Trusting {} certificates from {}\n", added, path),
+                        Ok((added, skipped)) => write!(stderr(), "* Trusting {} certificates from {}, skipped {} invalid\n", added, path, skipped),
+                        Err(err) => write!(stderr(), "* Failed to load certificates from {}: {}\n", path, err)
+                    };
+                }
+            }
+            TLS = Box::into_raw(Box::new(client.cfg));
+        });
+        TlsClient {
+            cfg: (*TLS).clone(),
+        }
+    }
+}
+
+/// An environment variable, in lower or upper case, if it is set to something
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).or_else(|_| env::var(name.to_uppercase())).ok()
+        .map(|value| value.trim().to_string())
+        .and_then(|value| if value.is_empty() { None } else { Some(value) })
+}
+
+/// Whether `no_proxy` lists a host, by itself or as a domain it is in, or is `*`
+fn bypass(host: &str) -> bool {
+    let no_proxy = match env_var("no_proxy") {
+        Some(no_proxy) => no_proxy,
+        None => return false
+    };
+
+    let host = host.to_lowercase();
+    no_proxy.split(',')
+        .map(|entry| entry.trim().trim_left_matches('.').to_lowercase())
+        .filter(|entry| ! entry.is_empty())
+        .any(|entry| entry == "*" || host == entry || host.ends_with(&format!(".{}", entry)))
+}
+
+/// The host and port of the proxy to send a request through, from `http_proxy` or `https_proxy`
+/// depending on its scheme
+fn proxy(url: &Url) -> Option<(String, u16)> {
+    let name = if url.scheme() == "https" { "https_proxy" } else { "http_proxy" };
+    let value = match env_var(name) {
+        Some(value) => value,
+        None => return None
+    };
+    if bypass(url.host_str().unwrap_or("")) {
+        return None;
+    }
+
+    // The scheme is often left out, as in proxy.example.com:3128
+    let proxy_url = if value.contains("://") {
+        Url::parse(&value)
+    } else {
+        Url::parse(&format!("http://{}", value))
+    };
+    match proxy_url {
+        Ok(proxy_url) => match proxy_url.host_str() {
+            Some(host) => Some((host.to_string(), proxy_url.port_or_known_default().unwrap_or(80))),
+            None => {
+                let _ = write!(stderr(), "* Ignoring {} without a host: {}\n", name, value);
+                None
+            }
+        },
+        Err(err) => {
+            let _ = write!(stderr(), "* Ignoring invalid {} '{}': {}\n", name, value, err);
+            None
+        }
+    }
+}
+
+/// A client to send a request with, which goes through the proxy for its URL if there is one.
+/// Plain requests are passed to the proxy, and secure ones are tunneled through it.
+pub fn client(url: &Url) -> Client {
+    match proxy(url) {
+        Some((host, port)) => {
+            let _ = write!(stderr(), "* Using proxy {}:{}\n", host, port);
+            Client::with_proxy_config(ProxyConfig::new("http", host, port, HttpConnector, tls_client()))
+        },
+        None => Client::with_connector(HttpsConnector::new(tls_client()))
+    }
+}
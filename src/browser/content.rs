@@ -0,0 +1,689 @@
+use std::{cmp, str, u32};
+
+use gif::{self, SetParameter};
+use hyper::header::Headers;
+use orbclient::Color;
+use orbimage::{self, Image};
+use url::Url;
+use url::percent_encoding::percent_decode;
+
+/// Windows-1252 characters for bytes 0x80 to 0x9F, which Latin-1 labels are decoded as in practice
+static WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+/// The most pixels a decoded GIF may have, as its size comes from a header anyone can write
+const MAX_GIF_PIXELS: usize = 32 * 1024 * 1024;
+
+/// Split the `Content-Type` header into its lowercase media type and its charset, if any
+pub fn content_type(headers: &Headers) -> (String, Option<String>) {
+    let value = headers.get_raw("content-type")
+        .and_then(|values| values.first())
+        .and_then(|value| str::from_utf8(value).ok())
+        .unwrap_or("");
+
+    let mut parts = value.split(';');
+    let media_type = parts.next().unwrap_or("").trim().to_lowercase();
+
+    let mut charset = None;
+    for part in parts {
+        let mut pair = part.splitn(2, '=');
+        if let (Some(name), Some(value)) = (pair.next(), pair.next()) {
+            if name.trim().to_lowercase() == "charset" {
+                charset = Some(value.trim().trim_matches('"').to_lowercase());
+            }
+        }
+    }
+
+    (media_type, charset)
+}
+
+/// The media type to handle a resource as. Servers often send no type, or a generic one, so the first bytes decide
+/// instead, but a resource sent as plain text is only ever found to be binary, never a page or an image.
+pub fn effective_type(media_type: &str, data: &[u8]) -> String {
+    match media_type {
+        "" | "unknown/unknown" | "application/unknown" | "*/*" | "application/octet-stream" => sniff(data).to_string(),
+        "text/plain" => {
+            let sniffed = sniff(data);
+            if sniffed.starts_with("text/") || sniffed == "application/xhtml+xml" {
+                media_type.to_string()
+            } else {
+                String::from("application/octet-stream")
+            }
+        },
+        _ => media_type.to_string()
+    }
+}
+
+/// Guess the media type of a resource from its first bytes
+pub fn sniff(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG\r\n\x1A\n") {
+        return "image/png";
+    } else if data.starts_with(b"\xFF\xD8\xFF") {
+        return "image/jpeg";
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return "image/gif";
+    } else if data.starts_with(b"BM") && data.len() >= 18 && [12, 40, 108, 124].contains(&le32(data, 14)) {
+        // The size of the bitmap header that follows the file header tells a bitmap from text starting with BM
+        return "image/x-ms-bmp";
+    } else if data.starts_with(b"\0\0\x01\0") && data.len() > 6 {
+        return "image/x-icon";
+    } else if data.starts_with(b"%PDF-") {
+        return "application/pdf";
+    } else if data.starts_with(b"\xFE\xFF") || data.starts_with(b"\xFF\xFE") || data.starts_with(b"\xEF\xBB\xBF") {
+        // Text with a byte order mark is sniffed again after it has been decoded
+        let text = decode(&data[..cmp::min(data.len(), 1024)], None);
+        return sniff_text(&text);
+    }
+
+    let head = &data[..cmp::min(data.len(), 1024)];
+    if head.iter().any(|&b| b < 0x09 || (b > 0x0D && b < 0x20 && b != 0x1B)) {
+        "application/octet-stream"
+    } else {
+        sniff_text(&String::from_utf8_lossy(head))
+    }
+}
+
+fn sniff_text(text: &str) -> &'static str {
+    let start = text.trim_left_matches('\u{FEFF}').trim_left().to_lowercase();
+    if start.starts_with("<?xml") {
+        if start.contains("<html") {
+            "application/xhtml+xml"
+        } else {
+            "text/xml"
+        }
+    } else if start.starts_with("<!doctype html") || start.starts_with("<html") || start.starts_with("<head") || start.starts_with("<body") || start.starts_with("<!--") {
+        "text/html"
+    } else {
+        "text/plain"
+    }
+}
+
+/// Whether a media type is shown as plain text
+pub fn is_text(media_type: &str) -> bool {
+    media_type.starts_with("text/") || match media_type {
+        "application/xml" | "application/json" | "application/javascript" | "application/x-sh" => true,
+        _ => false
+    }
+}
+
+/// Whether a response is meant to be saved rather than shown, because the server sends it as an attachment
+/// or gives a type that is neither a page, text nor an image. Generic types are left to the first bytes to decide.
+pub fn is_download(headers: &Headers) -> bool {
+    let attachment = headers.get_raw("content-disposition")
+        .and_then(|values| values.first())
+        .and_then(|value| str::from_utf8(value).ok())
+        .map_or(false, |value| value.trim().to_lowercase().starts_with("attachment"));
+    if attachment {
+        return true;
+    }
+
+    let (media_type, _) = content_type(headers);
+    match &*media_type {
+        "" | "unknown/unknown" | "application/unknown" | "*/*" | "application/octet-stream" => false,
+        "text/html" | "application/xhtml+xml" => false,
+        _ => ! is_text(&media_type) && ! media_type.starts_with("image/")
+    }
+}
+
+/// Find the charset an HTML document declares in a `meta` element near its start
+pub fn html_charset(data: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(&data[..cmp::min(data.len(), 1024)]).to_lowercase();
+
+    let mut rest = &head[..];
+    while let Some(meta) = rest.find("<meta") {
+        rest = &rest[meta + 5..];
+        let end = rest.find('>').unwrap_or(rest.len());
+        let tag = &rest[..end];
+        if let Some(charset) = tag.find("charset=") {
+            let value = tag[charset + 8..].trim_left_matches(|c| c == '"' || c == '\'' || c == ' ');
+            let len = value.find(|c: char| c == '"' || c == '\'' || c == ';' || c == ' ' || c == '/').unwrap_or(value.len());
+            if len > 0 {
+                return Some(value[..len].to_string());
+            }
+        }
+    }
+
+    None
+}
+
+/// Decode text in the given charset, or in the charset of its byte order mark, falling back to UTF-8
+pub fn decode(data: &[u8], charset: Option<&str>) -> String {
+    // A byte order mark overrides any declared charset
+    if data.starts_with(b"\xEF\xBB\xBF") {
+        return String::from_utf8_lossy(&data[3..]).into_owned();
+    } else if data.starts_with(b"\xFF\xFE") {
+        return decode_utf16(&data[2..], false);
+    } else if data.starts_with(b"\xFE\xFF") {
+        return decode_utf16(&data[2..], true);
+    }
+
+    match charset.unwrap_or("utf-8") {
+        "iso-8859-1" | "iso8859-1" | "latin1" | "l1" | "windows-1252" | "cp1252" | "us-ascii" | "ascii" => {
+            data.iter().map(|&b| if b >= 0x80 && b < 0xA0 {
+                WINDOWS_1252[(b - 0x80) as usize]
+            } else {
+                b as char
+            }).collect()
+        },
+        "utf-16" | "utf-16le" => decode_utf16(data, false),
+        "utf-16be" => decode_utf16(data, true),
+        _ => String::from_utf8_lossy(data).into_owned()
+    }
+}
+
+fn decode_utf16(data: &[u8], big_endian: bool) -> String {
+    let units: Vec<u16> = data.chunks(2).filter(|pair| pair.len() == 2).map(|pair| if big_endian {
+        (pair[0] as u16) << 8 | pair[1] as u16
+    } else {
+        (pair[1] as u16) << 8 | pair[0] as u16
+    }).collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Decode an image of the given media type, or `None` if the type is not an image that can be shown
+pub fn parse_image(media_type: &str, data: &[u8]) -> Option<Result<Image, String>> {
+    match media_type {
+        "image/png" => Some(orbimage::parse_png(data)),
+        "image/jpeg" | "image/jpg" | "image/pjpeg" => Some(orbimage::parse_jpg(data)),
+        "image/bmp" | "image/x-ms-bmp" => Some(orbimage::parse_bmp(data)),
+        "image/gif" => Some(parse_gif(data)),
+        "image/x-icon" | "image/vnd.microsoft.icon" => Some(parse_ico(data)),
+        _ => None
+    }
+}
+
+/// Decode the first frame of a GIF
+fn parse_gif(data: &[u8]) -> Result<Image, String> {
+    let mut decoder = gif::Decoder::new(data);
+    decoder.set(gif::ColorOutput::RGBA);
+    let mut reader = decoder.read_info().map_err(|err| format!("{}", err))?;
+
+    let width = reader.width() as usize;
+    let height = reader.height() as usize;
+    match width.checked_mul(height) {
+        Some(count) if count <= MAX_GIF_PIXELS => (),
+        _ => return Err(format!("GIF too large: {}x{}", width, height))
+    }
+    let mut pixels = vec![Color::rgba(0, 0, 0, 0); width * height].into_boxed_slice();
+
+    if let Some(frame) = reader.read_next_frame().map_err(|err| format!("{}", err))? {
+        for (i, rgba) in frame.buffer.chunks(4).enumerate() {
+            let x = frame.left as usize + i % frame.width as usize;
+            let y = frame.top as usize + i / frame.width as usize;
+            if rgba.len() == 4 && x < width && y < height {
+                pixels[y * width + x] = Color::rgba(rgba[0], rgba[1], rgba[2], rgba[3]);
+            }
+        }
+    }
+
+    Image::from_data(width as u32, height as u32, pixels)
+}
+
+/// Decode a `data:` URL into its content and a `Content-Type` header for it, as described in RFC 2397
+pub fn data_url(url: &Url) -> Result<(Headers, Vec<u8>), String> {
+    // Everything after the scheme belongs to the data, including what looks like a query, but not the fragment
+    let rest = url.as_str()["data:".len() ..].split('#').next().unwrap_or("");
+    let comma = rest.find(',').ok_or_else(|| format!("Invalid data URL, it has no comma: {}", url))?;
+    let meta = percent_decode(rest[.. comma].as_bytes()).decode_utf8_lossy().into_owned();
+    let encoded: Vec<u8> = percent_decode(rest[comma + 1 ..].as_bytes()).collect();
+
+    let mut params: Vec<&str> = meta.split(';').map(|param| param.trim()).collect();
+    let base64 = params.last().map_or(false, |param| param.to_lowercase() == "base64");
+    if base64 {
+        params.pop();
+    }
+
+    // Without a type, the data is text, in US-ASCII unless it gives a charset
+    let content_type = if params.is_empty() || params[0].is_empty() {
+        if params.len() > 1 {
+            format!("text/plain;{}", params[1 ..].join(";"))
+        } else {
+            String::from("text/plain;charset=US-ASCII")
+        }
+    } else {
+        params.join(";")
+    };
+
+    let data = if base64 {
+        base64_decode(&encoded)?
+    } else {
+        encoded
+    };
+
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![content_type.into_bytes()]);
+    Ok((headers, data))
+}
+
+/// Decode base64, in either the standard or the URL-safe alphabet, ignoring whitespace and padding
+fn base64_decode(text: &[u8]) -> Result<Vec<u8>, String> {
+    let mut data = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for &byte in text.iter() {
+        let value = match byte {
+            b'A' ... b'Z' => byte - b'A',
+            b'a' ... b'z' => byte - b'a' + 26,
+            b'0' ... b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            b' ' | b'\t' | b'\r' | b'\n' => continue,
+            _ => return Err(format!("Invalid base64 character '{}'", byte as char))
+        };
+
+        bits = (bits << 6 | value as u32) & 0xFFFF;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            data.push((bits >> count) as u8);
+        }
+    }
+    Ok(data)
+}
+
+/// A size in bytes, in the largest unit that keeps it at least 1
+pub fn format_size(bytes: u64) -> String {
+    let units = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+/// Escape text for use in a generated HTML page
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+fn le16(data: &[u8], i: usize) -> u32 {
+    data[i] as u32 | (data[i + 1] as u32) << 8
+}
+
+fn le32(data: &[u8], i: usize) -> u32 {
+    le16(data, i) | le16(data, i + 2) << 16
+}
+
+/// Decode the image of an icon file closest to 16 pixels wide
+fn parse_ico(data: &[u8]) -> Result<Image, String> {
+    if data.len() < 6 {
+        return Err(String::from("ICO header truncated"));
+    }
+
+    let mut best: Option<(i32, &[u8])> = None;
+    for i in 0..le16(data, 4) as usize {
+        let entry = 6 + i * 16;
+        if entry + 16 > data.len() {
+            break;
+        }
+
+        let width = if data[entry] == 0 { 256 } else { data[entry] as i32 };
+        let size = le32(data, entry + 8) as usize;
+        let offset = le32(data, entry + 12) as usize;
+        if offset >= data.len() || size > data.len() - offset {
+            continue;
+        }
+
+        let distance = (width - 16).abs();
+        if best.map_or(true, |(best_distance, _)| distance < best_distance) {
+            best = Some((distance, &data[offset..offset + size]));
+        }
+    }
+
+    match best {
+        Some((_, image)) => if image.starts_with(b"\x89PNG\r\n\x1A\n") {
+            orbimage::parse_png(image)
+        } else {
+            match dib_to_bmp(image) {
+                Some(bmp) => orbimage::parse_bmp(&bmp),
+                None => Err(String::from("ICO image not readable"))
+            }
+        },
+        None => Err(String::from("ICO has no images"))
+    }
+}
+
+/// Turn the bitmap of an icon, which has no file header and is followed by its mask, into a BMP file
+fn dib_to_bmp(dib: &[u8]) -> Option<Vec<u8>> {
+    if dib.len() < 40 {
+        return None;
+    }
+
+    // The header and the palette come from the file, so they are checked before any size is worked out from them
+    let header_size = le32(dib, 0);
+    let bits = le16(dib, 14);
+    let colors = le32(dib, 32);
+    if header_size < 40 || header_size as usize > dib.len() || colors > 256 {
+        return None;
+    }
+    let palette = if bits <= 8 {
+        4 * if colors == 0 { 1 << bits } else { colors }
+    } else {
+        0
+    };
+
+    let file_size = match (dib.len() as u64).checked_add(14) {
+        Some(size) if size <= u32::MAX as u64 => size as u32,
+        _ => return None
+    };
+    let data_offset = match 14u32.checked_add(header_size).and_then(|offset| offset.checked_add(palette)) {
+        Some(offset) if offset <= file_size => offset,
+        _ => return None
+    };
+
+    let mut bmp = Vec::with_capacity(file_size as usize);
+    bmp.extend_from_slice(b"BM");
+    for &value in [file_size, 0, data_offset].iter() {
+        bmp.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+    }
+    bmp.extend_from_slice(dib);
+
+    // The height counts the mask too, which is left out
+    let height = le32(dib, 8) as i32 / 2;
+    for i in 0..4 {
+        bmp[14 + 8 + i] = (height >> (i * 8)) as u8;
+    }
+
+    Some(bmp)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::Headers;
+    use orbclient::Color;
+    use url::Url;
+
+    use super::{base64_decode, content_type, data_url, decode, dib_to_bmp, effective_type, html_charset, is_download, parse_image, sniff};
+
+    /// A 1x1 GIF with a single red pixel
+    static RED_GIF: &'static [u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\xFF\x00\x00\x00\x00\x00\
+        \x21\xF9\x04\x00\x00\x00\x00\x00\x2C\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00\x3B";
+
+    fn headers(pairs: &[(&str, &[u8])]) -> Headers {
+        let mut headers = Headers::new();
+        for &(name, value) in pairs.iter() {
+            headers.set_raw(name.to_string(), vec![value.to_vec()]);
+        }
+        headers
+    }
+
+    /// Decode a data URL into its content type and data
+    fn data(url: &str) -> Result<(String, Vec<u8>), String> {
+        data_url(&Url::parse(url).unwrap()).map(|(headers, data)| {
+            let content_type = String::from_utf8(headers.get_raw("Content-Type").unwrap()[0].clone()).unwrap();
+            (content_type, data)
+        })
+    }
+
+    /// The header of a bitmap in an icon, with the given header size, bits per pixel and palette size
+    fn dib(header_size: u32, bits: u16, colors: u32, len: usize) -> Vec<u8> {
+        let mut dib = vec![0; len];
+        dib[0..4].copy_from_slice(&[header_size as u8, (header_size >> 8) as u8, (header_size >> 16) as u8, (header_size >> 24) as u8]);
+        dib[4] = 1;
+        dib[8] = 2;
+        dib[14] = bits as u8;
+        dib[15] = (bits >> 8) as u8;
+        dib[32..36].copy_from_slice(&[colors as u8, (colors >> 8) as u8, (colors >> 16) as u8, (colors >> 24) as u8]);
+        dib
+    }
+
+    #[test]
+    fn sniff_signatures() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1A\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(sniff(b"\xFF\xD8\xFF\xE0"), "image/jpeg");
+        assert_eq!(sniff(b"GIF87a"), "image/gif");
+        assert_eq!(sniff(RED_GIF), "image/gif");
+        assert_eq!(sniff(b"BM\x46\0\0\0\0\0\0\0\x36\0\0\0\x28\0\0\0"), "image/x-ms-bmp");
+        assert_eq!(sniff(b"BM\x46\0\0\0\0\0\0\0\x1A\0\0\0\x0C\0\0\0"), "image/x-ms-bmp");
+        assert_eq!(sniff(b"%PDF-1.4\n"), "application/pdf");
+
+        // Signatures that are cut short are not enough
+        assert_eq!(sniff(b"BM"), "text/plain");
+        assert_eq!(sniff(b"BM\x46\0\0\0\0\0\0\0\x36\0\0\0\x28"), "application/octet-stream");
+        assert_eq!(sniff(b"BMW models and prices"), "text/plain");
+        assert_eq!(sniff(b"\0\0\x01\0"), "application/octet-stream");
+        assert_eq!(sniff(b"\x89PNG"), "text/plain");
+    }
+
+    #[test]
+    fn sniff_text_and_binary() {
+        assert_eq!(sniff(b""), "text/plain");
+        assert_eq!(sniff(b"Hello, world\r\n\t\x1B[0m"), "text/plain");
+        assert_eq!(sniff(b"  \n<!DOCTYPE HTML><title>x</title>"), "text/html");
+        assert_eq!(sniff(b"<HTML>"), "text/html");
+        assert_eq!(sniff(b"<!-- comment --><p>"), "text/html");
+        assert_eq!(sniff(b"<p>A fragment</p>"), "text/plain");
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?><rss>"), "text/xml");
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?><html xmlns=\"http://www.w3.org/1999/xhtml\">"), "application/xhtml+xml");
+        assert_eq!(sniff(b"text then a \0 byte"), "application/octet-stream");
+        assert_eq!(sniff(b"\x7FELF\x02\x01\x01"), "application/octet-stream");
+        assert_eq!(sniff("caf\u{E9} \u{2603}".as_bytes()), "text/plain");
+
+        // Only the first kilobyte is looked at
+        let mut late_binary = vec![b'a'; 1024];
+        late_binary.push(0);
+        assert_eq!(sniff(&late_binary), "text/plain");
+    }
+
+    #[test]
+    fn effective_types() {
+        assert_eq!(effective_type("", b"<!doctype html>"), "text/html");
+        assert_eq!(effective_type("application/octet-stream", RED_GIF), "image/gif");
+        assert_eq!(effective_type("image/png", b"<html>"), "image/png");
+
+        // Plain text is only ever found to be binary
+        assert_eq!(effective_type("text/plain", b"<!doctype html>"), "text/plain");
+        assert_eq!(effective_type("text/plain", b"\xEF\xBB\xBF<html>"), "text/plain");
+        assert_eq!(effective_type("text/plain", RED_GIF), "application/octet-stream");
+        assert_eq!(effective_type("text/plain", b"%PDF-1.4\n"), "application/octet-stream");
+        assert_eq!(effective_type("text/plain", b"\x7FELF\x02\x01\x01"), "application/octet-stream");
+    }
+
+    #[test]
+    fn sniff_byte_order_marks() {
+        assert_eq!(sniff(b"\xEF\xBB\xBF<!doctype html>"), "text/html");
+        assert_eq!(sniff(b"\xFF\xFE<\0h\0t\0m\0l\0>\0"), "text/html");
+        assert_eq!(sniff(b"\xFE\xFF\0<\0h\0t\0m\0l\0>"), "text/html");
+        assert_eq!(sniff(b"\xFF\xFEh\0i\0"), "text/plain");
+        assert_eq!(sniff(b"\xFF\xFE"), "text/plain");
+        assert_eq!(sniff(b"\xFF\xFE\0"), "text/plain");
+    }
+
+    #[test]
+    fn decode_charsets() {
+        assert_eq!(decode("caf\u{E9}".as_bytes(), None), "caf\u{E9}");
+        assert_eq!(decode(b"caf\xC3", None), "caf\u{FFFD}");
+        assert_eq!(decode(b"\xFD\xFE", Some("utf-8")), "\u{FFFD}\u{FFFD}");
+        assert_eq!(decode(b"caf\xE9 \x80 \x9D \xA0", Some("iso-8859-1")), "caf\u{E9} \u{20AC} \u{9D} \u{A0}");
+        assert_eq!(decode(b"\x93quoted\x94", Some("windows-1252")), "\u{201C}quoted\u{201D}");
+        assert_eq!(decode(b"h\0i\0", Some("utf-16le")), "hi");
+        assert_eq!(decode(b"\0h\0i", Some("utf-16be")), "hi");
+        assert_eq!(decode(b"caf\xC3\xA9", Some("x-unknown")), "caf\u{E9}");
+    }
+
+    #[test]
+    fn decode_malformed_utf16() {
+        // A trailing odd byte is dropped, and an unpaired surrogate is replaced
+        assert_eq!(decode(b"h\0i\0!", Some("utf-16le")), "hi");
+        assert_eq!(decode(b"\x00\xD8h\0", Some("utf-16le")), "\u{FFFD}h");
+        assert_eq!(decode(b"\x3D\xD8\x03\xDE", Some("utf-16le")), "\u{1F603}");
+        assert_eq!(decode(b"", Some("utf-16")), "");
+        assert_eq!(decode(b"\xFF", Some("utf-16")), "");
+    }
+
+    #[test]
+    fn decode_byte_order_marks() {
+        // A byte order mark is removed and overrides the charset given
+        assert_eq!(decode(b"\xEF\xBB\xBFcaf\xC3\xA9", Some("iso-8859-1")), "caf\u{E9}");
+        assert_eq!(decode(b"\xFF\xFEh\0i\0", Some("utf-8")), "hi");
+        assert_eq!(decode(b"\xFE\xFF\0h\0i", None), "hi");
+        assert_eq!(decode(b"\xEF\xBB\xBF", None), "");
+        assert_eq!(decode(b"\xEF\xBB", None), "\u{FFFD}");
+    }
+
+    #[test]
+    fn charset_declarations() {
+        assert_eq!(html_charset(b"<html><head><META CharSet=\"ISO-8859-1\">"), Some("iso-8859-1".to_string()));
+        assert_eq!(html_charset(b"<meta charset=utf-8/>"), Some("utf-8".to_string()));
+        assert_eq!(html_charset(b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=windows-1252\">"), Some("windows-1252".to_string()));
+        assert_eq!(html_charset(b"<meta name=\"viewport\"><meta charset='koi8-r'>"), Some("koi8-r".to_string()));
+        assert_eq!(html_charset(b"<meta charset=\"\">"), None);
+        assert_eq!(html_charset(b"<meta charset="), None);
+        assert_eq!(html_charset(b"<p>charset=utf-8</p>"), None);
+
+        let mut late = vec![b' '; 1024];
+        late.extend_from_slice(b"<meta charset=utf-8>");
+        assert_eq!(html_charset(&late), None);
+    }
+
+    #[test]
+    fn content_types() {
+        assert_eq!(content_type(&headers(&[("Content-Type", b"Text/HTML; Charset=\"UTF-8\"")])), ("text/html".to_string(), Some("utf-8".to_string())));
+        assert_eq!(content_type(&headers(&[("Content-Type", b"text/plain;format=flowed;charset=latin1")])), ("text/plain".to_string(), Some("latin1".to_string())));
+        assert_eq!(content_type(&headers(&[("Content-Type", b"image/png;charset")])), ("image/png".to_string(), None));
+        assert_eq!(content_type(&headers(&[("Content-Type", b"\xFF\xFE")])), (String::new(), None));
+        assert_eq!(content_type(&Headers::new()), (String::new(), None));
+    }
+
+    #[test]
+    fn downloads() {
+        assert!(is_download(&headers(&[("Content-Type", b"application/zip")])));
+        assert!(is_download(&headers(&[("Content-Type", b"text/html"), ("Content-Disposition", b" Attachment; filename=page.html")])));
+        assert!(! is_download(&headers(&[("Content-Type", b"text/html"), ("Content-Disposition", b"inline")])));
+        assert!(! is_download(&headers(&[("Content-Type", b"application/xhtml+xml")])));
+        assert!(! is_download(&headers(&[("Content-Type", b"text/csv")])));
+        assert!(! is_download(&headers(&[("Content-Type", b"application/json")])));
+        assert!(! is_download(&headers(&[("Content-Type", b"image/webp")])));
+        assert!(! is_download(&headers(&[("Content-Type", b"application/octet-stream")])));
+        assert!(! is_download(&Headers::new()));
+    }
+
+    #[test]
+    fn data_urls() {
+        assert_eq!(data("data:,Hello%2C%20World!"), Ok(("text/plain;charset=US-ASCII".to_string(), b"Hello, World!".to_vec())));
+        assert_eq!(data("data:;charset=utf-8,caf%C3%A9"), Ok(("text/plain;charset=utf-8".to_string(), "caf\u{E9}".as_bytes().to_vec())));
+        assert_eq!(data("data:text/html;base64,PGI+aGk8L2I+"), Ok(("text/html".to_string(), b"<b>hi</b>".to_vec())));
+        assert_eq!(data("data:text/plain;BASE64,aGk%3D"), Ok(("text/plain".to_string(), b"hi".to_vec())));
+        assert_eq!(data("data:image/png;base64,"), Ok(("image/png".to_string(), Vec::new())));
+        assert_eq!(data("data:,"), Ok(("text/plain;charset=US-ASCII".to_string(), Vec::new())));
+
+        // The query belongs to the data, the fragment does not, and later commas are data
+        assert_eq!(data("data:,a?b=c,d#top").unwrap().1, b"a?b=c,d".to_vec());
+
+        // A base64 parameter that is not last is an ordinary parameter
+        assert_eq!(data("data:text/plain;base64;charset=utf-8,aGk="), Ok(("text/plain;base64;charset=utf-8".to_string(), b"aGk=".to_vec())));
+    }
+
+    #[test]
+    fn data_urls_malformed() {
+        assert!(data("data:text/plain").is_err());
+        assert!(data("data:").is_err());
+        assert!(data("data:;base64,aGk*").is_err());
+        assert!(data("data:;base64,%FF%FE").is_err());
+
+        // Bytes that are not UTF-8 are kept as they are
+        assert_eq!(data("data:application/octet-stream,%00%FF%80").unwrap().1, vec![0, 0xFF, 0x80]);
+        assert_eq!(data("data:text/%FF%FE,x").unwrap().0, "text/\u{FFFD}\u{FFFD}");
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(base64_decode(b""), Ok(Vec::new()));
+        assert_eq!(base64_decode(b"TWFu"), Ok(b"Man".to_vec()));
+        assert_eq!(base64_decode(b"TWE="), Ok(b"Ma".to_vec()));
+        assert_eq!(base64_decode(b"TQ=="), Ok(b"M".to_vec()));
+        assert_eq!(base64_decode(b"TQ"), Ok(b"M".to_vec()));
+        assert_eq!(base64_decode(b" TW\r\nFu\t"), Ok(b"Man".to_vec()));
+        assert_eq!(base64_decode(b"+/8="), Ok(vec![0xFB, 0xFF]));
+        assert_eq!(base64_decode(b"-_8="), Ok(vec![0xFB, 0xFF]));
+        assert_eq!(base64_decode(b"AAAA////"), Ok(vec![0, 0, 0, 0xFF, 0xFF, 0xFF]));
+    }
+
+    #[test]
+    fn base64_malformed() {
+        // Leftover bits that do not make a byte are dropped, as is anything after the padding
+        assert_eq!(base64_decode(b"T"), Ok(Vec::new()));
+        assert_eq!(base64_decode(b"TQ==TWFu"), Ok(b"M".to_vec()));
+        assert_eq!(base64_decode(b"="), Ok(Vec::new()));
+
+        assert!(base64_decode(b"TW@u").is_err());
+        assert!(base64_decode(b"TWF.").is_err());
+        assert!(base64_decode(b"\0").is_err());
+        assert!(base64_decode("T\u{E9}".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn gif_decoding() {
+        let image = parse_image("image/gif", RED_GIF).unwrap().unwrap();
+        assert_eq!((image.width(), image.height()), (1, 1));
+        assert_eq!(image.data()[0], Color::rgba(255, 0, 0, 255));
+
+        assert!(parse_image("image/gif", b"GIF89a").unwrap().is_err());
+        assert!(parse_image("image/gif", &RED_GIF[..20]).unwrap().is_err());
+        assert!(parse_image("text/plain", RED_GIF).is_none());
+    }
+
+    #[test]
+    fn gif_size_limit() {
+        // The screen size comes from the header, and an absurd one is refused before anything is allocated for it
+        let mut huge = RED_GIF.to_vec();
+        huge[6..10].copy_from_slice(b"\xFF\xFF\xFF\xFF");
+        assert_eq!(parse_image("image/gif", &huge).unwrap().err(), Some("GIF too large: 65535x65535".to_string()));
+    }
+
+    #[test]
+    fn icon_bitmaps() {
+        assert_eq!(sniff(b"\0\0\x01\0\x01\0\x10"), "image/x-icon");
+
+        // A 32 bit bitmap has no palette, so its pixels follow the file and bitmap headers
+        let bmp = dib_to_bmp(&dib(40, 32, 0, 48)).unwrap();
+        assert_eq!(&bmp[..2], b"BM");
+        assert_eq!(&bmp[2..6], &[62, 0, 0, 0]);
+        assert_eq!(&bmp[10..14], &[54, 0, 0, 0]);
+        assert_eq!(&bmp[14 + 8..14 + 12], &[1, 0, 0, 0]);
+        assert_eq!(bmp.len(), 62);
+
+        // An 8 bit bitmap has a palette of 256 colors unless it says otherwise
+        assert_eq!(&dib_to_bmp(&dib(40, 8, 0, 40 + 1024)).unwrap()[10..14], &[0x36, 4, 0, 0]);
+        assert_eq!(&dib_to_bmp(&dib(40, 8, 2, 48)).unwrap()[10..14], &[62, 0, 0, 0]);
+    }
+
+    #[test]
+    fn icon_bitmaps_malformed() {
+        assert_eq!(dib_to_bmp(&[]), None);
+        assert_eq!(dib_to_bmp(&dib(40, 32, 0, 40)[..39]), None);
+        assert_eq!(dib_to_bmp(&dib(40, 0xFFFF, 0, 48)).map(|bmp| bmp.len()), Some(62));
+    }
+
+    #[test]
+    fn icon_bitmaps_bounds() {
+        // The header size and palette size come from the file, and must fit in the bitmap
+        assert_eq!(dib_to_bmp(&dib(12, 32, 0, 40)), None);
+        assert_eq!(dib_to_bmp(&dib(41, 32, 0, 40)), None);
+        assert_eq!(dib_to_bmp(&dib(0xFFFFFFFF, 32, 0, 40)), None);
+        assert_eq!(dib_to_bmp(&dib(40, 8, 257, 2000)), None);
+        assert_eq!(dib_to_bmp(&dib(40, 8, 0xFFFFFFFF, 2000)), None);
+        assert_eq!(dib_to_bmp(&dib(40, 8, 0, 48)), None);
+    }
+}
@@ -0,0 +1,486 @@
+use std::{fs, io, process, str};
+use std::fs::{File, OpenOptions};
+use std::io::{stderr, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once, ONCE_INIT};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::header::{Headers, HttpDate};
+use url::{Host, Url};
+
+use settings;
+
+/// A cookie set by a server, sent back with the requests it matches
+#[derive(Clone)]
+struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    /// Whether the cookie is only sent to the exact host that set it, as it had no `Domain` attribute
+    host_only: bool,
+    path: String,
+    /// When the cookie expires, or `None` for a session cookie that is not saved
+    expires: Option<u64>,
+    secure: bool,
+    http_only: bool,
+}
+
+impl Cookie {
+    /// Parse a `Set-Cookie` header received from the given URL
+    fn parse(header: &str, url: &Url, now: u64) -> Option<Cookie> {
+        let host = match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None => return None
+        };
+
+        let mut parts = header.split(';');
+        let (name, value) = match parts.next() {
+            Some(pair) => match pair.find('=') {
+                Some(equals) => (pair[..equals].trim().to_string(), pair[equals + 1..].trim().to_string()),
+                None => return None
+            },
+            None => return None
+        };
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = Cookie {
+            name: name,
+            value: value,
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            expires: None,
+            secure: false,
+            http_only: false,
+        };
+
+        let is_address = match url.host() {
+            Some(Host::Domain(_)) => false,
+            _ => true
+        };
+
+        let mut max_age = None;
+        for attribute in parts {
+            let (key, value) = match attribute.find('=') {
+                Some(equals) => (attribute[..equals].trim().to_lowercase(), attribute[equals + 1..].trim()),
+                None => (attribute.trim().to_lowercase(), "")
+            };
+
+            match &*key {
+                "expires" => if let Ok(date) = value.parse::<HttpDate>() {
+                    let secs = date.0.to_timespec().sec;
+                    cookie.expires = Some(if secs < 0 { 0 } else { secs as u64 });
+                },
+                "max-age" => if let Ok(secs) = value.parse::<i64>() {
+                    max_age = Some(secs);
+                },
+                "domain" => {
+                    let domain = value.trim_left_matches('.').to_lowercase();
+                    // A cookie may only be set for the host itself or a parent domain, and never for a top level domain.
+                    // An IP address has no parent domains, so a cookie from one stays with it.
+                    if is_address {
+                        if ! domain.is_empty() && domain != host {
+                            return None;
+                        }
+                    } else if ! domain.is_empty() {
+                        // A top level domain that is the host itself only leaves the cookie with the host
+                        if domain == host && ! domain.contains('.') {
+                            continue;
+                        }
+                        if ! domain_match(&host, &domain) || ! domain.contains('.') {
+                            return None;
+                        }
+                        cookie.domain = domain;
+                        cookie.host_only = false;
+                    }
+                },
+                "path" => if value.starts_with('/') {
+                    cookie.path = value.to_string();
+                },
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                _ => ()
+            }
+        }
+
+        // Max-Age takes precedence over Expires
+        if let Some(secs) = max_age {
+            cookie.expires = Some(if secs <= 0 { 0 } else { now + secs as u64 });
+        }
+
+        Some(cookie)
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.map_or(false, |expires| expires <= now)
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or("").to_lowercase();
+        let host_matches = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(&host, &self.domain)
+        };
+
+        host_matches && path_match(url.path(), &self.path) && (! self.secure || url.scheme() == "https")
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path || (request_path.starts_with(cookie_path) && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The directory of the request path, which is the path of a cookie without a `Path` attribute
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => String::from("/"),
+        Some(slash) => path[..slash].to_string()
+    }
+}
+
+fn jar_path() -> Option<PathBuf> {
+    settings::config_dir().map(|dir| dir.join("cookies"))
+}
+
+/// Create a file that only its owner can read, as cookies carry the sessions of their sites
+#[cfg(unix)]
+fn create_private(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create(true).truncate(true).open(path)
+}
+
+/// The cookies of every site, shared by all the threads loading pages
+struct Jar {
+    cookies: Vec<Cookie>,
+}
+
+impl Jar {
+    /// Load the saved cookies, dropping those that have expired
+    fn load() -> Jar {
+        let mut cookies = Vec::new();
+
+        let mut data = String::new();
+        if let Some(path) = jar_path() {
+            if let Ok(mut file) = File::open(&path) {
+                let _ = file.read_to_string(&mut data);
+            }
+        }
+
+        // Each line is the domain, host only, path, secure, HTTP only, expiry, name and value, separated by tabs
+        let now = now();
+        for line in data.lines() {
+            let fields: Vec<&str> = line.splitn(8, '\t').collect();
+            if fields.len() < 8 {
+                continue;
+            }
+
+            if let Ok(expires) = fields[5].parse::<u64>() {
+                let cookie = Cookie {
+                    name: fields[6].to_string(),
+                    value: fields[7].to_string(),
+                    domain: fields[0].to_string(),
+                    host_only: fields[1] == "1",
+                    path: fields[2].to_string(),
+                    expires: Some(expires),
+                    secure: fields[3] == "1",
+                    http_only: fields[4] == "1",
+                };
+                if ! cookie.is_expired(now) {
+                    cookies.push(cookie);
+                }
+            }
+        }
+
+        Jar {
+            cookies: cookies,
+        }
+    }
+
+    /// Write the cookies that outlive the session
+    fn save(&self) {
+        let path = match jar_path() {
+            Some(path) => path,
+            None => return
+        };
+
+        let mut data = String::new();
+        for cookie in self.cookies.iter() {
+            if let Some(expires) = cookie.expires {
+                data.push_str(&format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                    cookie.domain,
+                    if cookie.host_only { 1 } else { 0 },
+                    cookie.path,
+                    if cookie.secure { 1 } else { 0 },
+                    if cookie.http_only { 1 } else { 0 },
+                    expires,
+                    cookie.name,
+                    cookie.value
+                ));
+            }
+        }
+
+        // The jar is written to a temporary file first, so that a crash while writing leaves the old one whole.
+        // A temporary file left behind may have other permissions, which only a new file gets right.
+        let tmp_path = path.with_extension(format!("{}.tmp", process::id()));
+        let _ = fs::remove_file(&tmp_path);
+        let result = path.parent().map_or(Ok(()), |parent| fs::create_dir_all(parent))
+            .and_then(|_| create_private(&tmp_path))
+            .and_then(|mut file| {
+                file.write_all(data.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, &path));
+        if let Err(err) = result {
+            let _ = fs::remove_file(&tmp_path);
+            let _ = write!(stderr(), "* Failed to save cookies to {}: {}\n", path.display(), err);
+        }
+    }
+}
+
+static JAR_INIT: Once = ONCE_INIT;
+static mut JAR: *const Mutex<Jar> = 0 as *const Mutex<Jar>;
+
+fn jar() -> &'static Mutex<Jar> {
+    unsafe {
+        JAR_INIT.call_once(|| {
+            JAR = Box::into_raw(Box::new(Mutex::new(Jar::load())));
+        });
+        &*JAR
+    }
+}
+
+/// Add the cookies matching a URL to the headers of a request to it
+pub fn request(url: &Url, headers: &mut Headers) {
+    let jar = jar().lock().unwrap();
+
+    let now = now();
+    let mut matching: Vec<&Cookie> = jar.cookies.iter()
+        .filter(|cookie| ! cookie.is_expired(now) && cookie.matches(url))
+        .collect();
+    if matching.is_empty() {
+        return;
+    }
+
+    // Cookies with longer paths are sent first
+    matching.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+
+    let header = matching.iter()
+        .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+        .collect::<Vec<String>>()
+        .join("; ");
+    headers.set_raw("Cookie", vec![header.into_bytes()]);
+}
+
+/// Store the cookies set by the response to a request to a URL
+pub fn response(url: &Url, headers: &Headers) {
+    let set_cookies = match headers.get_raw("Set-Cookie") {
+        Some(set_cookies) => set_cookies,
+        None => return
+    };
+
+    let mut jar = jar().lock().unwrap();
+
+    let now = now();
+    let mut persistent = false;
+    for set_cookie in set_cookies.iter() {
+        let cookie = match str::from_utf8(set_cookie).ok().and_then(|header| Cookie::parse(header, url, now)) {
+            Some(cookie) => cookie,
+            None => continue
+        };
+
+        // A cookie replaces the one with the same name, domain and path, and an expired one deletes it
+        let len = jar.cookies.len();
+        jar.cookies.retain(|other| ! (other.name == cookie.name && other.domain == cookie.domain && other.path == cookie.path));
+        if jar.cookies.len() != len || cookie.expires.is_some() {
+            persistent = true;
+        }
+
+        if ! cookie.is_expired(now) {
+            jar.cookies.push(cookie);
+        }
+    }
+
+    if persistent {
+        jar.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::Cookie;
+
+    const NOW: u64 = 1000;
+
+    fn parse(header: &str, url: &str) -> Option<Cookie> {
+        Cookie::parse(header, &Url::parse(url).unwrap(), NOW)
+    }
+
+    fn matches(cookie: &Cookie, url: &str) -> bool {
+        cookie.matches(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn parse_attributes() {
+        let cookie = parse("id = abc ; Path=/docs; Secure; HttpOnly; Unknown=1", "https://WWW.Example.com/a/b").unwrap();
+        assert_eq!(cookie.name, "id");
+        assert_eq!(cookie.value, "abc");
+        assert_eq!(cookie.domain, "www.example.com");
+        assert!(cookie.host_only);
+        assert_eq!(cookie.path, "/docs");
+        assert_eq!(cookie.expires, None);
+        assert!(cookie.secure);
+        assert!(cookie.http_only);
+
+        let cookie = parse("token=a=b;", "http://example.com/").unwrap();
+        assert_eq!(cookie.value, "a=b");
+        assert!(! cookie.secure && ! cookie.http_only);
+
+        assert_eq!(parse("empty=", "http://example.com/").unwrap().value, "");
+    }
+
+    #[test]
+    fn parse_malformed() {
+        for header in ["", "name", "=value", " = value", "; id=abc"].iter() {
+            assert!(parse(header, "http://example.com/").is_none(), "{:?}", header);
+        }
+
+        // Without a name value pair first, an attribute is taken for the cookie itself
+        assert_eq!(parse("Path=/", "http://example.com/").unwrap().name, "Path");
+
+        // Cookies need a host to belong to
+        assert!(parse("id=abc", "data:text/plain,hello").is_none());
+    }
+
+    #[test]
+    fn parse_path() {
+        assert_eq!(parse("a=b", "http://example.com/a/b/c").unwrap().path, "/a/b");
+        assert_eq!(parse("a=b", "http://example.com/a/b/").unwrap().path, "/a/b");
+        assert_eq!(parse("a=b", "http://example.com/a").unwrap().path, "/");
+        assert_eq!(parse("a=b", "http://example.com").unwrap().path, "/");
+        assert_eq!(parse("a=b; Path=relative", "http://example.com/a/b").unwrap().path, "/a");
+        assert_eq!(parse("a=b; Path=", "http://example.com/a/b").unwrap().path, "/a");
+    }
+
+    #[test]
+    fn parse_expiry() {
+        let expires = |header: &str| parse(header, "http://example.com/").unwrap().expires;
+
+        assert_eq!(expires("a=b; Expires=Wed, 21 Oct 2015 07:28:00 GMT"), Some(1445412480));
+        assert_eq!(expires("a=b; Expires=Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(expires("a=b; Expires=tomorrow"), None);
+        assert_eq!(expires("a=b; Max-Age=60"), Some(NOW + 60));
+        assert_eq!(expires("a=b; Max-Age=0"), Some(0));
+        assert_eq!(expires("a=b; Max-Age=-5"), Some(0));
+        assert_eq!(expires("a=b; Max-Age=soon"), None);
+        assert_eq!(expires("a=b; Max-Age=60; Expires=Wed, 21 Oct 2015 07:28:00 GMT"), Some(NOW + 60));
+        assert_eq!(expires("a=b; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Max-Age=60"), Some(NOW + 60));
+
+        let cookie = parse("a=b; Max-Age=60", "http://example.com/").unwrap();
+        assert!(! cookie.is_expired(NOW + 59));
+        assert!(cookie.is_expired(NOW + 60));
+        assert!(! parse("a=b", "http://example.com/").unwrap().is_expired(u64::max_value()));
+    }
+
+    #[test]
+    fn parse_domain() {
+        let domain = |header: &str, url: &str| parse(header, url).map(|cookie| (cookie.domain, cookie.host_only));
+        let url = "http://www.example.com/";
+
+        assert_eq!(domain("a=b; Domain=example.com", url), Some(("example.com".to_string(), false)));
+        assert_eq!(domain("a=b; Domain=.Example.COM", url), Some(("example.com".to_string(), false)));
+        assert_eq!(domain("a=b; Domain=www.example.com", url), Some(("www.example.com".to_string(), false)));
+        assert_eq!(domain("a=b; Domain=", url), Some(("www.example.com".to_string(), true)));
+        assert_eq!(domain("a=b; Domain=.", url), Some(("www.example.com".to_string(), true)));
+
+        // Other sites, children of the host, and suffixes that do not end at a dot are refused
+        assert_eq!(domain("a=b; Domain=other.com", url), None);
+        assert_eq!(domain("a=b; Domain=sub.www.example.com", url), None);
+        assert_eq!(domain("a=b; Domain=ample.com", url), None);
+        assert_eq!(domain("a=b; Domain=www.example.com.evil.com", url), None);
+
+        // Top level domains are refused, by the dot they lack, unless they are the host itself
+        assert_eq!(domain("a=b; Domain=com", url), None);
+        assert_eq!(domain("a=b; Domain=.com", url), None);
+        assert_eq!(domain("a=b; Domain=localhost", "http://localhost/"), Some(("localhost".to_string(), true)));
+        assert_eq!(domain("a=b; Domain=.LocalHost", "http://localhost/"), Some(("localhost".to_string(), true)));
+        assert_eq!(domain("a=b; Domain=localhost", "http://sub.localhost/"), None);
+        assert_eq!(domain("a=b", "http://localhost/"), Some(("localhost".to_string(), true)));
+
+        // There is no public suffix list, so a suffix with a dot in it passes for a parent domain
+        assert_eq!(domain("a=b; Domain=co.uk", "http://www.example.co.uk/"), Some(("co.uk".to_string(), false)));
+    }
+
+    #[test]
+    fn parse_domain_addresses() {
+        let domain = |header: &str, url: &str| parse(header, url).map(|cookie| (cookie.domain, cookie.host_only));
+
+        // An IP address has no parent domains
+        assert_eq!(domain("a=b; Domain=0.0.1", "http://10.0.0.1/"), None);
+        assert_eq!(domain("a=b; Domain=0.1", "http://10.0.0.1/"), None);
+        assert_eq!(domain("a=b; Domain=10.0.0.1", "http://10.0.0.1/"), Some(("10.0.0.1".to_string(), true)));
+        assert_eq!(domain("a=b; Domain=example.com", "http://[::1]/"), None);
+    }
+
+    #[test]
+    fn match_domain() {
+        let cookie = parse("a=b; Domain=example.com", "http://www.example.com/").unwrap();
+        assert!(matches(&cookie, "http://example.com/"));
+        assert!(matches(&cookie, "http://WWW.EXAMPLE.COM/"));
+        assert!(matches(&cookie, "http://a.b.example.com/"));
+        assert!(! matches(&cookie, "http://notexample.com/"));
+        assert!(! matches(&cookie, "http://example.com.evil.com/"));
+        assert!(! matches(&cookie, "http://com/"));
+
+        let cookie = parse("a=b", "http://www.example.com/").unwrap();
+        assert!(matches(&cookie, "http://www.example.com/"));
+        assert!(matches(&cookie, "http://www.example.com:8080/"));
+        assert!(! matches(&cookie, "http://example.com/"));
+        assert!(! matches(&cookie, "http://sub.www.example.com/"));
+    }
+
+    #[test]
+    fn match_path() {
+        let cookie = parse("a=b; Path=/docs", "http://example.com/").unwrap();
+        assert!(matches(&cookie, "http://example.com/docs"));
+        assert!(matches(&cookie, "http://example.com/docs/"));
+        assert!(matches(&cookie, "http://example.com/docs/web/page?q=1"));
+        assert!(! matches(&cookie, "http://example.com/docsearch"));
+        assert!(! matches(&cookie, "http://example.com/"));
+        assert!(! matches(&cookie, "http://example.com/Docs"));
+
+        let cookie = parse("a=b; Path=/docs/", "http://example.com/").unwrap();
+        assert!(matches(&cookie, "http://example.com/docs/web"));
+        assert!(! matches(&cookie, "http://example.com/docs"));
+
+        let cookie = parse("a=b; Path=/", "http://example.com/").unwrap();
+        assert!(matches(&cookie, "http://example.com/anything/at/all"));
+    }
+
+    #[test]
+    fn match_secure() {
+        let cookie = parse("a=b; Secure", "https://example.com/").unwrap();
+        assert!(matches(&cookie, "https://example.com/"));
+        assert!(! matches(&cookie, "http://example.com/"));
+        assert!(matches(&parse("a=b", "https://example.com/").unwrap(), "http://example.com/"));
+    }
+}
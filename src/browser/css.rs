@@ -0,0 +1,1157 @@
+use std::cmp;
+
+use html5ever::Attribute;
+use orbclient::Color;
+
+use settings;
+
+/// The user agent stylesheet, applied before any author styles
+static DEFAULT_STYLESHEET: &'static str = "
+html, body, div, p, h1, h2, h3, h4, h5, h6, ul, ol, li, dl, dt, dd, blockquote, pre, hr,
+form, center, address, article, aside, footer, header, main, nav, section,
+figure, figcaption, caption {
+    display: block;
+}
+table { display: table; border-spacing: 2px; }
+thead, tbody, tfoot { display: table-row-group; }
+tr { display: table-row; }
+td, th { display: table-cell; padding: 1px; }
+th { text-align: center; }
+caption { text-align: center; }
+head, title, link, meta, script, style, noscript, template {
+    display: none;
+}
+body { margin: 8px; }
+p, ul, ol, dl, pre, form { margin: 1em 0; }
+h1 { font-size: 2em; font-weight: bold; margin: 0.67em 0; }
+h2 { font-size: 1.5em; font-weight: bold; margin: 0.83em 0; }
+h3 { font-size: 1.125em; font-weight: bold; margin: 1em 0; }
+h4 { font-size: 1em; font-weight: bold; margin: 1.33em 0; }
+h5 { font-size: 0.875em; font-weight: bold; margin: 1.67em 0; }
+h6 { font-size: 0.625em; font-weight: bold; margin: 2.33em 0; }
+ul, ol { padding-left: 40px; }
+dd { margin-left: 40px; }
+blockquote { margin: 1em 40px; }
+li { display: list-item; }
+ul { list-style-type: disc; }
+ul ul, ol ul { list-style-type: circle; }
+ul ul ul, ul ol ul, ol ul ul, ol ol ul { list-style-type: square; }
+ol { list-style-type: decimal; }
+pre { white-space: pre; }
+pre, code, kbd, samp, tt { font-family: monospace; }
+i, em, cite, var, dfn, address { font-style: italic; }
+u, ins { text-decoration: underline; }
+s, strike, del { text-decoration: line-through; }
+hr { border: 1px solid gray; margin: 0.5em 0; }
+a { color: #0000ff; }
+b, strong, th { font-weight: bold; }
+center { text-align: center; }
+small { font-size: smaller; }
+big { font-size: larger; }
+";
+
+/// The specificity given to declarations in a `style` attribute
+const INLINE_SPECIFICITY: u32 = 1000000;
+
+/// How an element takes part in layout
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Display {
+    Inline,
+    Block,
+    Table,
+    TableRowGroup,
+    TableRow,
+    TableCell,
+    /// A block with a list marker before its first line
+    ListItem,
+    None,
+}
+
+/// The horizontal alignment of lines within a block
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+/// How the spaces and newlines of text are laid out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WhiteSpace {
+    /// Runs of whitespace collapse into one space and lines wrap
+    Normal,
+    /// Whitespace is kept as it is and lines only break at newlines
+    Pre,
+}
+
+/// The marker drawn before each item of a list
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListStyle {
+    None,
+    Disc,
+    Circle,
+    Square,
+    Decimal,
+    LowerAlpha,
+    UpperAlpha,
+    LowerRoman,
+    UpperRoman,
+}
+
+/// Whether a border is drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BorderStyle {
+    None,
+    Solid,
+}
+
+/// A length that is resolved against the containing block during layout
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Length {
+    Auto,
+    Px(f32),
+    Percent(f32),
+}
+
+impl Length {
+    /// Resolve to pixels at the zoom of the page, given the width of the containing block, which is already zoomed
+    pub fn resolve(&self, width: i32, zoom: f32) -> i32 {
+        match *self {
+            Length::Auto => 0,
+            Length::Px(px) => (px * zoom).round() as i32,
+            Length::Percent(percent) => (width as f32 * percent / 100.0).round() as i32,
+        }
+    }
+}
+
+/// The computed style of a node
+#[derive(Clone)]
+pub struct Style {
+    pub color: Color,
+    pub font_size: f32,
+    pub bold: bool,
+    pub italic: bool,
+    pub monospace: bool,
+    pub underline: bool,
+    pub line_through: bool,
+    pub white_space: WhiteSpace,
+    pub list_style: ListStyle,
+    /// Top, right, bottom and left margins
+    pub margin: [Length; 4],
+    /// Top, right, bottom and left padding
+    pub padding: [Length; 4],
+    /// The content width, or `Auto` to fill the containing block
+    pub width: Length,
+    /// The largest content width, or `Auto` for no limit
+    pub max_width: Length,
+    pub border_width: i32,
+    pub border_style: BorderStyle,
+    /// The border color, or `None` to use the text color
+    pub border_color: Option<Color>,
+    /// The space between the cells of a table
+    pub border_spacing: i32,
+    pub display: Display,
+    pub text_align: TextAlign,
+    pub background_color: Option<Color>,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            color: Color::rgb(0, 0, 0),
+            font_size: settings::get().font_size,
+            bold: false,
+            italic: false,
+            monospace: false,
+            underline: false,
+            line_through: false,
+            white_space: WhiteSpace::Normal,
+            list_style: ListStyle::None,
+            margin: [Length::Px(0.0); 4],
+            padding: [Length::Px(0.0); 4],
+            width: Length::Auto,
+            max_width: Length::Auto,
+            border_width: 3,
+            border_style: BorderStyle::None,
+            border_color: None,
+            border_spacing: 0,
+            display: Display::Inline,
+            text_align: TextAlign::Left,
+            background_color: None,
+        }
+    }
+}
+
+impl Style {
+    /// The starting style of a child element, carrying over the inherited properties
+    pub fn inherit(&self) -> Style {
+        Style {
+            color: self.color,
+            font_size: self.font_size,
+            bold: self.bold,
+            italic: self.italic,
+            monospace: self.monospace,
+            underline: self.underline,
+            line_through: self.line_through,
+            white_space: self.white_space,
+            list_style: self.list_style,
+            text_align: self.text_align,
+            border_spacing: self.border_spacing,
+            .. Style::default()
+        }
+    }
+
+    /// The width of the border as drawn at the zoom of the page, which keeps thin borders visible
+    pub fn border(&self, zoom: f32) -> i32 {
+        match self.border_style {
+            BorderStyle::None => 0,
+            BorderStyle::Solid => if self.border_width > 0 {
+                cmp::max(1, (self.border_width as f32 * zoom).round() as i32)
+            } else {
+                0
+            },
+        }
+    }
+
+    /// The color the border is drawn in
+    pub fn border_color(&self) -> Color {
+        self.border_color.unwrap_or(self.color)
+    }
+
+    fn apply(&mut self, declaration: &Declaration, parent: &Style) {
+        let value = declaration.value.as_str();
+
+        if value == "inherit" {
+            match declaration.name.as_str() {
+                "color" => self.color = parent.color,
+                "font-size" => self.font_size = parent.font_size,
+                "font-weight" => self.bold = parent.bold,
+                "font-style" => self.italic = parent.italic,
+                "font-family" => self.monospace = parent.monospace,
+                "text-decoration" => {
+                    self.underline = parent.underline;
+                    self.line_through = parent.line_through;
+                },
+                "white-space" => self.white_space = parent.white_space,
+                "list-style" | "list-style-type" => self.list_style = parent.list_style,
+                "margin" => self.margin = parent.margin,
+                "margin-top" => self.margin[0] = parent.margin[0],
+                "margin-right" => self.margin[1] = parent.margin[1],
+                "margin-bottom" => self.margin[2] = parent.margin[2],
+                "margin-left" => self.margin[3] = parent.margin[3],
+                "padding" => self.padding = parent.padding,
+                "padding-top" => self.padding[0] = parent.padding[0],
+                "padding-right" => self.padding[1] = parent.padding[1],
+                "padding-bottom" => self.padding[2] = parent.padding[2],
+                "padding-left" => self.padding[3] = parent.padding[3],
+                "width" => self.width = parent.width,
+                "max-width" => self.max_width = parent.max_width,
+                "border-width" => self.border_width = parent.border_width,
+                "border-style" => self.border_style = parent.border_style,
+                "border-color" => self.border_color = parent.border_color,
+                "border-spacing" => self.border_spacing = parent.border_spacing,
+                "display" => self.display = parent.display,
+                "text-align" => self.text_align = parent.text_align,
+                "background-color" => self.background_color = parent.background_color,
+                _ => ()
+            }
+            return;
+        }
+
+        match declaration.name.as_str() {
+            "color" => if let Some(color) = parse_color(value) {
+                self.color = color;
+            },
+            "background-color" => if value == "transparent" {
+                self.background_color = None;
+            } else if let Some(color) = parse_color(value) {
+                self.background_color = Some(color);
+            },
+            "background" => for part in value.split_whitespace() {
+                if part == "none" || part == "transparent" {
+                    self.background_color = None;
+                } else if let Some(color) = parse_color(part) {
+                    self.background_color = Some(color);
+                }
+            },
+            "font-size" => if let Some(size) = parse_font_size(value, parent.font_size) {
+                self.font_size = size;
+            },
+            "font-weight" => if let Some(bold) = parse_font_weight(value) {
+                self.bold = bold;
+            },
+            "font-style" => if let Some(italic) = parse_font_style(value) {
+                self.italic = italic;
+            },
+            "font-family" => self.monospace = is_monospace(value),
+            "font" => {
+                for part in value.split_whitespace() {
+                    if let Some(bold) = parse_font_weight(part) {
+                        self.bold = bold;
+                    } else if let Some(italic) = parse_font_style(part) {
+                        self.italic = italic;
+                    } else if let Some(size) = parse_font_size(part.split('/').next().unwrap_or(""), parent.font_size) {
+                        self.font_size = size;
+                    }
+                }
+                self.monospace = is_monospace(value);
+            },
+            "text-decoration" | "text-decoration-line" => {
+                self.underline = value.contains("underline");
+                self.line_through = value.contains("line-through");
+            },
+            "white-space" => match value {
+                "normal" | "nowrap" => self.white_space = WhiteSpace::Normal,
+                "pre" | "pre-wrap" | "pre-line" | "break-spaces" => self.white_space = WhiteSpace::Pre,
+                _ => ()
+            },
+            "list-style-type" => if let Some(list_style) = parse_list_style(value) {
+                self.list_style = list_style;
+            },
+            "list-style" => for part in value.split_whitespace() {
+                if let Some(list_style) = parse_list_style(part) {
+                    self.list_style = list_style;
+                }
+            },
+            "margin" => if let Some(lengths) = parse_edges(value, self.font_size) {
+                self.margin = lengths;
+            },
+            "margin-top" => if let Some(length) = parse_length(value, self.font_size) {
+                self.margin[0] = length;
+            },
+            "margin-right" => if let Some(length) = parse_length(value, self.font_size) {
+                self.margin[1] = length;
+            },
+            "margin-bottom" => if let Some(length) = parse_length(value, self.font_size) {
+                self.margin[2] = length;
+            },
+            "margin-left" => if let Some(length) = parse_length(value, self.font_size) {
+                self.margin[3] = length;
+            },
+            "padding" => if let Some(lengths) = parse_edges(value, self.font_size) {
+                self.padding = lengths;
+            },
+            "padding-top" => if let Some(length) = parse_length(value, self.font_size) {
+                self.padding[0] = length;
+            },
+            "padding-right" => if let Some(length) = parse_length(value, self.font_size) {
+                self.padding[1] = length;
+            },
+            "padding-bottom" => if let Some(length) = parse_length(value, self.font_size) {
+                self.padding[2] = length;
+            },
+            "padding-left" => if let Some(length) = parse_length(value, self.font_size) {
+                self.padding[3] = length;
+            },
+            "width" => if let Some(length) = parse_length(value, self.font_size) {
+                self.width = length;
+            },
+            "max-width" => if value == "none" {
+                self.max_width = Length::Auto;
+            } else if let Some(length) = parse_length(value, self.font_size) {
+                self.max_width = length;
+            },
+            "border" => for part in value.split_whitespace() {
+                if let Some(width) = parse_border_width(part, self.font_size) {
+                    self.border_width = width;
+                } else if let Some(border_style) = parse_border_style(part) {
+                    self.border_style = border_style;
+                } else if let Some(color) = parse_color(part) {
+                    self.border_color = Some(color);
+                }
+            },
+            "border-width" => if let Some(width) = parse_border_width(value.split_whitespace().next().unwrap_or(""), self.font_size) {
+                self.border_width = width;
+            },
+            "border-style" => if let Some(border_style) = parse_border_style(value.split_whitespace().next().unwrap_or("")) {
+                self.border_style = border_style;
+            },
+            "border-color" => if let Some(color) = parse_color(value.split_whitespace().next().unwrap_or("")) {
+                self.border_color = Some(color);
+            },
+            "border-spacing" => if let Some(length) = parse_length(value.split_whitespace().next().unwrap_or(""), self.font_size) {
+                self.border_spacing = length.resolve(0, 1.0);
+            },
+            "display" => match value {
+                "none" => self.display = Display::None,
+                "inline" | "inline-block" => self.display = Display::Inline,
+                "block" | "flex" | "grid" | "table-caption" => self.display = Display::Block,
+                "list-item" => self.display = Display::ListItem,
+                "table" | "inline-table" => self.display = Display::Table,
+                "table-row-group" | "table-header-group" | "table-footer-group" => self.display = Display::TableRowGroup,
+                "table-row" => self.display = Display::TableRow,
+                "table-cell" => self.display = Display::TableCell,
+                _ => ()
+            },
+            "text-align" => match value {
+                "left" | "start" | "justify" => self.text_align = TextAlign::Left,
+                "center" => self.text_align = TextAlign::Center,
+                "right" | "end" => self.text_align = TextAlign::Right,
+                _ => ()
+            },
+            _ => ()
+        }
+    }
+}
+
+/// The parts of an element that selectors can match against
+pub struct ElementData {
+    pub tag: String,
+    pub id: Option<String>,
+    pub classes: Vec<String>,
+}
+
+impl ElementData {
+    pub fn new(tag: &str, attrs: &[Attribute]) -> ElementData {
+        let mut id = None;
+        let mut classes = Vec::new();
+        for attr in attrs.iter() {
+            match &*attr.name.local {
+                "id" => id = Some(attr.value.to_string()),
+                "class" => for class in attr.value.split_whitespace() {
+                    classes.push(class.to_string());
+                },
+                _ => ()
+            }
+        }
+
+        ElementData {
+            tag: tag.to_lowercase(),
+            id: id,
+            classes: classes,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+/// A type, id and class selector without combinators, such as `p.note`
+struct Compound {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+}
+
+impl Compound {
+    fn parse(text: &str) -> Option<Compound> {
+        if text.is_empty() || text.contains(|c: char| c == ':' || c == '[' || c == '+' || c == '~') {
+            return None;
+        }
+
+        let mut compound = Compound {
+            tag: None,
+            id: None,
+            classes: Vec::new(),
+        };
+
+        let mut kind = ' ';
+        let mut name = String::new();
+        for c in text.chars().chain(Some('.')) {
+            if c == '.' || c == '#' {
+                match kind {
+                    ' ' => if ! name.is_empty() && name != "*" {
+                        compound.tag = Some(name.to_lowercase());
+                    },
+                    '#' => compound.id = Some(name.clone()),
+                    _ => compound.classes.push(name.clone()),
+                }
+                kind = c;
+                name.clear();
+            } else {
+                name.push(c);
+            }
+        }
+
+        Some(compound)
+    }
+
+    fn matches(&self, element: &ElementData) -> bool {
+        if let Some(ref tag) = self.tag {
+            if *tag != element.tag {
+                return false;
+            }
+        }
+
+        if let Some(ref id) = self.id {
+            if Some(id) != element.id.as_ref() {
+                return false;
+            }
+        }
+
+        self.classes.iter().all(|class| element.classes.contains(class))
+    }
+
+    fn specificity(&self) -> u32 {
+        let mut specificity = 0;
+        if self.id.is_some() {
+            specificity += 10000;
+        }
+        specificity += self.classes.len() as u32 * 100;
+        if self.tag.is_some() {
+            specificity += 1;
+        }
+        specificity
+    }
+}
+
+/// A complex selector such as `div > p.note a`
+struct Selector {
+    /// Compound selectors from right to left, each with the combinator to the compound on its left
+    compounds: Vec<(Compound, Combinator)>,
+    specificity: u32,
+}
+
+impl Selector {
+    fn parse(text: &str) -> Option<Selector> {
+        let mut compounds = Vec::new();
+        let mut combinator = Combinator::Descendant;
+        for part in text.replace('>', " > ").split_whitespace() {
+            if part == ">" {
+                combinator = Combinator::Child;
+            } else {
+                match Compound::parse(part) {
+                    Some(compound) => compounds.push((compound, combinator)),
+                    None => return None
+                }
+                combinator = Combinator::Descendant;
+            }
+        }
+
+        if compounds.is_empty() {
+            return None;
+        }
+
+        // Compounds are matched from the subject on the right, each holding the combinator to its left
+        compounds.reverse();
+
+        let specificity = compounds.iter().map(|&(ref compound, _)| compound.specificity()).sum::<u32>();
+
+        Some(Selector {
+            compounds: compounds,
+            specificity: specificity,
+        })
+    }
+
+    fn matches(&self, element: &ElementData, ancestors: &[ElementData]) -> bool {
+        match self.compounds.first() {
+            Some(&(ref compound, combinator)) => compound.matches(element) && matches_ancestors(&self.compounds[1..], combinator, ancestors),
+            None => false
+        }
+    }
+}
+
+/// Match the remaining compounds of a selector against the ancestors, which are ordered from the root down
+fn matches_ancestors(compounds: &[(Compound, Combinator)], combinator: Combinator, ancestors: &[ElementData]) -> bool {
+    let (compound, next_combinator) = match compounds.first() {
+        Some(&(ref compound, next_combinator)) => (compound, next_combinator),
+        None => return true
+    };
+
+    match combinator {
+        Combinator::Child => match ancestors.last() {
+            Some(parent) => compound.matches(parent) && matches_ancestors(&compounds[1..], next_combinator, &ancestors[.. ancestors.len() - 1]),
+            None => false
+        },
+        Combinator::Descendant => {
+            let mut i = ancestors.len();
+            while i > 0 {
+                i -= 1;
+                if compound.matches(&ancestors[i]) && matches_ancestors(&compounds[1..], next_combinator, &ancestors[.. i]) {
+                    return true;
+                }
+            }
+            false
+        }
+    }
+}
+
+struct Declaration {
+    name: String,
+    value: String,
+    important: bool,
+}
+
+fn parse_declarations(text: &str) -> Vec<Declaration> {
+    let mut declarations = Vec::new();
+    for part in text.split(';') {
+        let mut name_value = part.splitn(2, ':');
+        if let (Some(name), Some(value)) = (name_value.next(), name_value.next()) {
+            let name = name.trim().to_lowercase();
+            let mut value = value.trim();
+            let mut important = false;
+            if let Some(i) = value.find('!') {
+                if value[i + 1 ..].trim().to_lowercase() == "important" {
+                    important = true;
+                    value = value[.. i].trim();
+                }
+            }
+
+            if ! name.is_empty() && ! value.is_empty() {
+                declarations.push(Declaration {
+                    name: name,
+                    value: value.to_string(),
+                    important: important,
+                });
+            }
+        }
+    }
+    declarations
+}
+
+struct Rule {
+    selectors: Vec<Selector>,
+    declarations: Vec<Declaration>,
+}
+
+/// A parsed stylesheet, from a `style` element, a linked file, or the user agent
+pub struct Stylesheet {
+    rules: Vec<Rule>,
+    user_agent: bool,
+}
+
+impl Stylesheet {
+    /// The user agent stylesheet, which author styles override regardless of specificity
+    pub fn user_agent() -> Stylesheet {
+        let mut stylesheet = Stylesheet::parse(DEFAULT_STYLESHEET);
+        stylesheet.user_agent = true;
+        stylesheet
+    }
+
+    pub fn parse(text: &str) -> Stylesheet {
+        let text = strip_comments(text);
+
+        let mut rules = Vec::new();
+        let mut rest = text.as_str();
+        loop {
+            rest = rest.trim_left();
+            if rest.is_empty() {
+                break;
+            }
+
+            // At-rules such as @media and @import are skipped
+            if rest.starts_with('@') {
+                match (rest.find(';'), rest.find('{')) {
+                    (Some(semicolon), Some(open)) if semicolon < open => rest = &rest[semicolon + 1 ..],
+                    (_, Some(open)) => rest = &rest[block_end(rest, open) ..],
+                    (Some(semicolon), None) => rest = &rest[semicolon + 1 ..],
+                    (None, None) => break
+                }
+                continue;
+            }
+
+            let open = match rest.find('{') {
+                Some(open) => open,
+                None => break
+            };
+            let close = match rest[open ..].find('}') {
+                Some(close) => open + close,
+                None => rest.len()
+            };
+
+            let selectors: Vec<Selector> = rest[.. open].split(',').filter_map(|selector| Selector::parse(selector.trim())).collect();
+            if ! selectors.is_empty() {
+                rules.push(Rule {
+                    selectors: selectors,
+                    declarations: parse_declarations(&rest[open + 1 .. close]),
+                });
+            }
+
+            rest = if close < rest.len() { &rest[close + 1 ..] } else { "" };
+        }
+
+        Stylesheet {
+            rules: rules,
+            user_agent: false,
+        }
+    }
+}
+
+fn strip_comments(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("/*") {
+        result.push_str(&rest[.. start]);
+        rest = match rest[start + 2 ..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2 ..],
+            None => ""
+        };
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Find the index just past the brace that closes the block opened at `open`
+fn block_end(text: &str, open: usize) -> usize {
+    let mut depth = 0;
+    for (i, c) in text[open ..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return open + i + 1;
+                }
+            },
+            _ => ()
+        }
+    }
+    text.len()
+}
+
+/// Compute the style of an element from every matching rule, its presentational hints and its `style` attribute, in cascade order
+pub fn cascade(stylesheets: &[Stylesheet], element: &ElementData, ancestors: &[ElementData], hints: &str, inline: Option<&str>, parent: &Style) -> Style {
+    let hint_declarations = parse_declarations(hints);
+    let inline_declarations = match inline {
+        Some(text) => parse_declarations(text),
+        None => Vec::new()
+    };
+
+    // Declarations are sorted by importance, then origin, then specificity, then order of appearance.
+    // Author declarations win over those of the user agent, unless both are important.
+    let mut matched = Vec::new();
+    for stylesheet in stylesheets.iter() {
+        for rule in stylesheet.rules.iter() {
+            let specificity = rule.selectors.iter()
+                .filter(|selector| selector.matches(element, ancestors))
+                .map(|selector| selector.specificity)
+                .max();
+
+            if let Some(specificity) = specificity {
+                for declaration in rule.declarations.iter() {
+                    let order = matched.len();
+                    matched.push((declaration.important, ! stylesheet.user_agent, specificity, order, declaration));
+                }
+            }
+        }
+    }
+
+    for declaration in hint_declarations.iter() {
+        let order = matched.len();
+        matched.push((declaration.important, true, 0, order, declaration));
+    }
+
+    for declaration in inline_declarations.iter() {
+        let order = matched.len();
+        matched.push((declaration.important, true, INLINE_SPECIFICITY, order, declaration));
+    }
+
+    matched.sort_by_key(|&(important, author, specificity, order, _)| (important, author != important, specificity, order));
+
+    let mut style = parent.inherit();
+
+    // The font size is applied first, so that em lengths in other properties can use it
+    for &(_, _, _, _, declaration) in matched.iter() {
+        if declaration.name == "font-size" || declaration.name == "font" {
+            style.apply(declaration, parent);
+        }
+    }
+    for &(_, _, _, _, declaration) in matched.iter() {
+        if declaration.name != "font-size" && declaration.name != "font" {
+            style.apply(declaration, parent);
+        }
+    }
+
+    style
+}
+
+/// Parse a length, using the font size for em units
+pub fn parse_length(value: &str, font_size: f32) -> Option<Length> {
+    let value = value.trim();
+    if value == "auto" {
+        return Some(Length::Auto);
+    }
+    if value == "0" {
+        return Some(Length::Px(0.0));
+    }
+
+    let units = [("px", 1.0), ("pt", 4.0 / 3.0), ("rem", settings::get().font_size), ("em", font_size), ("ex", font_size / 2.0)];
+    for &(unit, scale) in units.iter() {
+        if value.ends_with(unit) {
+            return value[.. value.len() - unit.len()].parse::<f32>().ok().map(|number| Length::Px(number * scale));
+        }
+    }
+
+    if value.ends_with('%') {
+        return value[.. value.len() - 1].parse::<f32>().ok().map(Length::Percent);
+    }
+
+    None
+}
+
+/// Translate presentational attributes such as `bgcolor` and `border` into declarations
+pub fn hints(tag: &str, attrs: &[Attribute]) -> String {
+    let mut hints = String::new();
+    for attr in attrs.iter() {
+        let value = attr.value.trim();
+        match (tag, &*attr.name.local) {
+            (_, "bgcolor") => {
+                hints.push_str(&format!("background-color: {};", value));
+            },
+            ("table", "width") | ("td", "width") | ("th", "width") => {
+                if value.ends_with('%') {
+                    hints.push_str(&format!("width: {};", value));
+                } else {
+                    hints.push_str(&format!("width: {}px;", value));
+                }
+            },
+            ("table", "align") => if value.to_lowercase() == "center" {
+                hints.push_str("margin-left: auto; margin-right: auto;");
+            },
+            (_, "align") => {
+                hints.push_str(&format!("text-align: {};", value.to_lowercase()));
+            },
+            ("table", "border") => match value.parse::<i32>() {
+                Ok(0) => (),
+                Ok(width) => hints.push_str(&format!("border: {}px solid gray;", width)),
+                Err(_) => hints.push_str("border: 1px solid gray;"),
+            },
+            ("table", "cellspacing") => if let Ok(spacing) = value.parse::<i32>() {
+                hints.push_str(&format!("border-spacing: {}px;", spacing));
+            },
+            ("ol", "type") | ("ul", "type") | ("li", "type") => {
+                let list_style = match value {
+                    "1" => "decimal",
+                    "a" => "lower-alpha",
+                    "A" => "upper-alpha",
+                    "i" => "lower-roman",
+                    "I" => "upper-roman",
+                    _ => value
+                };
+                hints.push_str(&format!("list-style-type: {};", list_style.to_lowercase()));
+            },
+            ("pre", "wrap") => {
+                hints.push_str("white-space: pre-wrap;");
+            },
+            _ => ()
+        }
+    }
+    hints
+}
+
+fn parse_border_width(value: &str, font_size: f32) -> Option<i32> {
+    match value {
+        "thin" => Some(1),
+        "medium" => Some(3),
+        "thick" => Some(5),
+        _ => match parse_length(value, font_size) {
+            Some(Length::Px(px)) => Some(px.round() as i32),
+            _ => None
+        }
+    }
+}
+
+fn parse_border_style(value: &str) -> Option<BorderStyle> {
+    match value {
+        "none" | "hidden" => Some(BorderStyle::None),
+        "solid" | "dotted" | "dashed" | "double" | "groove" | "ridge" | "inset" | "outset" => Some(BorderStyle::Solid),
+        _ => None
+    }
+}
+
+/// Parse the one to four lengths of a margin or padding shorthand into top, right, bottom and left
+fn parse_edges(value: &str, font_size: f32) -> Option<[Length; 4]> {
+    let parts: Vec<Length> = value.split_whitespace().filter_map(|part| parse_length(part, font_size)).collect();
+    match parts.len() {
+        1 => Some([parts[0], parts[0], parts[0], parts[0]]),
+        2 => Some([parts[0], parts[1], parts[0], parts[1]]),
+        3 => Some([parts[0], parts[1], parts[2], parts[1]]),
+        4 => Some([parts[0], parts[1], parts[2], parts[3]]),
+        _ => None
+    }
+}
+
+fn parse_font_size(value: &str, parent_size: f32) -> Option<f32> {
+    // The keywords are relative to the size set by the user
+    let medium = settings::get().font_size;
+    match value {
+        "xx-small" => Some(medium * 0.5625),
+        "x-small" => Some(medium * 0.625),
+        "small" => Some(medium * 0.8125),
+        "medium" => Some(medium),
+        "large" => Some(medium * 1.125),
+        "x-large" => Some(medium * 1.5),
+        "xx-large" => Some(medium * 2.0),
+        "smaller" => Some(parent_size / 1.2),
+        "larger" => Some(parent_size * 1.2),
+        _ => match parse_length(value, parent_size) {
+            Some(Length::Px(px)) => Some(px),
+            Some(Length::Percent(percent)) => Some(parent_size * percent / 100.0),
+            _ => None
+        }
+    }
+}
+
+fn parse_font_weight(value: &str) -> Option<bool> {
+    match value {
+        "bold" | "bolder" => Some(true),
+        "normal" | "lighter" => Some(false),
+        _ => value.parse::<u32>().ok().map(|weight| weight >= 600)
+    }
+}
+
+fn parse_font_style(value: &str) -> Option<bool> {
+    match value {
+        "italic" | "oblique" => Some(true),
+        "normal" => Some(false),
+        _ => None
+    }
+}
+
+/// Whether a font family list asks for a monospace font, which is the only kind of family told apart
+fn is_monospace(value: &str) -> bool {
+    let value = value.to_lowercase();
+    value.contains("mono") || value.contains("courier")
+}
+
+fn parse_list_style(value: &str) -> Option<ListStyle> {
+    match value {
+        "none" => Some(ListStyle::None),
+        "disc" => Some(ListStyle::Disc),
+        "circle" => Some(ListStyle::Circle),
+        "square" => Some(ListStyle::Square),
+        "decimal" | "decimal-leading-zero" => Some(ListStyle::Decimal),
+        "lower-alpha" | "lower-latin" => Some(ListStyle::LowerAlpha),
+        "upper-alpha" | "upper-latin" => Some(ListStyle::UpperAlpha),
+        "lower-roman" => Some(ListStyle::LowerRoman),
+        "upper-roman" => Some(ListStyle::UpperRoman),
+        _ => None
+    }
+}
+
+/// Parse a color keyword, hex color, or rgb() function
+pub fn parse_color(value: &str) -> Option<Color> {
+    let value = value.trim().to_lowercase();
+
+    if value.starts_with('#') {
+        let hex = &value[1 ..];
+        let digits: Vec<u8> = hex.chars().filter_map(|c| c.to_digit(16)).map(|digit| digit as u8).collect();
+        if digits.len() != hex.len() {
+            return None;
+        }
+        return match digits.len() {
+            3 => Some(Color::rgb(digits[0] * 17, digits[1] * 17, digits[2] * 17)),
+            6 => Some(Color::rgb(digits[0] * 16 + digits[1], digits[2] * 16 + digits[3], digits[4] * 16 + digits[5])),
+            _ => None
+        };
+    }
+
+    if value.starts_with("rgb(") || value.starts_with("rgba(") {
+        let open = value.find('(').unwrap_or(0);
+        let close = value.rfind(')').unwrap_or(value.len());
+        if close <= open {
+            return None;
+        }
+
+        let channels: Vec<u8> = value[open + 1 .. close].split(',').take(3).filter_map(|part| {
+            let part = part.trim();
+            if part.ends_with('%') {
+                part[.. part.len() - 1].parse::<f32>().ok().map(|percent| (percent * 2.55).max(0.0).min(255.0) as u8)
+            } else {
+                part.parse::<f32>().ok().map(|number| number.max(0.0).min(255.0) as u8)
+            }
+        }).collect();
+        if channels.len() != 3 {
+            return None;
+        }
+
+        let alpha = match value[open + 1 .. close].split(',').nth(3) {
+            Some(part) => match part.trim().parse::<f32>() {
+                Ok(alpha) => (alpha.max(0.0).min(1.0) * 255.0) as u8,
+                Err(_) => return None
+            },
+            None => 255
+        };
+
+        return Some(Color::rgba(channels[0], channels[1], channels[2], alpha));
+    }
+
+    match value.as_str() {
+        "black" => Some(Color::rgb(0, 0, 0)),
+        "silver" => Some(Color::rgb(192, 192, 192)),
+        "gray" | "grey" => Some(Color::rgb(128, 128, 128)),
+        "white" => Some(Color::rgb(255, 255, 255)),
+        "maroon" => Some(Color::rgb(128, 0, 0)),
+        "red" => Some(Color::rgb(255, 0, 0)),
+        "purple" => Some(Color::rgb(128, 0, 128)),
+        "fuchsia" | "magenta" => Some(Color::rgb(255, 0, 255)),
+        "green" => Some(Color::rgb(0, 128, 0)),
+        "lime" => Some(Color::rgb(0, 255, 0)),
+        "olive" => Some(Color::rgb(128, 128, 0)),
+        "yellow" => Some(Color::rgb(255, 255, 0)),
+        "navy" => Some(Color::rgb(0, 0, 128)),
+        "blue" => Some(Color::rgb(0, 0, 255)),
+        "teal" => Some(Color::rgb(0, 128, 128)),
+        "aqua" | "cyan" => Some(Color::rgb(0, 255, 255)),
+        "orange" => Some(Color::rgb(255, 165, 0)),
+        "lightgray" | "lightgrey" => Some(Color::rgb(211, 211, 211)),
+        "darkgray" | "darkgrey" => Some(Color::rgb(169, 169, 169)),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use orbclient::Color;
+
+    use super::{cascade, parse_color, ElementData, Selector, Style, Stylesheet};
+
+    fn element(tag: &str, id: Option<&str>, classes: &[&str]) -> ElementData {
+        ElementData {
+            tag: tag.to_string(),
+            id: id.map(|id| id.to_string()),
+            classes: classes.iter().map(|class| class.to_string()).collect(),
+        }
+    }
+
+    fn specificity(text: &str) -> Option<u32> {
+        Selector::parse(text).map(|selector| selector.specificity)
+    }
+
+    /// The color of a `p class="note" id="main"` inside a `div`, styled by the given stylesheets
+    fn color(stylesheets: &[Stylesheet], inline: Option<&str>) -> Color {
+        let ancestors = [element("div", None, &[])];
+        cascade(stylesheets, &element("p", Some("main"), &["note"]), &ancestors, "", inline, &Style::default()).color
+    }
+
+    #[test]
+    fn parse_color_formats() {
+        assert_eq!(parse_color("#fff"), Some(Color::rgb(255, 255, 255)));
+        assert_eq!(parse_color("#FF8000"), Some(Color::rgb(255, 128, 0)));
+        assert_eq!(parse_color(" Red "), Some(Color::rgb(255, 0, 0)));
+        assert_eq!(parse_color("grey"), Some(Color::rgb(128, 128, 128)));
+        assert_eq!(parse_color("rgb(255, 128, 0)"), Some(Color::rgb(255, 128, 0)));
+        assert_eq!(parse_color("rgb(100%, 0%, 50%)"), Some(Color::rgb(255, 0, 127)));
+        assert_eq!(parse_color("rgb(300, -20, 0)"), Some(Color::rgb(255, 0, 0)));
+        assert_eq!(parse_color("rgba(0, 0, 255, 0.5)"), Some(Color::rgba(0, 0, 255, 127)));
+        assert_eq!(parse_color("rgba(0, 0, 255, 7)"), Some(Color::rgba(0, 0, 255, 255)));
+    }
+
+    #[test]
+    fn parse_color_malformed() {
+        for value in ["", "#", "#ff", "#ffff", "#ggg", "#fé", "#ff00zz", "rgb()", "rgb(1, 2)", "rgb(a, b, c)",
+                      "rgba(1, 2, 3, x)", "rgb)(", "rgba(", "blurple", "red blue"].iter() {
+            assert_eq!(parse_color(value), None, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn selector_specificity() {
+        assert_eq!(specificity("*"), Some(0));
+        assert_eq!(specificity("p"), Some(1));
+        assert_eq!(specificity(".note"), Some(100));
+        assert_eq!(specificity("p.note.wide"), Some(201));
+        assert_eq!(specificity("#main"), Some(10000));
+        assert_eq!(specificity("div > #main p.note a"), Some(10103));
+        assert_eq!(specificity("DIV>P"), Some(2));
+    }
+
+    #[test]
+    fn selector_malformed() {
+        for text in ["", "   ", ">", "a:hover", "a[href]", "h1 + p", "h1 ~ p", "a::before"].iter() {
+            assert!(Selector::parse(text).is_none(), "{:?}", text);
+        }
+    }
+
+    /// Whether a selector matches a `p class="note"` under the first `depth` of `body`, `div id="main" class="box"` and `span`
+    fn matches(text: &str, depth: usize) -> bool {
+        let mut ancestors = vec![element("body", None, &[]), element("div", Some("main"), &["box"]), element("span", None, &[])];
+        ancestors.truncate(depth);
+        Selector::parse(text).unwrap().matches(&element("p", None, &["note"]), &ancestors)
+    }
+
+    #[test]
+    fn selector_combinators() {
+        assert!(matches("p", 0));
+        assert!(matches("P.note", 0));
+        assert!(! matches("p.other", 0));
+        assert!(! matches("div p", 0));
+        assert!(matches("div p", 3));
+        assert!(matches("body #main p", 3));
+        assert!(matches("div > p", 2));
+        assert!(! matches("div > p", 3));
+        assert!(matches("div > span > p", 3));
+        assert!(matches(".box > * > p", 3));
+        assert!(! matches("span div p", 3));
+    }
+
+    #[test]
+    fn cascade_specificity_and_order() {
+        let red = Some(Color::rgb(255, 0, 0));
+        let blue = Some(Color::rgb(0, 0, 255));
+
+        // Specificity beats order, and order breaks ties
+        assert_eq!(Some(color(&[Stylesheet::parse("p.note { color: red } p { color: blue }")], None)), red);
+        assert_eq!(Some(color(&[Stylesheet::parse("p { color: red } p { color: blue }")], None)), blue);
+        assert_eq!(Some(color(&[Stylesheet::parse("p { color: red }"), Stylesheet::parse("p { color: blue }")], None)), blue);
+        assert_eq!(Some(color(&[Stylesheet::parse("#main { color: red } div p.note { color: blue }")], None)), red);
+
+        // A selector list uses the most specific selector that matches
+        assert_eq!(Some(color(&[Stylesheet::parse("span, #main { color: red } p.note { color: blue }")], None)), red);
+
+        // The style attribute beats any selector, and !important beats the style attribute
+        assert_eq!(Some(color(&[Stylesheet::parse("#main { color: red }")], Some("color: blue"))), blue);
+        assert_eq!(Some(color(&[Stylesheet::parse("p { color: red !important }")], Some("color: blue"))), red);
+        assert_eq!(Some(color(&[Stylesheet::parse("p { color: red ! IMPORTANT } #main { color: blue }")], None)), red);
+    }
+
+    #[test]
+    fn cascade_origin() {
+        let mut user_agent = Stylesheet::parse("#main.note { color: red }");
+        user_agent.user_agent = true;
+        let author = Stylesheet::parse("p { color: blue }");
+        assert_eq!(color(&[user_agent, author], None), Color::rgb(0, 0, 255));
+
+        // Important declarations of the user agent win over important author ones
+        let mut user_agent = Stylesheet::parse("p { color: red !important }");
+        user_agent.user_agent = true;
+        let author = Stylesheet::parse("p { color: blue !important }");
+        assert_eq!(color(&[user_agent, author], None), Color::rgb(255, 0, 0));
+
+        let mut user_agent = Stylesheet::parse("p { color: red !important }");
+        user_agent.user_agent = true;
+        assert_eq!(color(&[user_agent], Some("color: blue !important")), Color::rgb(255, 0, 0));
+
+        let mut user_agent = Stylesheet::parse("p { color: red }");
+        user_agent.user_agent = true;
+        let author = Stylesheet::parse("p { color: blue !important }");
+        assert_eq!(color(&[user_agent, author], None), Color::rgb(0, 0, 255));
+    }
+
+    #[test]
+    fn font_shorthand() {
+        let font = |value: &str| cascade(&[], &element("p", None, &[]), &[], "", Some(&format!("font: {}", value)), &Style::default());
+
+        let style = font("italic bold 20px/1.5 \"Courier New\", serif");
+        assert!(style.italic && style.bold && style.monospace);
+        assert_eq!(style.font_size, 20.0);
+
+        let style = font("16px Georgia, serif");
+        assert!(! style.italic && ! style.bold && ! style.monospace);
+        assert_eq!(style.font_size, 16.0);
+    }
+
+    #[test]
+    fn cascade_malformed() {
+        let blue = Color::rgb(0, 0, 255);
+
+        // Broken declarations and invalid values are skipped without losing the ones around them
+        assert_eq!(color(&[Stylesheet::parse("p { color: ; : red; color blue; color: blue; color: nonsense }")], None), blue);
+        assert_eq!(color(&[Stylesheet::parse("p { color: blue }")], Some(";;color:;;")), blue);
+
+        // Unsupported selectors, at-rules and comments are skipped, leaving the rest of a selector list
+        assert_eq!(color(&[Stylesheet::parse("p { color: blue } p:hover { color: red }")], None), blue);
+        assert_eq!(color(&[Stylesheet::parse("p { color: red } a[href], p { color: blue }")], None), blue);
+        assert_eq!(color(&[Stylesheet::parse("@import 'x.css'; @media print { p { color: red } } p { color: blue }")], None), blue);
+        assert_eq!(color(&[Stylesheet::parse("p { color: blue } /* p { color: red } */")], None), blue);
+        assert_eq!(color(&[Stylesheet::parse("p { color: blue } /* p { color: red }")], None), blue);
+
+        // An unclosed block runs to the end of the stylesheet
+        assert_eq!(color(&[Stylesheet::parse("p { color: blue")], None), blue);
+        assert_eq!(color(&[Stylesheet::parse("p { color: blue } @media { p { color: red }")], None), blue);
+        assert_eq!(color(&[Stylesheet::parse("} { color: red } p { color: blue }")], None), blue);
+    }
+}
@@ -0,0 +1,583 @@
+use std::{cmp, fs, str};
+use std::fs::{File, OpenOptions};
+use std::io::{stderr, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use hyper::client::{RedirectPolicy, Response};
+use hyper::header::{self, ByteRangeSpec, ContentRangeSpec, Headers};
+use hyper::status::StatusCode;
+use orbclient::{Color, Renderer, Window};
+use orbfont::Font;
+use url::Url;
+use url::percent_encoding::percent_decode;
+
+use auth;
+use connection;
+use content::format_size;
+use cookies;
+use settings;
+use super::MAX_REDIRECTS;
+
+#[cfg(target_os = "redox")]
+static LAUNCH_COMMAND: &'static str = "/ui/bin/launcher";
+
+#[cfg(not(target_os = "redox"))]
+static LAUNCH_COMMAND: &'static str = "xdg-open";
+
+const ROW_H: i32 = 24;
+/// How many downloads the pane shows at once
+const ROWS: i32 = 5;
+
+/// The height of the downloads pane along the bottom of the window
+pub const HEIGHT: i32 = ROWS * ROW_H + 4;
+
+const FONT_SIZE: f32 = 12.0;
+const BAR_W: i32 = 160;
+const BAR_H: i32 = 8;
+const BUTTON_W: i32 = 64;
+
+/// What a download thread reports back
+enum Update {
+    /// How much of the file has arrived, and how large it is if the server said
+    Progress(u64, Option<u64>),
+    Finished,
+    Failed(String),
+}
+
+/// Where a download is at
+pub enum State {
+    Running,
+    /// Stopped from the pane, keeping what has arrived for resuming
+    Stopped,
+    /// Interrupted, keeping what has arrived for resuming
+    Failed(String),
+    Finished,
+}
+
+/// A file being saved to the downloads directory
+pub struct Download {
+    pub url: Url,
+    /// Where the file goes once it is complete, with the part received so far next to it until then
+    pub path: PathBuf,
+    pub received: u64,
+    pub total: Option<u64>,
+    pub state: State,
+    receiver: Option<Receiver<Update>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Download {
+    fn new(url: Url, data: Option<Vec<u8>>) -> Download {
+        let mut download = Download {
+            url: url,
+            path: PathBuf::new(),
+            received: 0,
+            total: None,
+            state: State::Stopped,
+            receiver: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        };
+        download.run(data);
+        download
+    }
+
+    /// Fetch the file again, going on from the end of the part already received
+    fn resume(&mut self) {
+        self.run(None);
+    }
+
+    /// Start a thread saving the file, from its content if that has already arrived, and otherwise
+    /// by fetching it from the end of the part received so far
+    fn run(&mut self, data: Option<Vec<u8>>) {
+        // The part file is created straight away, so that no other download picks the same name
+        if self.path.as_os_str().is_empty() {
+            let reserved = file_path(&self.url).and_then(|path| {
+                File::create(part_path(&path)).map(|_| path).map_err(|err| format!("Failed to create file: {}", err))
+            });
+            match reserved {
+                Ok(path) => self.path = path,
+                Err(err) => {
+                    self.state = State::Failed(err);
+                    return;
+                }
+            }
+        }
+
+        let offset = fs::metadata(part_path(&self.path)).map(|metadata| metadata.len()).unwrap_or(0);
+        let (sender, receiver) = channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let url = self.url.clone();
+        let path = self.path.clone();
+        let thread_cancelled = cancelled.clone();
+        thread::spawn(move || {
+            let result = match data {
+                Some(data) => store(&url, &path, &data, &sender),
+                None => fetch(&url, &path, offset, &sender, &thread_cancelled)
+            };
+            if let Err(err) = result {
+                let _ = write!(stderr(), "* Failed to download {}: {}\n", url, err);
+                let _ = sender.send(Update::Failed(err));
+            }
+        });
+
+        self.received = offset;
+        self.total = None;
+        self.state = State::Running;
+        self.receiver = Some(receiver);
+        self.cancelled = cancelled;
+    }
+
+    fn stop(&mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.receiver = None;
+        self.state = State::Stopped;
+    }
+
+    /// Apply what the thread has reported, and return whether anything changed
+    fn poll(&mut self) -> bool {
+        let mut updates = Vec::new();
+        let mut gone = false;
+        if let Some(ref receiver) = self.receiver {
+            loop {
+                match receiver.try_recv() {
+                    Ok(update) => updates.push(update),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        gone = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        let changed = gone || ! updates.is_empty();
+        for update in updates {
+            match update {
+                Update::Progress(received, total) => {
+                    self.received = received;
+                    self.total = total;
+                },
+                Update::Finished => self.state = State::Finished,
+                Update::Failed(err) => self.state = State::Failed(err)
+            }
+        }
+
+        if gone {
+            self.receiver = None;
+            // A thread that went away without a word has panicked
+            if let State::Running = self.state {
+                self.state = State::Failed(format!("Interrupted"));
+            }
+        }
+        changed
+    }
+
+    fn name(&self) -> String {
+        match self.path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => self.url.to_string()
+        }
+    }
+
+    fn status(&self) -> String {
+        match self.state {
+            State::Running => match self.total {
+                Some(total) => format!("{} of {}", format_size(self.received), format_size(total)),
+                None => format!("{} received", format_size(self.received))
+            },
+            State::Stopped => format!("Stopped at {}", format_size(self.received)),
+            State::Failed(ref err) => format!("Failed at {}: {}", format_size(self.received), err),
+            State::Finished => format!("{}, saved to {}", format_size(self.received), self.path.display())
+        }
+    }
+
+    /// The label of the button of the row, which stops, resumes or opens the download
+    fn action(&self) -> &'static str {
+        match self.state {
+            State::Running => "Stop",
+            State::Stopped | State::Failed(_) => "Resume",
+            State::Finished => "Open"
+        }
+    }
+}
+
+/// The downloads of the session, newest first, and the pane listing them
+pub struct Downloads {
+    pub list: Vec<Download>,
+    pub open: bool,
+    /// The first row in view
+    scroll: usize,
+}
+
+impl Downloads {
+    pub fn new() -> Downloads {
+        Downloads {
+            list: Vec::new(),
+            open: false,
+            scroll: 0,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = ! self.open;
+    }
+
+    /// Start saving a URL, with its content if that has already arrived, opening the pane so that its progress can be followed
+    pub fn start(&mut self, url: Url, data: Option<Vec<u8>>) {
+        let _ = write!(stderr(), "* Downloading {}\n", url);
+        self.list.insert(0, Download::new(url, data));
+        self.open = true;
+        self.scroll = 0;
+    }
+
+    /// Apply what the download threads have reported, and return whether anything changed
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        for download in self.list.iter_mut() {
+            if download.poll() {
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Scroll the pane by a number of rows
+    pub fn scroll(&mut self, rows: i32) {
+        let max = cmp::max(0, self.list.len() as i32 - ROWS);
+        self.scroll = cmp::max(0, cmp::min(max, self.scroll as i32 + rows)) as usize;
+    }
+
+    /// Press the button of the row at a point within the pane
+    pub fn click(&mut self, x: i32, y: i32, width: i32) {
+        let button_x = width - BUTTON_W - 4;
+        if x < button_x || x >= button_x + BUTTON_W {
+            return;
+        }
+
+        let row = self.scroll + (cmp::max(0, y - 2) / ROW_H) as usize;
+        if let Some(download) = self.list.get_mut(row) {
+            match download.state {
+                State::Running => download.stop(),
+                State::Stopped | State::Failed(_) => download.resume(),
+                State::Finished => open(&download.path)
+            }
+        }
+    }
+
+    /// Draw the pane with its bottom edge at the given height
+    pub fn draw(&self, window: &mut Window, font: &Font, bottom: i32) {
+        let width = window.width() as i32;
+        let y = bottom - HEIGHT;
+        window.rect(0, y, width as u32, HEIGHT as u32, Color::rgb(245, 245, 245));
+        window.rect(0, y, width as u32, 1, Color::rgb(128, 128, 128));
+
+        if self.list.is_empty() {
+            let text = font.render("No downloads", FONT_SIZE);
+            text.draw(window, 4, y + 2 + (ROW_H - text.height() as i32) / 2, Color::rgb(96, 96, 96));
+            return;
+        }
+
+        let button_x = width - BUTTON_W - 4;
+        let bar_x = button_x - BAR_W - 8;
+        for (i, download) in self.list.iter().skip(self.scroll).take(ROWS as usize).enumerate() {
+            let row_y = y + 2 + i as i32 * ROW_H;
+
+            // A label too long for the room left of the bar is cut short
+            let mut label: String = format!("{}  {}", download.name(), download.status()).chars().take(160).collect();
+            while ! label.is_empty() && font.render(&label, FONT_SIZE).width() as i32 > bar_x - 12 {
+                label.pop();
+            }
+            let color = match download.state {
+                State::Failed(_) => Color::rgb(160, 0, 0),
+                _ => Color::rgb(0, 0, 0)
+            };
+            let text = font.render(&label, FONT_SIZE);
+            text.draw(window, 4, row_y + (ROW_H - text.height() as i32) / 2, color);
+
+            let fraction = match (&download.state, download.total) {
+                (&State::Finished, _) => 1.0,
+                (_, Some(total)) if total > 0 => (download.received as f32 / total as f32).min(1.0),
+                _ => 0.0
+            };
+            let bar_y = row_y + (ROW_H - BAR_H) / 2;
+            window.rect(bar_x, bar_y, BAR_W as u32, BAR_H as u32, Color::rgb(210, 210, 210));
+            let filled = (BAR_W as f32 * fraction) as i32;
+            if filled > 0 {
+                window.rect(bar_x, bar_y, filled as u32, BAR_H as u32, Color::rgb(0, 120, 215));
+            }
+
+            window.rect(button_x, row_y + 2, BUTTON_W as u32, (ROW_H - 4) as u32, Color::rgb(128, 128, 128));
+            window.rect(button_x + 1, row_y + 3, (BUTTON_W - 2) as u32, (ROW_H - 6) as u32, Color::rgb(240, 240, 240));
+            let button = font.render(download.action(), FONT_SIZE);
+            button.draw(window, button_x + (BUTTON_W - button.width() as i32) / 2, row_y + (ROW_H - button.height() as i32) / 2, Color::rgb(0, 0, 0));
+        }
+    }
+}
+
+/// A path in the downloads directory for a URL, named after the last segment of its path
+pub fn file_path(url: &Url) -> Result<PathBuf, String> {
+    let dir = match settings::get().downloads {
+        Some(ref dir) => dir.clone(),
+        None => return Err(format!("No downloads directory"))
+    };
+    fs::create_dir_all(&dir).map_err(|err| format!("Failed to create {}: {}", dir.display(), err))?;
+
+    let name = url.path_segments()
+        .and_then(|segments| segments.last())
+        .map(|segment| percent_decode(segment.as_bytes()).decode_utf8_lossy().into_owned())
+        .and_then(|name| if name.is_empty() || name.contains('/') { None } else { Some(name) })
+        .unwrap_or(String::from("download"));
+
+    // An existing file is never overwritten, the new one is numbered instead
+    let mut path = dir.join(&name);
+    let mut number = 1;
+    while path.exists() || part_path(&path).exists() {
+        path = dir.join(format!("{} ({})", name, number));
+        number += 1;
+    }
+    Ok(path)
+}
+
+/// Where the part of a download received so far is kept
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_os_string();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Where the validator of the response a part file came from is kept, so that resuming only appends
+/// to the part if the file has not changed since
+fn validator_path(path: &Path) -> PathBuf {
+    let mut validator = path.as_os_str().to_os_string();
+    validator.push(".part-validator");
+    PathBuf::from(validator)
+}
+
+/// What a response can be told apart from a changed file by, for `If-Range`: its strong ETag, or else
+/// the time it was last modified
+fn validator(headers: &Headers) -> Option<String> {
+    let raw = |name: &str| headers.get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| str::from_utf8(value).ok())
+        .map(|value| value.trim().to_string())
+        .and_then(|value| if value.is_empty() { None } else { Some(value) });
+
+    // A weak ETag is not allowed in If-Range
+    match raw("ETag") {
+        Some(etag) => if etag.starts_with("W/") {
+            raw("Last-Modified")
+        } else {
+            Some(etag)
+        },
+        None => raw("Last-Modified")
+    }
+}
+
+/// The first byte a partial response starts at, from its `Content-Range`
+fn range_start(headers: &Headers) -> Option<u64> {
+    match headers.get::<header::ContentRange>() {
+        Some(&header::ContentRange(ContentRangeSpec::Bytes { range: Some((start, _)), .. })) => Some(start),
+        _ => None
+    }
+}
+
+/// Open a finished download through the launcher, which lets the user choose an application for it
+fn open(path: &Path) {
+    if let Err(err) = Command::new(LAUNCH_COMMAND).arg(path).spawn() {
+        let _ = write!(stderr(), "* Failed to open {}: {}\n", path.display(), err);
+    }
+}
+
+/// Send the request for a download, following redirects, and asking only for what comes after the offset
+/// if the file still has the validator it had when the part before the offset arrived
+fn request(url: &Url, offset: u64, validator: Option<&str>) -> Result<Response, String> {
+    let mut url = url.clone();
+    let mut redirects = 0;
+    loop {
+        let mut headers = Headers::new();
+        cookies::request(&url, &mut headers);
+        auth::request(&url, &mut headers);
+        if offset > 0 {
+            headers.set(header::Range::Bytes(vec![ByteRangeSpec::AllFrom(offset)]));
+            if let Some(validator) = validator {
+                headers.set_raw("If-Range", vec![validator.as_bytes().to_vec()]);
+            }
+        }
+
+        let mut client = connection::client(&url);
+        client.set_redirect_policy(RedirectPolicy::FollowNone);
+        let res = client.get(url.clone()).headers(headers).send().map_err(|err| format!("{}", err))?;
+        cookies::response(&url, &res.headers);
+
+        let location = if res.status.is_redirection() {
+            res.headers.get::<header::Location>().and_then(|location| url.join(location).ok())
+        } else {
+            None
+        };
+        let next = match location {
+            Some(next) => next,
+            None => return Ok(res)
+        };
+        if next.scheme() != "http" && next.scheme() != "https" {
+            return Err(format!("Redirected to {}", next));
+        }
+
+        redirects += 1;
+        if redirects > MAX_REDIRECTS {
+            return Err(format!("Too many redirects from {}", url));
+        }
+        url = next;
+    }
+}
+
+/// Fetch a download into its part file from the offset on, and move the file into place once all of it is there.
+/// A stopped download returns early, leaving the part file for resuming.
+fn fetch(url: &Url, path: &Path, offset: u64, sender: &Sender<Update>, cancelled: &AtomicBool) -> Result<(), String> {
+    let part = part_path(path);
+
+    // Without a validator there is no telling whether the part still belongs to the file, so it is fetched again
+    let mut saved = String::new();
+    let has_validator = File::open(validator_path(path)).and_then(|mut file| file.read_to_string(&mut saved)).is_ok() && ! saved.trim().is_empty();
+    let offset = if has_validator { offset } else { 0 };
+
+    let mut res = request(url, offset, Some(saved.trim()))?;
+
+    // A part that does not start where the file left off cannot be appended
+    if offset > 0 && res.status == StatusCode::PartialContent && range_start(&res.headers) != Some(offset) {
+        let _ = write!(stderr(), "* Restarting download of {}, the server sent another range\n", url);
+        res = request(url, 0, None)?;
+    }
+
+    // A server that cannot send part of the file, or whose file has changed, sends all of it instead
+    let (file, mut received) = match res.status {
+        StatusCode::PartialContent if offset > 0 => (OpenOptions::new().append(true).open(&part), offset),
+        StatusCode::RangeNotSatisfiable => {
+            // The part is no use to this server, so resuming starts over
+            let _ = File::create(&part);
+            let _ = fs::remove_file(validator_path(path));
+            return Err(format!("The server cannot continue the download"));
+        },
+        status => if status.is_success() && status != StatusCode::PartialContent {
+            (File::create(&part), 0)
+        } else {
+            return Err(format!("{}", status));
+        }
+    };
+    let mut file = file.map_err(|err| format!("Failed to open {}: {}", part.display(), err))?;
+
+    // A new part is kept with the validator of the response it comes from
+    if received == 0 {
+        let result = match validator(&res.headers) {
+            Some(validator) => File::create(validator_path(path)).and_then(|mut file| file.write_all(validator.as_bytes())),
+            None => fs::remove_file(validator_path(path)).or(Ok(()))
+        };
+        if let Err(err) = result {
+            let _ = write!(stderr(), "* Failed to keep the validator of {}: {}\n", url, err);
+        }
+    }
+
+    let total = match res.headers.get::<header::ContentRange>() {
+        Some(&header::ContentRange(ContentRangeSpec::Bytes { instance_length: Some(length), .. })) => Some(length),
+        _ => res.headers.get::<header::ContentLength>().map(|length| received + length.0)
+    };
+    let _ = sender.send(Update::Progress(received, total));
+
+    let mut buf = [0; 16384];
+    loop {
+        if cancelled.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let count = res.read(&mut buf).map_err(|err| format!("Failed to read response: {}", err))?;
+        if count == 0 {
+            break;
+        }
+        file.write_all(&buf[..count]).map_err(|err| format!("Failed to write {}: {}", part.display(), err))?;
+        received += count as u64;
+        let _ = sender.send(Update::Progress(received, total));
+    }
+
+    // The connection can close before the whole file has arrived
+    if total.map_or(false, |total| received < total) {
+        return Err(format!("Connection closed early"));
+    }
+
+    fs::rename(&part, path).map_err(|err| format!("Failed to move {} into place: {}", part.display(), err))?;
+    let _ = fs::remove_file(validator_path(path));
+    let _ = write!(stderr(), "* Downloaded {} to {}\n", url, path.display());
+    let _ = sender.send(Update::Finished);
+    Ok(())
+}
+
+/// Write a download whose content has already arrived into its part file, and move the file into place
+fn store(url: &Url, path: &Path, data: &[u8], sender: &Sender<Update>) -> Result<(), String> {
+    let part = part_path(path);
+    let total = data.len() as u64;
+    let _ = sender.send(Update::Progress(0, Some(total)));
+
+    File::create(&part)
+        .and_then(|mut file| file.write_all(data))
+        .map_err(|err| format!("Failed to write {}: {}", part.display(), err))?;
+    let _ = sender.send(Update::Progress(total, Some(total)));
+
+    fs::rename(&part, path).map_err(|err| format!("Failed to move {} into place: {}", part.display(), err))?;
+    let _ = write!(stderr(), "* Downloaded {} to {}\n", url, path.display());
+    let _ = sender.send(Update::Finished);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use hyper::header::Headers;
+
+    use super::{part_path, range_start, validator, validator_path};
+
+    #[test]
+    fn part_paths() {
+        assert_eq!(part_path(Path::new("/home/user/Downloads/file.tar.gz")), PathBuf::from("/home/user/Downloads/file.tar.gz.part"));
+        assert_eq!(part_path(Path::new("/home/user/Downloads/download (1)")), PathBuf::from("/home/user/Downloads/download (1).part"));
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::new();
+        for &(name, value) in pairs.iter() {
+            headers.set_raw(name.to_string(), vec![value.as_bytes().to_vec()]);
+        }
+        headers
+    }
+
+    #[test]
+    fn validators() {
+        let last_modified = ("Last-Modified", "Wed, 21 Oct 2015 07:28:00 GMT");
+        assert_eq!(validator(&headers(&[("ETag", "\"v1\""), last_modified])), Some(String::from("\"v1\"")));
+        assert_eq!(validator(&headers(&[last_modified])), Some(String::from("Wed, 21 Oct 2015 07:28:00 GMT")));
+        assert_eq!(validator(&headers(&[])), None);
+        assert_eq!(validator(&headers(&[("ETag", " ")])), None);
+
+        // A weak ETag cannot be used in If-Range
+        assert_eq!(validator(&headers(&[("ETag", "W/\"v1\""), last_modified])), Some(String::from("Wed, 21 Oct 2015 07:28:00 GMT")));
+        assert_eq!(validator(&headers(&[("ETag", "W/\"v1\"")])), None);
+
+        assert_eq!(validator_path(Path::new("/tmp/file")), PathBuf::from("/tmp/file.part-validator"));
+    }
+
+    #[test]
+    fn range_starts() {
+        assert_eq!(range_start(&headers(&[("Content-Range", "bytes 100-199/200")])), Some(100));
+        assert_eq!(range_start(&headers(&[("Content-Range", "bytes 0-0/*")])), Some(0));
+
+        // A resumed download cannot continue without knowing where the response starts
+        assert_eq!(range_start(&headers(&[("Content-Range", "bytes */200")])), None);
+        assert_eq!(range_start(&headers(&[("Content-Range", "items 100-199/200")])), None);
+        assert_eq!(range_start(&headers(&[("Content-Range", "bytes 100-")])), None);
+        assert_eq!(range_start(&headers(&[])), None);
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+
+use hyper::status::StatusCode;
+use url::Url;
+
+use content::escape;
+
+/// Why a request got no response to show
+pub enum RequestError {
+    /// The server could not be reached, or the connection broke
+    Network(String),
+    /// The secure connection could not be set up
+    Tls(String),
+    /// Redirects went on for too long, or in a loop, from the given URL
+    Redirects(Url),
+    /// The response is a file, which is handed to the downloads manager rather than shown
+    Download,
+    Other(String),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RequestError::Network(ref message) => write!(f, "Connection failed: {}", message),
+            RequestError::Tls(ref message) => write!(f, "Secure connection failed: {}", message),
+            RequestError::Redirects(ref url) => write!(f, "Too many redirects from {}", url),
+            RequestError::Download => write!(f, "Saving to the downloads directory"),
+            RequestError::Other(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+fn page(title: &str, explanation: &str, detail: &str, url: &Url) -> String {
+    format!("<html><head><title>{}</title></head><body><h1>{}</h1><p>{}</p><p>{}</p><p><a href=\"{}\">Try again</a></p></body></html>",
+        escape(title), escape(title), escape(explanation), escape(detail), escape(url.as_str()))
+}
+
+/// The page shown instead of a response with a client or server error status
+pub fn status_page(url: &Url, status: StatusCode) -> String {
+    let title = match status.canonical_reason() {
+        Some(reason) => format!("{} {}", status.to_u16(), reason),
+        None => format!("Error {}", status.to_u16())
+    };
+
+    let explanation = match status {
+        StatusCode::NotFound => "The page was not found on the server. The address may be mistyped, or the page may have moved.",
+        StatusCode::Gone => "The page has been removed from the server.",
+        StatusCode::Forbidden | StatusCode::Unauthorized => "The server refuses to show this page.",
+        StatusCode::TooManyRequests | StatusCode::ServiceUnavailable => "The server is too busy to answer. Trying again later may help.",
+        _ => if status.is_server_error() {
+            "The server failed to answer the request. Trying again later may help."
+        } else {
+            "The server could not answer the request."
+        }
+    };
+
+    page(&title, explanation, url.as_str(), url)
+}
+
+/// Why a TLS handshake failed, going by the error rustls gave
+fn tls_problem(message: &str) -> (&'static str, &'static str) {
+    if message.contains("UnknownIssuer") {
+        ("Certificate not trusted", "The certificate of the server was not issued by a certificate authority the browser trusts. \
+            If the site uses a private certificate authority, its certificate can be added with a certificates line in the settings file.")
+    } else if message.contains("CertExpired") {
+        ("Certificate expired", "The certificate of the server has expired. If the clock of this computer is wrong, setting it right may help.")
+    } else if message.contains("CertNotValidYet") {
+        ("Certificate not yet valid", "The certificate of the server is not valid yet. If the clock of this computer is wrong, setting it right may help.")
+    } else if message.contains("CertNotValidForName") {
+        ("Certificate for another site", "The certificate of the server is for a different address, so the server may not be the site it claims to be.")
+    } else if message.contains("NoCertificatesPresented") {
+        ("Certificate missing", "The server did not present a certificate, so it could not be verified.")
+    } else if message.contains("AlertReceived") || message.contains("PeerIncompatible") {
+        ("Secure connection failed", "The server ended the handshake, possibly because it supports no protocol version or cipher suite the browser does.")
+    } else {
+        ("Secure connection failed", "A secure connection to the server could not be set up, so the page was not loaded.")
+    }
+}
+
+/// The page shown when a request failed without a response
+pub fn request_page(url: &Url, err: &RequestError) -> String {
+    let (title, explanation) = match *err {
+        RequestError::Network(_) => ("Problem loading page", "The server could not be reached. Check the address and the network connection."),
+        RequestError::Tls(ref message) => tls_problem(message),
+        RequestError::Redirects(_) => ("Redirect loop", "The server keeps redirecting the request in a way that will never finish."),
+        RequestError::Download => ("Downloading", "The file cannot be shown, so it is being saved to the downloads directory."),
+        RequestError::Other(_) => ("Problem loading page", "The page could not be loaded."),
+    };
+
+    page(title, explanation, &err.to_string(), url)
+}
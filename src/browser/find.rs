@@ -0,0 +1,205 @@
+use orbclient::{Color, Renderer, Window, K_BKSP, K_ENTER, K_ESC};
+use orbfont::Font;
+
+use super::Block;
+
+/// The height of the find bar along the bottom of the window
+pub const HEIGHT: i32 = 28;
+
+const FONT_SIZE: f32 = 14.0;
+
+/// The find bar, which searches the words of the page and highlights what it finds
+pub struct FindBar {
+    pub text: String,
+    pub open: bool,
+    /// Whether typing goes to the find bar rather than the page
+    pub editing: bool,
+    /// The first and last block of each match, in document order
+    matches: Vec<(usize, usize)>,
+    current: Option<usize>,
+}
+
+impl FindBar {
+    pub fn new() -> FindBar {
+        FindBar {
+            text: String::new(),
+            open: false,
+            editing: false,
+            matches: Vec::new(),
+            current: None,
+        }
+    }
+
+    pub fn open(&mut self) {
+        self.open = true;
+        self.editing = true;
+    }
+
+    pub fn close(&mut self) {
+        self.open = false;
+        self.editing = false;
+        self.matches.clear();
+        self.current = None;
+    }
+
+    /// Find the text in the words of the page, ignoring case, and keep the current match if it is still there
+    pub fn search(&mut self, blocks: &[Block]) {
+        let current = self.current.and_then(|i| self.matches.get(i).cloned());
+        self.matches.clear();
+        self.current = None;
+
+        let query = self.text.to_lowercase();
+        if query.is_empty() {
+            return;
+        }
+
+        // The words are joined by single spaces, so that a match may span several of them
+        let mut page = String::new();
+        let mut starts = Vec::new();
+        for (i, block) in blocks.iter().enumerate() {
+            if block.is_text() {
+                if ! page.is_empty() {
+                    page.push(' ');
+                }
+                starts.push((page.len(), i));
+                page.push_str(&block.string.to_lowercase());
+            }
+        }
+
+        let block_at = |position: usize| -> usize {
+            match starts.binary_search_by(|&(start, _)| start.cmp(&position)) {
+                Ok(found) => starts[found].1,
+                Err(after) => starts[after.saturating_sub(1)].1
+            }
+        };
+
+        let mut position = 0;
+        while let Some(found) = page[position..].find(&query) {
+            let start = position + found;
+            let end = start + query.len();
+            self.matches.push((block_at(start), block_at(end - 1)));
+            position = end;
+        }
+
+        if ! self.matches.is_empty() {
+            let index = current.and_then(|current| self.matches.iter().position(|&found| found.0 >= current.0)).unwrap_or(0);
+            self.current = Some(index);
+        }
+    }
+
+    /// Move to the next match, or the previous one, wrapping around, and return the block to scroll to
+    pub fn step(&mut self, backwards: bool) -> Option<usize> {
+        if self.matches.is_empty() {
+            return None;
+        }
+
+        let count = self.matches.len();
+        let index = match self.current {
+            Some(current) => if backwards {
+                (current + count - 1) % count
+            } else {
+                (current + 1) % count
+            },
+            None => 0
+        };
+        self.current = Some(index);
+        Some(self.matches[index].0)
+    }
+
+    /// The block of the current match
+    pub fn current(&self) -> Option<usize> {
+        self.current.map(|i| self.matches[i].0)
+    }
+
+    /// The highlight of a block that is part of a match, with the current match standing out
+    pub fn highlight(&self, index: usize) -> Option<Color> {
+        if ! self.open {
+            return None;
+        }
+
+        for (i, &(first, last)) in self.matches.iter().enumerate() {
+            if index >= first && index <= last {
+                return Some(if Some(i) == self.current {
+                    Color::rgb(255, 150, 50)
+                } else {
+                    Color::rgb(255, 255, 0)
+                });
+            } else if first > index {
+                break;
+            }
+        }
+        None
+    }
+
+    pub fn draw(&self, window: &mut Window, font: &Font) {
+        let width = window.width() as i32;
+        let y = window.height() as i32 - HEIGHT;
+        window.rect(0, y, width as u32, HEIGHT as u32, Color::rgb(221, 221, 221));
+        window.rect(0, y, width as u32, 1, Color::rgb(128, 128, 128));
+
+        let label = font.render("Find:", FONT_SIZE);
+        label.draw(window, 4, y + (HEIGHT - label.height() as i32) / 2, Color::rgb(0, 0, 0));
+
+        let field_x = 8 + label.width() as i32;
+        let field_w = width / 2 - field_x;
+        if field_w <= 8 {
+            return;
+        }
+
+        let frame = if self.editing {
+            Color::rgb(0, 0, 255)
+        } else {
+            Color::rgb(128, 128, 128)
+        };
+        window.rect(field_x, y + 4, field_w as u32, (HEIGHT - 8) as u32, frame);
+        window.rect(field_x + 1, y + 5, (field_w - 2) as u32, (HEIGHT - 10) as u32, Color::rgb(255, 255, 255));
+
+        // Text too long for the field scrolls so that its end stays visible
+        let mut start = 0;
+        while start < self.text.len() && font.render(&self.text[start..], FONT_SIZE).width() as i32 > field_w - 8 {
+            start += self.text[start..].chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+        }
+
+        let text = font.render(&self.text[start..], FONT_SIZE);
+        text.draw(window, field_x + 4, y + (HEIGHT - text.height() as i32) / 2, Color::rgb(0, 0, 0));
+
+        if self.editing {
+            window.rect(field_x + 4 + text.width() as i32, y + 7, 1, (HEIGHT - 14) as u32, Color::rgb(0, 0, 0));
+        }
+
+        let summary = if self.text.is_empty() {
+            String::new()
+        } else if let Some(current) = self.current {
+            format!("{} of {} matches", current + 1, self.matches.len())
+        } else {
+            String::from("No matches")
+        };
+        let summary = font.render(&summary, FONT_SIZE);
+        summary.draw(window, field_x + field_w + 8, y + (HEIGHT - summary.height() as i32) / 2, Color::rgb(0, 0, 0));
+    }
+
+    /// Handle a key press while editing, searching as the text changes, and return the block to scroll to
+    pub fn key(&mut self, blocks: &[Block], character: char, scancode: u8, shift: bool) -> Option<usize> {
+        match scancode {
+            K_ENTER => self.step(shift),
+            K_ESC => {
+                self.close();
+                None
+            },
+            K_BKSP => {
+                self.text.pop();
+                self.search(blocks);
+                self.current()
+            },
+            _ => {
+                if character != '\0' && ! character.is_control() {
+                    self.text.push(character);
+                    self.search(blocks);
+                    self.current()
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
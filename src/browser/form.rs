@@ -0,0 +1,652 @@
+use std::cmp;
+
+use html5ever::Attribute;
+use html5ever::rcdom::{Text, Element, Handle};
+use orbclient::{K_BKSP, K_DOWN, K_ENTER, K_SPACE, K_UP};
+use url::{form_urlencoded, Url};
+
+use layout::{BoxKind, LayoutBox};
+
+/// The form a control belongs to, and where it is submitted
+#[derive(Clone)]
+pub struct Form {
+    pub index: usize,
+    action: Url,
+    post: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControlKind {
+    Text,
+    Password,
+    Hidden,
+    Checkbox,
+    Radio,
+    Submit,
+    Reset,
+    Button,
+    Select,
+    TextArea,
+}
+
+/// An interactive form control, holding both its current and its initial state
+pub struct Control {
+    /// Numbers the controls of a document in tree order
+    pub index: usize,
+    pub kind: ControlKind,
+    pub form: Option<Form>,
+    pub name: String,
+    pub value: String,
+    default_value: String,
+    /// The text shown on a button
+    pub label: String,
+    pub checked: bool,
+    default_checked: bool,
+    /// The label and value of each option of a select
+    pub options: Vec<(String, String)>,
+    pub selected: usize,
+    default_selected: usize,
+    /// The width of a text control in characters
+    pub size: usize,
+    /// The height of a text area in lines
+    pub rows: usize,
+    pub disabled: bool,
+    pub focused: bool,
+}
+
+/// A navigation caused by submitting a form
+pub struct Submission {
+    pub url: Url,
+    /// The `application/x-www-form-urlencoded` body of a POST request
+    pub body: Option<String>,
+}
+
+/// What a click or key press on a control did
+pub enum Input {
+    Ignored,
+    Changed,
+    Submit(Submission),
+}
+
+/// Numbers the forms and controls of a document while its layout tree is built
+pub struct FormBuilder {
+    forms: usize,
+    controls: usize,
+    form: Option<Form>,
+}
+
+impl FormBuilder {
+    pub fn new() -> FormBuilder {
+        FormBuilder {
+            forms: 0,
+            controls: 0,
+            form: None,
+        }
+    }
+
+    /// Start a form element, returning the form it is nested in
+    pub fn begin(&mut self, url: &Url, attrs: &[Attribute]) -> Option<Form> {
+        let mut action = url.clone();
+        let mut post = false;
+        for attr in attrs.iter() {
+            match &*attr.name.local {
+                "action" => if ! attr.value.trim().is_empty() {
+                    if let Ok(action_url) = url.join(attr.value.trim()) {
+                        action = action_url;
+                    }
+                },
+                "method" => post = attr.value.trim().to_lowercase() == "post",
+                _ => ()
+            }
+        }
+
+        let form = Form {
+            index: self.forms,
+            action: action,
+            post: post,
+        };
+        self.forms += 1;
+
+        let previous = self.form.take();
+        self.form = Some(form);
+        previous
+    }
+
+    /// End a form element, returning to the form it was nested in
+    pub fn end(&mut self, previous: Option<Form>) {
+        self.form = previous;
+    }
+
+    /// Create the control for an `input`, `select`, `textarea` or `button` element
+    pub fn control(&mut self, tag: &str, attrs: &[Attribute], children: &[Handle]) -> Option<Control> {
+        let mut input_type = String::from("text");
+        let mut name = String::new();
+        let mut value_opt = None;
+        let mut alt_opt = None;
+        let mut checked = false;
+        let mut disabled = false;
+        let mut size = 20;
+        let mut rows = 2;
+        for attr in attrs.iter() {
+            match &*attr.name.local {
+                "type" => input_type = attr.value.trim().to_lowercase(),
+                "name" => name = attr.value.to_string(),
+                "value" => value_opt = Some(attr.value.to_string()),
+                "alt" => alt_opt = Some(attr.value.to_string()),
+                "checked" => checked = true,
+                "disabled" => disabled = true,
+                "size" | "cols" => if let Some(columns) = parse_size(&attr.value) {
+                    size = columns;
+                },
+                "rows" => if let Some(lines) = parse_size(&attr.value) {
+                    rows = lines;
+                },
+                _ => ()
+            }
+        }
+
+        let kind = match tag {
+            "textarea" => ControlKind::TextArea,
+            "select" => ControlKind::Select,
+            "button" => match &*input_type {
+                "reset" => ControlKind::Reset,
+                "button" => ControlKind::Button,
+                _ => ControlKind::Submit,
+            },
+            _ => match &*input_type {
+                "password" => ControlKind::Password,
+                "hidden" => ControlKind::Hidden,
+                "checkbox" => ControlKind::Checkbox,
+                "radio" => ControlKind::Radio,
+                "submit" | "image" => ControlKind::Submit,
+                "reset" => ControlKind::Reset,
+                "button" => ControlKind::Button,
+                // File uploads need multipart bodies, which are not supported
+                "file" => return None,
+                _ => ControlKind::Text,
+            }
+        };
+
+        let value = match kind {
+            ControlKind::TextArea => text_content(children).trim_left_matches('\n').to_string(),
+            _ => value_opt.clone().unwrap_or(String::new()),
+        };
+
+        let label = match kind {
+            ControlKind::Submit | ControlKind::Reset | ControlKind::Button => if tag == "button" {
+                text_content(children).split_whitespace().collect::<Vec<&str>>().join(" ")
+            } else {
+                match value_opt.or(alt_opt) {
+                    Some(label) => label,
+                    None => match kind {
+                        ControlKind::Reset => String::from("Reset"),
+                        ControlKind::Submit => String::from("Submit"),
+                        _ => String::new(),
+                    }
+                }
+            },
+            _ => String::new(),
+        };
+
+        let mut options = Vec::new();
+        let mut selected = 0;
+        if kind == ControlKind::Select {
+            select_options(children, &mut options, &mut selected);
+        }
+
+        let control = Control {
+            index: self.controls,
+            kind: kind,
+            form: self.form.clone(),
+            name: name,
+            default_value: value.clone(),
+            value: value,
+            label: label,
+            checked: checked,
+            default_checked: checked,
+            options: options,
+            selected: selected,
+            default_selected: selected,
+            size: size,
+            rows: rows,
+            disabled: disabled,
+            focused: false,
+        };
+        self.controls += 1;
+
+        Some(control)
+    }
+}
+
+/// Parse a size in characters or lines such as the `size` of an input, which is between 1 and 1000
+fn parse_size(value: &str) -> Option<usize> {
+    value.trim().parse::<usize>().ok().map(|size| cmp::min(cmp::max(size, 1), 1000))
+}
+
+fn text_content(children: &[Handle]) -> String {
+    let mut string = String::new();
+    for child in children.iter() {
+        let node = child.borrow();
+        match node.node {
+            Text(ref text) => string.push_str(text),
+            Element(..) => string.push_str(&text_content(&node.children)),
+            _ => ()
+        }
+    }
+    string
+}
+
+/// Collect the options of a select, including those inside option groups
+fn select_options(children: &[Handle], options: &mut Vec<(String, String)>, selected: &mut usize) {
+    for child in children.iter() {
+        let node = child.borrow();
+        if let Element(ref name, _, ref attrs) = node.node {
+            match &*name.local {
+                "option" => {
+                    let label = text_content(&node.children).split_whitespace().collect::<Vec<&str>>().join(" ");
+                    let mut value = label.clone();
+                    for attr in attrs.iter() {
+                        match &*attr.name.local {
+                            "value" => value = attr.value.to_string(),
+                            "selected" => *selected = options.len(),
+                            _ => ()
+                        }
+                    }
+                    options.push((label, value));
+                },
+                "optgroup" => select_options(&node.children, options, selected),
+                _ => ()
+            }
+        }
+    }
+}
+
+fn controls<'a>(layout_box: &'a LayoutBox, controls: &mut Vec<&'a Control>) {
+    if let BoxKind::Control(ref control) = layout_box.kind {
+        controls.push(control);
+    }
+    for child in layout_box.children.iter() {
+        self::controls(child, controls);
+    }
+}
+
+fn controls_mut<'a>(layout_box: &'a mut LayoutBox, controls: &mut Vec<&'a mut Control>) {
+    if let BoxKind::Control(ref mut control) = layout_box.kind {
+        controls.push(control);
+    }
+    for child in layout_box.children.iter_mut() {
+        controls_mut(child, controls);
+    }
+}
+
+/// Move the keyboard focus to a control, or away from every control
+pub fn focus(document: &mut LayoutBox, index: Option<usize>) {
+    let mut all = Vec::new();
+    controls_mut(document, &mut all);
+    for control in all.iter_mut() {
+        control.focused = Some(control.index) == index;
+    }
+}
+
+/// Whether the control can take the keyboard focus
+pub fn focusable(document: &LayoutBox, index: usize) -> bool {
+    let mut all = Vec::new();
+    controls(document, &mut all);
+    all.iter().any(|control| control.index == index && control.kind != ControlKind::Hidden && ! control.disabled)
+}
+
+/// Activate a control with the mouse, toggling it, choosing its next option, or submitting or resetting its form
+pub fn click(document: &mut LayoutBox, index: usize) -> Input {
+    let mut all = Vec::new();
+    controls_mut(document, &mut all);
+
+    let position = match all.iter().position(|control| control.index == index) {
+        Some(position) => position,
+        None => return Input::Ignored
+    };
+    if all[position].disabled {
+        return Input::Ignored;
+    }
+
+    match all[position].kind {
+        ControlKind::Checkbox => {
+            all[position].checked = ! all[position].checked;
+            Input::Changed
+        },
+        ControlKind::Radio => {
+            // Checking a radio button unchecks the others with its name in its form
+            let form = all[position].form.as_ref().map(|form| form.index);
+            let name = all[position].name.clone();
+            for control in all.iter_mut() {
+                if control.kind == ControlKind::Radio && control.name == name && control.form.as_ref().map(|form| form.index) == form {
+                    control.checked = control.index == index;
+                }
+            }
+            Input::Changed
+        },
+        ControlKind::Select => {
+            let control = &mut all[position];
+            if ! control.options.is_empty() {
+                control.selected = (control.selected + 1) % control.options.len();
+            }
+            Input::Changed
+        },
+        ControlKind::Submit => match all[position].form.clone() {
+            Some(form) => {
+                let shared: Vec<&Control> = all.iter().map(|control| &**control).collect();
+                Input::Submit(submit(&shared, &form, Some(index)))
+            },
+            None => Input::Ignored
+        },
+        ControlKind::Reset => match all[position].form.clone() {
+            Some(form) => {
+                for control in all.iter_mut() {
+                    if control.form.as_ref().map(|other| other.index) == Some(form.index) {
+                        control.value = control.default_value.clone();
+                        control.checked = control.default_checked;
+                        control.selected = control.default_selected;
+                    }
+                }
+                Input::Changed
+            },
+            None => Input::Ignored
+        },
+        _ => Input::Changed
+    }
+}
+
+/// Handle a key press in the focused control, editing its text or submitting its form on Enter
+pub fn key(document: &mut LayoutBox, index: usize, character: char, scancode: u8) -> Input {
+    let (kind, form) = {
+        let mut all = Vec::new();
+        controls(document, &mut all);
+        match all.iter().find(|control| control.index == index) {
+            Some(control) => if control.disabled {
+                return Input::Ignored;
+            } else {
+                (control.kind, control.form.clone())
+            },
+            None => return Input::Ignored
+        }
+    };
+
+    match kind {
+        ControlKind::Submit | ControlKind::Reset | ControlKind::Button | ControlKind::Checkbox | ControlKind::Radio => match scancode {
+            K_ENTER => if kind == ControlKind::Checkbox || kind == ControlKind::Radio {
+                implicit_submit(document, form)
+            } else {
+                click(document, index)
+            },
+            K_SPACE => click(document, index),
+            _ => Input::Ignored
+        },
+        ControlKind::Select => match scancode {
+            K_ENTER => implicit_submit(document, form),
+            K_UP | K_DOWN => {
+                let mut all = Vec::new();
+                controls_mut(document, &mut all);
+                if let Some(control) = all.iter_mut().find(|control| control.index == index) {
+                    if scancode == K_UP && control.selected > 0 {
+                        control.selected -= 1;
+                    } else if scancode == K_DOWN && control.selected + 1 < control.options.len() {
+                        control.selected += 1;
+                    }
+                }
+                Input::Changed
+            },
+            _ => Input::Ignored
+        },
+        ControlKind::Text | ControlKind::Password | ControlKind::TextArea => {
+            if scancode == K_ENTER && kind != ControlKind::TextArea {
+                return implicit_submit(document, form);
+            }
+
+            let mut all = Vec::new();
+            controls_mut(document, &mut all);
+            match all.iter_mut().find(|control| control.index == index) {
+                Some(control) => if scancode == K_BKSP {
+                    control.value.pop();
+                    Input::Changed
+                } else if scancode == K_ENTER {
+                    control.value.push('\n');
+                    Input::Changed
+                } else if character != '\0' && ! character.is_control() {
+                    control.value.push(character);
+                    Input::Changed
+                } else {
+                    Input::Ignored
+                },
+                None => Input::Ignored
+            }
+        },
+        ControlKind::Hidden => Input::Ignored
+    }
+}
+
+/// Submit a form from one of its fields, as if the user had pressed Enter in it
+fn implicit_submit(document: &mut LayoutBox, form: Option<Form>) -> Input {
+    match form {
+        Some(form) => {
+            let mut all = Vec::new();
+            controls(document, &mut all);
+            Input::Submit(submit(&all, &form, None))
+        },
+        None => Input::Ignored
+    }
+}
+
+/// Encode the successful controls of a form, including the button that submitted it, as a query string or POST body
+fn submit(all: &[&Control], form: &Form, submitter: Option<usize>) -> Submission {
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    for control in all.iter() {
+        let in_form = control.form.as_ref().map(|other| other.index) == Some(form.index);
+        if ! in_form || control.disabled || control.name.is_empty() {
+            continue;
+        }
+
+        match control.kind {
+            ControlKind::Text | ControlKind::Password | ControlKind::Hidden => {
+                serializer.append_pair(&control.name, &control.value);
+            },
+            ControlKind::TextArea => {
+                serializer.append_pair(&control.name, &control.value.replace("\n", "\r\n"));
+            },
+            ControlKind::Checkbox | ControlKind::Radio => if control.checked {
+                let value = if control.value.is_empty() { "on" } else { &control.value };
+                serializer.append_pair(&control.name, value);
+            },
+            ControlKind::Select => if let Some(&(_, ref value)) = control.options.get(control.selected) {
+                serializer.append_pair(&control.name, value);
+            },
+            ControlKind::Submit => if submitter == Some(control.index) {
+                serializer.append_pair(&control.name, &control.value);
+            },
+            ControlKind::Reset | ControlKind::Button => ()
+        }
+    }
+    let query = serializer.finish();
+
+    if form.post {
+        Submission {
+            url: form.action.clone(),
+            body: Some(query),
+        }
+    } else {
+        let mut url = form.action.clone();
+        url.set_query(Some(&query));
+        url.set_fragment(None);
+        Submission {
+            url: url,
+            body: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use orbclient::{K_BKSP, K_ENTER};
+    use url::Url;
+
+    use css::Style;
+    use layout::{BoxKind, LayoutBox};
+    use super::{click, key, parse_size, Control, ControlKind, Form, Input, Submission};
+
+    fn form(index: usize, action: &str, post: bool) -> Form {
+        Form {
+            index: index,
+            action: Url::parse(action).unwrap(),
+            post: post,
+        }
+    }
+
+    fn control(index: usize, kind: ControlKind, form: &Form, name: &str, value: &str) -> Control {
+        Control {
+            index: index,
+            kind: kind,
+            form: Some(form.clone()),
+            name: name.to_string(),
+            value: value.to_string(),
+            default_value: value.to_string(),
+            label: String::new(),
+            checked: false,
+            default_checked: false,
+            options: Vec::new(),
+            selected: 0,
+            default_selected: 0,
+            size: 20,
+            rows: 2,
+            disabled: false,
+            focused: false,
+        }
+    }
+
+    fn document(controls: Vec<Control>) -> LayoutBox {
+        let mut document = LayoutBox::new(BoxKind::Block, Style::default());
+        for control in controls {
+            document.children.push(LayoutBox::new(BoxKind::Control(control), Style::default()));
+        }
+        document
+    }
+
+    fn submitted(input: Input) -> Submission {
+        match input {
+            Input::Submit(submission) => submission,
+            _ => panic!("expected the form to be submitted")
+        }
+    }
+
+    /// The controls of a document, in order
+    fn controls(document: &LayoutBox) -> Vec<&Control> {
+        document.children.iter().map(|child| match child.kind {
+            BoxKind::Control(ref control) => control,
+            _ => panic!("expected a control")
+        }).collect()
+    }
+
+    #[test]
+    fn get_encoding() {
+        let search = form(0, "http://example.com/search?old=1#top", false);
+        let mut document = document(vec![
+            control(0, ControlKind::Text, &search, "q", "a b&c=d/\u{E9}"),
+            control(1, ControlKind::Hidden, &search, "lang", "en"),
+            control(2, ControlKind::Password, &search, "pw", "+%"),
+            control(3, ControlKind::Submit, &search, "go", "Go"),
+            control(4, ControlKind::Submit, &search, "other", "Other"),
+        ]);
+
+        // The query replaces the one of the action, and the fragment is dropped
+        let submission = submitted(click(&mut document, 3));
+        assert_eq!(submission.url.as_str(), "http://example.com/search?q=a+b%26c%3Dd%2F%C3%A9&lang=en&pw=%2B%25&go=Go");
+        assert_eq!(submission.body, None);
+    }
+
+    #[test]
+    fn post_encoding() {
+        let comment = form(0, "http://example.com/comment?id=7", true);
+        let mut document = document(vec![
+            control(0, ControlKind::TextArea, &comment, "text", "two\nlines"),
+            control(1, ControlKind::Submit, &comment, "", "Send"),
+        ]);
+
+        // Line breaks are sent as CRLF, and a button without a name sends nothing
+        let submission = submitted(click(&mut document, 1));
+        assert_eq!(submission.url.as_str(), "http://example.com/comment?id=7");
+        assert_eq!(submission.body, Some("text=two%0D%0Alines".to_string()));
+    }
+
+    #[test]
+    fn successful_controls() {
+        let main = form(0, "http://example.com/", false);
+        let other = form(1, "http://example.com/other", false);
+
+        let mut checked = control(0, ControlKind::Checkbox, &main, "agree", "");
+        checked.checked = true;
+        let unchecked = control(1, ControlKind::Checkbox, &main, "news", "yes");
+        let mut disabled = control(2, ControlKind::Text, &main, "disabled", "x");
+        disabled.disabled = true;
+        let unnamed = control(3, ControlKind::Text, &main, "", "x");
+        let elsewhere = control(4, ControlKind::Text, &other, "elsewhere", "x");
+        let mut select = control(5, ControlKind::Select, &main, "size", "");
+        select.options = vec![(String::from("Small"), String::from("s")), (String::from("Large"), String::from("l"))];
+        select.selected = 1;
+        let empty_select = control(6, ControlKind::Select, &main, "empty", "");
+        let reset = control(7, ControlKind::Reset, &main, "reset", "Reset");
+        let button = control(8, ControlKind::Button, &main, "button", "Button");
+        let mut radio = control(9, ControlKind::Radio, &main, "color", "red");
+        radio.checked = true;
+        let text = control(10, ControlKind::Text, &main, "q", "");
+
+        let mut document = document(vec![checked, unchecked, disabled, unnamed, elsewhere, select, empty_select, reset, button, radio, text]);
+
+        // Pressing Enter in a text field submits without any button
+        let submission = submitted(key(&mut document, 10, '\n', K_ENTER));
+        assert_eq!(submission.url.query(), Some("agree=on&size=l&color=red&q="));
+    }
+
+    #[test]
+    fn click_and_type() {
+        let main = form(0, "http://example.com/", false);
+        let other = form(1, "http://example.com/other", false);
+
+        let mut red = control(0, ControlKind::Radio, &main, "color", "red");
+        red.checked = true;
+        red.default_checked = true;
+        let blue = control(1, ControlKind::Radio, &main, "color", "blue");
+        let mut elsewhere = control(2, ControlKind::Radio, &other, "color", "green");
+        elsewhere.checked = true;
+        let mut disabled = control(3, ControlKind::Checkbox, &main, "disabled", "");
+        disabled.disabled = true;
+        let text = control(4, ControlKind::Text, &main, "q", "ab");
+        let reset = control(5, ControlKind::Reset, &main, "", "");
+        let mut document = document(vec![red, blue, elsewhere, disabled, text, reset]);
+
+        // Checking a radio button unchecks the others with its name in the same form only
+        click(&mut document, 1);
+        assert_eq!(controls(&document).iter().map(|control| control.checked).collect::<Vec<bool>>(), vec![false, true, true, false, false, false]);
+
+        click(&mut document, 3);
+        assert!(! controls(&document)[3].checked);
+
+        key(&mut document, 4, 'c', 0);
+        key(&mut document, 4, '\u{7}', 0);
+        assert_eq!(controls(&document)[4].value, "abc");
+        key(&mut document, 4, '\0', K_BKSP);
+        key(&mut document, 4, '\0', K_BKSP);
+        assert_eq!(controls(&document)[4].value, "a");
+
+        // Resetting restores the values the page gave
+        click(&mut document, 5);
+        assert!(controls(&document)[0].checked && ! controls(&document)[1].checked);
+        assert_eq!(controls(&document)[4].value, "ab");
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size(" 30 "), Some(30));
+        assert_eq!(parse_size("0"), Some(1));
+        assert_eq!(parse_size("4294967296"), Some(1000));
+        assert_eq!(parse_size("18446744073709551616"), None);
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_size("wide"), None);
+    }
+}
@@ -0,0 +1,471 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{stdout, Write};
+use std::thread;
+use std::time::Duration;
+
+use orbclient::Color;
+use orbimage::Image;
+use url::Url;
+
+use css::Style;
+use layout::{self, BoxKind, LayoutBox};
+use loader::{Load, Message};
+use super::{Block, Fonts};
+
+/// What the headless mode writes out
+enum Output {
+    Png(String),
+    Text,
+    Json,
+}
+
+/// The options of the headless mode, parsed from the command line
+struct Options {
+    output: Output,
+    width: u32,
+    height: u32,
+    zoom: f32,
+    url: Url,
+}
+
+pub const USAGE: &'static str = "Usage: browser [URL]
+       browser --png FILE [--size WIDTHxHEIGHT] [--zoom FACTOR] URL
+       browser --dump [--size WIDTHxHEIGHT] [--zoom FACTOR] URL
+       browser --json [--size WIDTHxHEIGHT] [--zoom FACTOR] URL
+
+Without a display, --png renders the page to a PNG image and --dump and --json print
+its blocks. The page is laid out at the size given, 800x600 by default, and zoomed
+by the factor given, 1 by default. The URL may be a path to a local file.";
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut output = None;
+    let mut size = (800, 600);
+    let mut zoom = 1.0;
+    let mut url = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--png" => match args.next() {
+                Some(path) => output = Some(Output::Png(path.clone())),
+                None => return Err(format!("--png needs a file name"))
+            },
+            "--dump" => output = Some(Output::Text),
+            "--json" => output = Some(Output::Json),
+            "--size" => {
+                let value = args.next().map(|value| value.as_str()).unwrap_or("");
+                let mut parts = value.split('x').map(|part| part.parse::<u32>());
+                size = match (parts.next(), parts.next()) {
+                    (Some(Ok(width)), Some(Ok(height))) if width > 0 && height > 0 => (width, height),
+                    _ => return Err(format!("Invalid size '{}', expected WIDTHxHEIGHT", value))
+                };
+            },
+            "--zoom" => {
+                let value = args.next().map(|value| value.as_str()).unwrap_or("");
+                zoom = match value.parse::<f32>() {
+                    Ok(factor) if factor > 0.0 => factor,
+                    _ => return Err(format!("Invalid zoom '{}', expected a factor such as 1.5", value))
+                };
+            },
+            _ => if arg.starts_with("--") {
+                return Err(format!("Unknown option {}", arg));
+            } else {
+                url = Some(parse_url(arg)?);
+            }
+        }
+    }
+
+    match (output, url) {
+        (Some(output), Some(url)) => Ok(Options {
+            output: output,
+            width: size.0,
+            height: size.1,
+            zoom: zoom,
+            url: url,
+        }),
+        (_, None) => Err(format!("No URL given")),
+        (None, _) => Err(format!("No output given"))
+    }
+}
+
+/// A URL, or a path to a local file
+fn parse_url(arg: &str) -> Result<Url, String> {
+    if let Ok(url) = Url::parse(arg) {
+        return Ok(url);
+    }
+
+    let path = env::current_dir().map_err(|err| format!("{}", err))?.join(arg);
+    Url::from_file_path(&path).map_err(|_| format!("Invalid URL or path: {}", arg))
+}
+
+/// Load a page with all of its images, waiting until the load is done
+fn load(url: Url) -> LayoutBox {
+    let load = Load::start(url, None, false);
+    let mut document = LayoutBox::new(BoxKind::Block, Style::default());
+    loop {
+        for message in load.messages() {
+            match message {
+                Message::Document(loaded, _) => document = loaded,
+                Message::Image(image_url, image) => {
+                    document.set_image(&image_url, &image);
+                },
+                Message::Done => return document,
+                Message::Progress(_) | Message::Redirect(_) | Message::Icon(_) | Message::Download(_, _) => ()
+            }
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Render a page, or list its blocks, without opening a window, so that pages can be compared with snapshots
+pub fn main(args: &[String], fonts: &Fonts) -> Result<(), String> {
+    let options = parse_options(args)?;
+
+    let document = load(options.url);
+    let mut anchors = BTreeMap::new();
+    let mut blocks = Vec::new();
+    layout::layout(&document, options.width as i32, fonts, options.zoom, &mut anchors, &mut blocks);
+
+    match options.output {
+        Output::Png(path) => {
+            let mut image = Image::from_color(options.width, options.height, Color::rgb(255, 255, 255));
+            for block in blocks.iter() {
+                block.draw(&mut image, (0, 0), None);
+            }
+
+            File::create(&path)
+                .and_then(|mut file| file.write_all(&png(&image)))
+                .map_err(|err| format!("Failed to write {}: {}", path, err))
+        },
+        Output::Text => stdout().write_all(dump(&blocks).as_bytes()).map_err(|err| format!("{}", err)),
+        Output::Json => {
+            let mut json = String::from("[\n");
+            for (i, block) in blocks.iter().enumerate() {
+                json.push_str(&format!("  {{\"x\": {}, \"y\": {}, \"w\": {}, \"h\": {}, \"kind\": \"{}\", \"color\": \"#{:06x}\", \"string\": {}, \"link\": {}}}",
+                    block.x, block.y, block.w, block.h, kind(block), block.color.data & 0xFFFFFF,
+                    json_string(&block.string),
+                    block.link.as_ref().map_or(String::from("null"), |link| json_string(link))
+                ));
+                if i + 1 < blocks.len() {
+                    json.push(',');
+                }
+                json.push('\n');
+            }
+            json.push_str("]\n");
+            stdout().write_all(json.as_bytes()).map_err(|err| format!("{}", err))
+        }
+    }
+}
+
+/// List the blocks one per line, as their position, size and kind, with their text and link if they have them
+fn dump(blocks: &[Block]) -> String {
+    let mut text = String::new();
+    for block in blocks.iter() {
+        text.push_str(&format!("{} {} {} {} {}", block.x, block.y, block.w, block.h, kind(block)));
+        if ! block.string.is_empty() {
+            text.push_str(&format!(" {:?}", block.string));
+        }
+        if let Some(ref link) = block.link {
+            text.push_str(&format!(" -> {}", link));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn kind(block: &Block) -> &'static str {
+    if block.control.is_some() {
+        "control"
+    } else if block.text.is_some() {
+        "text"
+    } else if block.image.is_some() {
+        "image"
+    } else {
+        "rect"
+    }
+}
+
+fn json_string(string: &str) -> String {
+    let mut json = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\t' => json.push_str("\\t"),
+            _ if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => json.push(c)
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data.iter() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    ! crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.push((value >> 24) as u8);
+    data.push((value >> 16) as u8);
+    data.push((value >> 8) as u8);
+    data.push(value as u8);
+}
+
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    push_u32(png, data.len() as u32);
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    push_u32(png, crc);
+}
+
+/// Encode an image as a PNG, in uncompressed deflate blocks, which needs no compression library
+fn png(image: &Image) -> Vec<u8> {
+    let width = image.width() as usize;
+
+    // Every row starts with the filter type, which is always none
+    let mut raw = Vec::with_capacity((width * 4 + 1) * image.height() as usize);
+    for row in image.data().chunks(cmp::max(1, width)) {
+        raw.push(0);
+        for color in row.iter() {
+            raw.push(color.r());
+            raw.push(color.g());
+            raw.push(color.b());
+            raw.push(color.a());
+        }
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(65535).peekable();
+    while let Some(block) = blocks.next() {
+        zlib.push(if blocks.peek().is_none() { 1 } else { 0 });
+        let len = block.len() as u16;
+        zlib.push(len as u8);
+        zlib.push((len >> 8) as u8);
+        zlib.push(! len as u8);
+        zlib.push((! len >> 8) as u8);
+        zlib.extend_from_slice(block);
+    }
+    if raw.is_empty() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+
+    push_u32(&mut zlib, adler32(&raw));
+
+    let mut header = Vec::new();
+    push_u32(&mut header, image.width());
+    push_u32(&mut header, image.height());
+    // 8 bits per channel, RGBA, and the default compression, filter and interlace methods
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+    push_chunk(&mut png, b"IHDR", &header);
+    push_chunk(&mut png, b"IDAT", &zlib);
+    push_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+#[cfg(test)]
+mod tests {
+    use orbclient::Color;
+    use orbimage::Image;
+
+    use settings;
+    use super::{adler32, crc32, dump, load, parse_options, png, Output};
+    use super::super::Fonts;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn be32(data: &[u8], i: usize) -> u32 {
+        (data[i] as u32) << 24 | (data[i + 1] as u32) << 16 | (data[i + 2] as u32) << 8 | data[i + 3] as u32
+    }
+
+    /// Read a PNG written by `png` back into its size and raw rows, checking every checksum on the way
+    fn decode_png(data: &[u8]) -> (u32, u32, Vec<u8>) {
+        assert_eq!(&data[..8], b"\x89PNG\r\n\x1A\n");
+
+        let mut size = (0, 0);
+        let mut zlib = Vec::new();
+        let mut i = 8;
+        let mut ended = false;
+        while i < data.len() {
+            let len = be32(data, i) as usize;
+            let kind = &data[i + 4 .. i + 8];
+            let body = &data[i + 8 .. i + 8 + len];
+            assert_eq!(be32(data, i + 8 + len), crc32(&data[i + 4 .. i + 8 + len]));
+            match kind {
+                b"IHDR" => {
+                    size = (be32(body, 0), be32(body, 4));
+                    assert_eq!(&body[8..], &[8, 6, 0, 0, 0]);
+                },
+                b"IDAT" => zlib.extend_from_slice(body),
+                b"IEND" => ended = true,
+                _ => panic!("unexpected chunk")
+            }
+            i += 12 + len;
+        }
+        assert!(ended);
+
+        // Only stored deflate blocks are written, each with its length and the complement of it
+        assert_eq!(&zlib[..2], &[0x78, 0x01]);
+        assert_eq!(((zlib[0] as u32) << 8 | zlib[1] as u32) % 31, 0);
+        let mut raw = Vec::new();
+        let mut i = 2;
+        loop {
+            let last = zlib[i] & 1 == 1;
+            assert_eq!(zlib[i] >> 1, 0);
+            let len = zlib[i + 1] as usize | (zlib[i + 2] as usize) << 8;
+            let nlen = zlib[i + 3] as usize | (zlib[i + 4] as usize) << 8;
+            assert_eq!(len ^ 0xFFFF, nlen);
+            raw.extend_from_slice(&zlib[i + 5 .. i + 5 + len]);
+            i += 5 + len;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(be32(&zlib, i), adler32(&raw));
+        assert_eq!(i + 4, zlib.len());
+
+        (size.0, size.1, raw)
+    }
+
+    #[test]
+    fn crc32_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+    }
+
+    #[test]
+    fn adler32_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+        // Long enough for the sums to wrap around the modulus
+        assert_eq!(adler32(&[0xFF; 6000]), 0xA49759EA);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let pixels = vec![
+            Color::rgba(255, 0, 0, 255), Color::rgba(0, 255, 0, 128),
+            Color::rgba(0, 0, 255, 0), Color::rgba(1, 2, 3, 4),
+        ];
+        let image = Image::from_data(2, 2, pixels.into_boxed_slice()).unwrap();
+
+        let (width, height, raw) = decode_png(&png(&image));
+        assert_eq!((width, height), (2, 2));
+        assert_eq!(raw, vec![
+            0, 255, 0, 0, 255, 0, 255, 0, 128,
+            0, 0, 0, 255, 0, 1, 2, 3, 4,
+        ]);
+    }
+
+    #[test]
+    fn png_splits_large_images_into_blocks() {
+        // 200 rows of 801 bytes need two stored blocks
+        let image = Image::from_data(200, 100, vec![Color::rgb(10, 20, 30); 200 * 100].into_boxed_slice()).unwrap();
+        let (width, height, raw) = decode_png(&png(&image));
+        assert_eq!((width, height), (200, 100));
+        assert_eq!(raw.len(), 801 * 100);
+        assert!(raw.chunks(801).all(|row| row[0] == 0 && row[1..].chunks(4).all(|pixel| pixel == &[10, 20, 30, 255])));
+    }
+
+    #[test]
+    fn png_of_empty_image() {
+        let image = Image::from_data(0, 0, Vec::new().into_boxed_slice()).unwrap();
+        let (width, height, raw) = decode_png(&png(&image));
+        assert_eq!((width, height), (0, 0));
+        assert!(raw.is_empty());
+    }
+
+    #[test]
+    fn options() {
+        let options = parse_options(&args(&["--png", "out.png", "--size", "640x480", "--zoom", "1.5", "http://example.com/"])).unwrap();
+        match options.output {
+            Output::Png(ref path) => assert_eq!(path, "out.png"),
+            _ => panic!("expected PNG output")
+        }
+        assert_eq!((options.width, options.height), (640, 480));
+        assert_eq!(options.zoom, 1.5);
+        assert_eq!(options.url.as_str(), "http://example.com/");
+
+        let options = parse_options(&args(&["--dump", "http://example.com/"])).unwrap();
+        assert!(match options.output { Output::Text => true, _ => false });
+        assert_eq!((options.width, options.height, options.zoom), (800, 600, 1.0));
+
+        // A path is taken as a local file
+        let options = parse_options(&args(&["--json", "page.html"])).unwrap();
+        assert_eq!(options.url.scheme(), "file");
+        assert!(options.url.path().ends_with("/page.html"));
+    }
+
+    #[test]
+    fn invalid_options() {
+        assert!(parse_options(&args(&["--png"])).is_err());
+        assert!(parse_options(&args(&["--dump"])).is_err());
+        assert!(parse_options(&args(&["http://example.com/"])).is_err());
+        assert!(parse_options(&args(&["--dump", "--verbose", "http://example.com/"])).is_err());
+        for size in ["", "640", "640x", "x480", "0x480", "640x0", "-1x480", "axb"].iter() {
+            assert!(parse_options(&args(&["--dump", "--size", size, "http://example.com/"])).is_err(), "size {:?}", size);
+        }
+        for zoom in ["", "0", "-1", "big"].iter() {
+            assert!(parse_options(&args(&["--dump", "--zoom", zoom, "http://example.com/"])).is_err(), "zoom {:?}", zoom);
+        }
+    }
+
+    #[test]
+    fn dump_snapshot() {
+        // Positions depend on the fonts installed, so the snapshot is of the words, their links and their order on the line
+        let fonts = match Fonts::find(settings::get()) {
+            Ok(fonts) => fonts,
+            Err(err) => {
+                println!("skipping, no fonts: {}", err);
+                return;
+            }
+        };
+
+        let url = "data:text/html,<p>Hello <a href=\"http://example.com/next\">next page</a></p>".parse().unwrap();
+        let document = load(url);
+        let mut anchors = ::std::collections::BTreeMap::new();
+        let mut blocks = Vec::new();
+        ::layout::layout(&document, 800, &fonts, 1.0, &mut anchors, &mut blocks);
+
+        let dump = dump(&blocks);
+        let words: Vec<(i32, i32, String)> = dump.lines()
+            .map(|line| line.splitn(5, ' ').collect::<Vec<&str>>())
+            .filter(|fields| fields[4].starts_with("text "))
+            .map(|fields| (fields[0].parse().unwrap(), fields[1].parse().unwrap(), fields[4]["text ".len()..].to_string()))
+            .collect();
+
+        let strings: Vec<&str> = words.iter().map(|&(_, _, ref rest)| rest.as_str()).collect();
+        assert_eq!(strings, vec![
+            "\"Hello\"",
+            "\"next\" -> http://example.com/next",
+            "\"page\" -> http://example.com/next",
+        ]);
+        assert!(words.windows(2).all(|pair| pair[0].1 == pair[1].1 && pair[0].0 < pair[1].0));
+    }
+}
@@ -0,0 +1,286 @@
+use std::fs;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{stderr, Read, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use url::Url;
+
+use bookmarks::Bookmarks;
+use content::escape;
+use settings;
+
+/// How many pages the address field suggests at most
+const MAX_SUGGESTIONS: usize = 8;
+
+/// How many lines the history file may grow to before it is compacted to one line per page
+const MAX_LINES: usize = 10000;
+
+/// How many pages the history page lists at most
+const MAX_PAGE_VISITS: usize = 500;
+
+/// A page that has been visited, with the time and title of the last visit
+pub struct Visit {
+    pub url: String,
+    pub title: String,
+    pub time: u64,
+    pub count: usize,
+}
+
+/// A page the address field suggests for what has been typed
+pub struct Suggestion {
+    pub url: String,
+    pub title: String,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+fn history_path() -> Option<PathBuf> {
+    settings::config_dir().map(|dir| dir.join("history"))
+}
+
+/// Every page visited, saved across sessions
+pub struct History {
+    visits: Vec<Visit>,
+}
+
+impl History {
+    /// Load the visits from the history file, keeping the latest visit to each page
+    pub fn load() -> History {
+        let mut data = String::new();
+        if let Some(path) = history_path() {
+            if let Ok(mut file) = File::open(&path) {
+                let _ = file.read_to_string(&mut data);
+            }
+        }
+
+        // Each line is the time, the URL and the title of a visit, separated by tabs
+        let mut lines = 0;
+        let mut visits: Vec<Visit> = Vec::new();
+        let mut indexes = BTreeMap::new();
+        for line in data.lines() {
+            lines += 1;
+
+            let fields: Vec<&str> = line.splitn(3, '\t').collect();
+            if fields.len() < 3 {
+                continue;
+            }
+            let time = match fields[0].parse::<u64>() {
+                Ok(time) => time,
+                Err(_) => continue
+            };
+
+            if let Some(&index) = indexes.get(fields[1]) {
+                let visit: &mut Visit = &mut visits[index];
+                visit.time = time;
+                visit.title = fields[2].to_string();
+                visit.count += 1;
+                continue;
+            }
+
+            indexes.insert(fields[1].to_string(), visits.len());
+            visits.push(Visit {
+                url: fields[1].to_string(),
+                title: fields[2].to_string(),
+                time: time,
+                count: 1,
+            });
+        }
+
+        let history = History {
+            visits: visits,
+        };
+        if lines > MAX_LINES {
+            history.save();
+        }
+        history
+    }
+
+    /// Rewrite the history file with one line for each page
+    fn save(&self) {
+        let path = match history_path() {
+            Some(path) => path,
+            None => return
+        };
+
+        let mut data = String::new();
+        for visit in self.visits.iter() {
+            data.push_str(&format!("{}\t{}\t{}\n", visit.time, visit.url, visit.title));
+        }
+
+        let result = File::create(&path).and_then(|mut file| file.write_all(data.as_bytes()));
+        if let Err(err) = result {
+            let _ = write!(stderr(), "* Failed to save history to {}: {}\n", path.display(), err);
+        }
+    }
+
+    /// Record a visit to a page, appending it to the history file
+    pub fn visit(&mut self, url: &Url, title: &str) {
+        // Pages generated by the browser itself are not remembered
+        if url.scheme() != "http" && url.scheme() != "https" && url.scheme() != "file" {
+            return;
+        }
+
+        let time = now();
+        let title = title.replace('\t', " ").replace('\n', " ");
+        let position = self.visits.iter().position(|visit| visit.url == url.as_str());
+        match position {
+            Some(index) => {
+                let visit = &mut self.visits[index];
+                visit.time = time;
+                visit.title = title.clone();
+                visit.count += 1;
+            },
+            None => self.visits.push(Visit {
+                url: url.to_string(),
+                title: title.clone(),
+                time: time,
+                count: 1,
+            })
+        }
+
+        let path = match history_path() {
+            Some(path) => path,
+            None => return
+        };
+        let result = path.parent().map_or(Ok(()), |parent| fs::create_dir_all(parent))
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(&path))
+            .and_then(|mut file| file.write_all(format!("{}\t{}\t{}\n", time, url, title).as_bytes()));
+        if let Err(err) = result {
+            let _ = write!(stderr(), "* Failed to save history to {}: {}\n", path.display(), err);
+        }
+    }
+
+    /// Suggest bookmarks and visited pages for a typed address, best first
+    pub fn complete(&self, bookmarks: &Bookmarks, text: &str) -> Vec<Suggestion> {
+        let query = text.trim().to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        // Pages whose address starts with the text come before those that only contain it,
+        // bookmarks come before other pages, and then pages visited more often and more recently
+        let mut scored = Vec::new();
+        for bookmark in bookmarks.iter() {
+            if let Some(prefix) = matches(&query, &bookmark.url, &bookmark.title) {
+                let count = self.visits.iter().find(|visit| visit.url == bookmark.url).map_or(0, |visit| visit.count);
+                scored.push(((prefix, true, count, 0), bookmark.url.clone(), bookmark.title.clone()));
+            }
+        }
+        for visit in self.visits.iter() {
+            if scored.iter().any(|&(_, ref url, _)| *url == visit.url) {
+                continue;
+            }
+            if let Some(prefix) = matches(&query, &visit.url, &visit.title) {
+                scored.push(((prefix, false, visit.count, visit.time), visit.url.clone(), visit.title.clone()));
+            }
+        }
+
+        scored.sort_by(|a, b| b.0.cmp(&a.0));
+        scored.into_iter().take(MAX_SUGGESTIONS).map(|(_, url, title)| Suggestion {
+            url: url,
+            title: title,
+        }).collect()
+    }
+}
+
+/// Whether a page matches the typed text, and if so whether its address starts with it
+fn matches(query: &str, url: &str, title: &str) -> Option<bool> {
+    let url = url.to_lowercase();
+    let mut address = &url[..];
+    for prefix in ["https://", "http://", "file://"].iter() {
+        if address.starts_with(prefix) {
+            address = &address[prefix.len()..];
+        }
+    }
+    if address.starts_with("www.") {
+        address = &address[4..];
+    }
+
+    if address.starts_with(query) || url.starts_with(query) {
+        Some(true)
+    } else if url.contains(query) || title.to_lowercase().contains(query) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// How long ago a time was, in words
+fn ago(time: u64, now: u64) -> String {
+    let seconds = now.saturating_sub(time);
+    let (count, unit) = if seconds < 60 {
+        return String::from("just now");
+    } else if seconds < 60 * 60 {
+        (seconds / 60, "minute")
+    } else if seconds < 24 * 60 * 60 {
+        (seconds / (60 * 60), "hour")
+    } else {
+        (seconds / (24 * 60 * 60), "day")
+    };
+
+    if count == 1 {
+        format!("1 {} ago", unit)
+    } else {
+        format!("{} {}s ago", count, unit)
+    }
+}
+
+/// The history page, as an HTML document listing the pages visited, latest first
+pub fn page() -> String {
+    let history = History::load();
+    let mut visits: Vec<&Visit> = history.visits.iter().collect();
+    visits.sort_by(|a, b| b.time.cmp(&a.time));
+
+    let mut html = String::from("<html><head><title>History</title></head><body><h1>History</h1>");
+    if visits.is_empty() {
+        html.push_str("<p>No pages have been visited yet.</p>");
+    } else {
+        let now = now();
+        html.push_str("<ul>");
+        for visit in visits.iter().take(MAX_PAGE_VISITS) {
+            let title = if visit.title.is_empty() {
+                &visit.url
+            } else {
+                &visit.title
+            };
+            html.push_str(&format!("<li><a href=\"{}\">{}</a><br>{}, {}</li>", escape(&visit.url), escape(title), escape(&visit.url), ago(visit.time, now)));
+        }
+        html.push_str("</ul>");
+    }
+    html.push_str("</body></html>");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ago, matches};
+
+    #[test]
+    fn address_matches() {
+        // The scheme and www. may be left out of the start of an address
+        assert_eq!(matches("exa", "https://www.example.com/", "Example"), Some(true));
+        assert_eq!(matches("http://www.exa", "http://www.example.com/", "Example"), Some(true));
+        assert_eq!(matches("/home", "file:///home/user/page.html", ""), Some(true));
+
+        // Text anywhere else in the address or in the title matches less well
+        assert_eq!(matches("com", "https://www.example.com/", "Example"), Some(false));
+        assert_eq!(matches("domain", "https://www.example.com/", "Example Domain"), Some(false));
+        assert_eq!(matches("news", "https://www.example.com/", "Example"), None);
+    }
+
+    #[test]
+    fn times_ago() {
+        assert_eq!(ago(1000, 1059), "just now");
+        assert_eq!(ago(1000, 1060), "1 minute ago");
+        assert_eq!(ago(1000, 1000 + 59 * 60), "59 minutes ago");
+        assert_eq!(ago(1000, 1000 + 2 * 60 * 60), "2 hours ago");
+        assert_eq!(ago(1000, 1000 + 3 * 24 * 60 * 60 + 5), "3 days ago");
+
+        // A time in the future, from a clock that was changed, is not an error
+        assert_eq!(ago(2000, 1000), "just now");
+    }
+}
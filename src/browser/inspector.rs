@@ -0,0 +1,221 @@
+use std::cmp;
+use std::collections::BTreeMap;
+
+use html5ever::rcdom::{Document, Doctype, Text, Comment, Element, Handle};
+use hyper::header::Headers;
+use orbclient::{Color, Renderer, Window};
+use orbfont::Font;
+
+use super::Block;
+
+/// The height of the inspector pane along the bottom of the page
+pub const HEIGHT: i32 = 240;
+
+const FONT_SIZE: f32 = 12.0;
+const LINE_H: i32 = 16;
+
+/// Text nodes and attribute values longer than this are cut short in the tree
+const MAX_LABEL: usize = 80;
+
+/// A node of the document tree, flattened in document order
+pub struct Node {
+    /// The identifier the layout boxes and blocks of the node carry
+    pub id: usize,
+    pub depth: usize,
+    pub label: String,
+}
+
+/// What the browser received for a page, which the inspector shows
+pub struct Inspection {
+    pub headers: Vec<String>,
+    pub errors: Vec<String>,
+    pub nodes: Vec<Node>,
+}
+
+impl Inspection {
+    pub fn new() -> Inspection {
+        Inspection {
+            headers: Vec::new(),
+            errors: Vec::new(),
+            nodes: Vec::new(),
+        }
+    }
+
+    pub fn set_headers(&mut self, headers: &Headers) {
+        self.headers = headers.iter().map(|header| format!("{}: {}", header.name(), header.value_string())).collect();
+    }
+
+    /// Record the document tree below a node
+    pub fn add_tree(&mut self, handle: Handle, depth: usize) {
+        let node = handle.borrow();
+
+        let label = match node.node {
+            Document => String::from("#document"),
+            Doctype(ref name, _, _) => format!("<!DOCTYPE {}>", name),
+            Comment(ref text) => format!("<!--{}-->", shorten(text)),
+            Text(ref text) => {
+                // Whitespace between elements produces nothing, and would only clutter the tree
+                if text.trim().is_empty() {
+                    return;
+                }
+                format!("\"{}\"", shorten(&text.split_whitespace().collect::<Vec<&str>>().join(" ")))
+            },
+            Element(ref name, _, ref attrs) => {
+                let mut label = format!("<{}", &*name.local);
+                for attr in attrs.iter() {
+                    label.push_str(&format!(" {}=\"{}\"", &*attr.name.local, shorten(&attr.value)));
+                }
+                label.push('>');
+                label
+            }
+        };
+
+        self.nodes.push(Node {
+            id: node_id(&handle),
+            depth: depth,
+            label: label,
+        });
+
+        for child in node.children.iter() {
+            self.add_tree(child.clone(), depth + 1);
+        }
+    }
+}
+
+/// An identifier of a node, unique among the nodes of its document, which layout passes on to the blocks it produces
+pub fn node_id(handle: &Handle) -> usize {
+    &**handle as *const _ as usize
+}
+
+fn shorten(text: &str) -> String {
+    if text.chars().count() > MAX_LABEL {
+        let mut short: String = text.chars().take(MAX_LABEL).collect();
+        short.push_str("...");
+        short
+    } else {
+        text.to_string()
+    }
+}
+
+/// The pane showing the document tree of a page, its parse errors, and the headers it came with.
+/// Clicking a node outlines the blocks it produced.
+pub struct Inspector {
+    pub open: bool,
+    /// The first line in view
+    scroll: usize,
+    /// The node whose blocks are outlined
+    selected: Option<usize>,
+}
+
+impl Inspector {
+    pub fn new() -> Inspector {
+        Inspector {
+            open: false,
+            scroll: 0,
+            selected: None,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = ! self.open;
+        self.scroll = 0;
+        self.selected = None;
+    }
+
+    /// Forget the selected node, whose page has gone
+    pub fn clear(&mut self) {
+        self.scroll = 0;
+        self.selected = None;
+    }
+
+    /// The lines of the pane, with the node each line of the tree stands for
+    fn lines(&self, inspection: &Inspection, blocks: &[Block]) -> Vec<(String, Color, Option<usize>)> {
+        let heading = Color::rgb(0, 0, 128);
+        let plain = Color::rgb(0, 0, 0);
+
+        let mut lines = Vec::new();
+        lines.push((format!("Response headers ({})", inspection.headers.len()), heading, None));
+        for header in inspection.headers.iter() {
+            lines.push((format!("  {}", header), plain, None));
+        }
+
+        lines.push((format!("Parse errors ({})", inspection.errors.len()), heading, None));
+        for error in inspection.errors.iter() {
+            lines.push((format!("  {}", error), Color::rgb(160, 0, 0), None));
+        }
+
+        let mut counts = BTreeMap::new();
+        for block in blocks.iter() {
+            if let Some(node) = block.node {
+                *counts.entry(node).or_insert(0) += 1;
+            }
+        }
+
+        lines.push((format!("Document ({} nodes)", inspection.nodes.len()), heading, None));
+        for node in inspection.nodes.iter() {
+            let mut line = format!("  {}{}", "  ".repeat(node.depth), node.label);
+            match counts.get(&node.id) {
+                Some(&1) => line.push_str("  [1 block]"),
+                Some(count) => line.push_str(&format!("  [{} blocks]", count)),
+                None => ()
+            }
+            lines.push((line, plain, Some(node.id)));
+        }
+
+        lines
+    }
+
+    /// Scroll the pane by a number of lines
+    pub fn scroll(&mut self, inspection: &Inspection, lines: i32) {
+        let count = (inspection.headers.len() + inspection.errors.len() + inspection.nodes.len() + 3) as i32;
+        let max = cmp::max(0, count - (HEIGHT - 4) / LINE_H);
+        self.scroll = cmp::max(0, cmp::min(max, self.scroll as i32 + lines)) as usize;
+    }
+
+    /// Select the node on the line at a height within the pane, and return the first block it produced to scroll to
+    pub fn click(&mut self, inspection: &Inspection, blocks: &[Block], y: i32) -> Option<usize> {
+        let line = self.scroll + (cmp::max(0, y - 2) / LINE_H) as usize;
+        self.selected = self.lines(inspection, blocks).get(line).and_then(|&(_, _, node)| node);
+        self.selected.and_then(|node| blocks.iter().position(|block| block.node == Some(node)))
+    }
+
+    /// Draw a frame around every block of the selected node
+    pub fn draw_outline(&self, window: &mut Window, blocks: &[Block], offset: (i32, i32)) {
+        let node = match self.selected {
+            Some(node) => node,
+            None => return
+        };
+
+        let color = Color::rgb(255, 0, 0);
+        for block in blocks.iter().filter(|block| block.node == Some(node)) {
+            let x = block.x - offset.0;
+            let y = block.y - offset.1;
+            let w = cmp::max(1, block.w);
+            let h = cmp::max(1, block.h);
+            window.rect(x, y, w as u32, 1, color);
+            window.rect(x, y + h - 1, w as u32, 1, color);
+            window.rect(x, y, 1, h as u32, color);
+            window.rect(x + w - 1, y, 1, h as u32, color);
+        }
+    }
+
+    /// Draw the pane with its bottom edge at the given height
+    pub fn draw(&self, window: &mut Window, font: &Font, inspection: &Inspection, blocks: &[Block], bottom: i32) {
+        let width = window.width();
+        let y = bottom - HEIGHT;
+        window.rect(0, y, width, HEIGHT as u32, Color::rgb(245, 245, 245));
+        window.rect(0, y, width, 1, Color::rgb(128, 128, 128));
+
+        let lines = self.lines(inspection, blocks);
+        let visible = (HEIGHT - 4) / LINE_H;
+        for (i, &(ref line, color, node)) in lines.iter().skip(self.scroll).take(visible as usize).enumerate() {
+            let line_y = y + 2 + i as i32 * LINE_H;
+            if node.is_some() && node == self.selected {
+                window.rect(0, line_y, width, LINE_H as u32, Color::rgb(179, 215, 255));
+            }
+
+            let text = font.render(line, FONT_SIZE);
+            text.draw(window, 4, line_y + (LINE_H - text.height() as i32) / 2, color);
+        }
+    }
+}
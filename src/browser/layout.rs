@@ -0,0 +1,1534 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use html5ever::Attribute;
+use html5ever::rcdom::{Document, Doctype, Text, Comment, Element, Handle};
+use orbclient::Color;
+use orbfont;
+use orbimage::{Image, ResizeType};
+use url::Url;
+
+use css::{self, Display, ElementData, Length, ListStyle, Style, Stylesheet, TextAlign, WhiteSpace};
+use form::{Control, ControlKind, FormBuilder};
+use inspector;
+use super::{Block, Fonts};
+
+/// What a box in the layout tree holds
+pub enum BoxKind {
+    /// Stacks block children, or flows inline children into line boxes
+    Block,
+    /// Groups inline children, such as a span or link
+    Inline,
+    /// Arranges its rows into a grid. The `cellpadding` and `border` attributes apply to every cell.
+    Table { cell_padding: Option<i32>, cell_border: i32 },
+    /// A row of cells, only laid out as part of a table
+    TableRow,
+    /// A cell covering one or more columns and rows of a table
+    TableCell { colspan: usize, rowspan: usize },
+    /// A run of text, with whitespace collapsed to single spaces unless it is preformatted
+    Text(String),
+    /// A replaced element
+    Image(Image),
+    /// An image still being fetched, showing its alt text until it arrives, with the size its attributes ask for
+    Pending { url: Url, width: Option<u32>, height: Option<u32> },
+    /// A forced line break
+    LineBreak,
+    /// A form control, drawn by the browser rather than from its children
+    Control(Control),
+}
+
+/// A node in the layout tree. The tree is built once per document and laid out again for every width.
+pub struct LayoutBox {
+    pub kind: BoxKind,
+    pub style: Style,
+    pub link: Option<String>,
+    /// The `id` of the element, and the `name` of a link, which fragments of the URL scroll to
+    pub anchors: Vec<String>,
+    /// The bullet or number drawn before the first line of a list item
+    pub marker: Option<String>,
+    /// The document node the box was made for, which the inspector finds its blocks by
+    pub node: Option<usize>,
+    pub children: Vec<LayoutBox>,
+}
+
+impl LayoutBox {
+    pub fn new(kind: BoxKind, style: Style) -> LayoutBox {
+        LayoutBox {
+            kind: kind,
+            style: style,
+            link: None,
+            anchors: Vec::new(),
+            marker: None,
+            node: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// A document holding a single paragraph of text, such as an error message
+    pub fn text(string: &str, style: Style) -> LayoutBox {
+        let mut document = LayoutBox::new(BoxKind::Block, Style::default());
+        document.children.push(LayoutBox::new(BoxKind::Text(string.to_string()), style));
+        document
+    }
+
+    /// A document holding a single image
+    pub fn image(image: Image) -> LayoutBox {
+        let mut document = LayoutBox::new(BoxKind::Block, Style::default());
+        document.children.push(LayoutBox::new(BoxKind::Image(image), Style::default()));
+        document
+    }
+
+    fn is_block(&self) -> bool {
+        match self.kind {
+            BoxKind::Block | BoxKind::Table { .. } | BoxKind::TableRow | BoxKind::TableCell { .. } => true,
+            _ => false
+        }
+    }
+
+    fn is_whitespace(&self) -> bool {
+        match self.kind {
+            BoxKind::Text(ref text) => text.trim().is_empty(),
+            _ => false
+        }
+    }
+
+    /// Collect the URLs of the images still to be fetched, each once
+    pub fn pending_images(&self, urls: &mut Vec<Url>) {
+        if let BoxKind::Pending { ref url, .. } = self.kind {
+            if ! urls.contains(url) {
+                urls.push(url.clone());
+            }
+        }
+
+        for child in self.children.iter() {
+            child.pending_images(urls);
+        }
+    }
+
+    /// Replace the pending images with the given URL by the fetched image, scaled to the size they ask for,
+    /// returning whether there were any
+    pub fn set_image(&mut self, url: &Url, image: &Image) -> bool {
+        let size = match self.kind {
+            BoxKind::Pending { url: ref pending, width, height } if pending == url => Some((width, height)),
+            _ => None
+        };
+
+        let pending = size.is_some();
+        if let Some((width, height)) = size {
+            self.kind = BoxKind::Image(sized_image(image, width, height));
+            self.children.clear();
+        }
+
+        let mut found = pending;
+        for child in self.children.iter_mut() {
+            if child.set_image(url, image) {
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Make the children either all block or all inline, wrapping runs of inline children in anonymous blocks
+    fn wrap_inline_children(&mut self) {
+        if ! self.children.iter().any(|child| child.is_block()) {
+            return;
+        }
+
+        let style = self.style.inherit();
+        let mut children = Vec::new();
+        let mut run: Vec<LayoutBox> = Vec::new();
+        for child in self.children.drain(..) {
+            if child.is_block() {
+                if run.iter().any(|inline| ! inline.is_whitespace()) {
+                    let mut anonymous = LayoutBox::new(BoxKind::Block, style.clone());
+                    anonymous.children = run;
+                    children.push(anonymous);
+                }
+                run = Vec::new();
+                children.push(child);
+            } else {
+                run.push(child);
+            }
+        }
+        if run.iter().any(|inline| ! inline.is_whitespace()) {
+            let mut anonymous = LayoutBox::new(BoxKind::Block, style);
+            anonymous.children = run;
+            children.push(anonymous);
+        }
+
+        self.children = children;
+    }
+
+    /// Make the children of a table all rows or captions, and the children of a row all cells
+    fn wrap_table_children(&mut self) {
+        let row = match self.kind {
+            BoxKind::TableRow => true,
+            _ => false
+        };
+
+        let style = self.style.inherit();
+        let mut children = Vec::new();
+        let mut run: Vec<LayoutBox> = Vec::new();
+        for child in self.children.drain(..) {
+            let fits = match child.kind {
+                BoxKind::TableCell { .. } => row,
+                BoxKind::TableRow | BoxKind::Block | BoxKind::Table { .. } => ! row,
+                _ => false
+            };
+
+            if fits {
+                wrap_table_run(run, row, &style, &mut children);
+                run = Vec::new();
+                children.push(child);
+            } else {
+                run.push(child);
+            }
+        }
+        wrap_table_run(run, row, &style, &mut children);
+
+        self.children = children;
+    }
+}
+
+/// Wrap stray content of a row in an anonymous cell, and stray content of a table in an anonymous row or block
+fn wrap_table_run(run: Vec<LayoutBox>, row: bool, style: &Style, children: &mut Vec<LayoutBox>) {
+    if ! run.iter().any(|child| ! child.is_whitespace()) {
+        return;
+    }
+
+    let cells = run.iter().any(|child| match child.kind {
+        BoxKind::TableCell { .. } => true,
+        _ => false
+    });
+
+    let mut anonymous = if row {
+        LayoutBox::new(BoxKind::TableCell { colspan: 1, rowspan: 1 }, style.clone())
+    } else if cells {
+        LayoutBox::new(BoxKind::TableRow, style.clone())
+    } else {
+        LayoutBox::new(BoxKind::Block, style.clone())
+    };
+    anonymous.children = run;
+
+    match anonymous.kind {
+        BoxKind::TableRow => anonymous.wrap_table_children(),
+        _ => anonymous.wrap_inline_children()
+    }
+
+    children.push(anonymous);
+}
+
+/// Scale an image to the size asked for, keeping its aspect ratio when only the width or the height is given
+fn sized_image(image: &Image, width: Option<u32>, height: Option<u32>) -> Image {
+    let natural = (image.width(), image.height());
+    let size = match (width, height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) if natural.0 > 0 => (width, cmp::max(1, (natural.1 as u64 * width as u64 / natural.0 as u64) as u32)),
+        (None, Some(height)) if natural.1 > 0 => (cmp::max(1, (natural.0 as u64 * height as u64 / natural.1 as u64) as u32), height),
+        _ => natural
+    };
+
+    if size == natural {
+        image.clone()
+    } else {
+        image.resize(size.0, size.1, ResizeType::Lanczos3).unwrap_or_else(|_| image.clone())
+    }
+}
+
+fn attr_value(attrs: &[Attribute], name: &str) -> Option<String> {
+    attrs.iter()
+        .find(|attr| &*attr.name.local == name)
+        .map(|attr| attr.value.trim().to_string())
+}
+
+/// Parse a size in pixels such as the `width` of an image, ignoring percentages
+fn attr_pixels(attrs: &[Attribute], name: &str) -> Option<u32> {
+    attr_value(attrs, name)
+        .and_then(|value| value.trim_right_matches("px").trim().parse::<u32>().ok())
+        .map(|pixels| cmp::min(cmp::max(pixels, 1), 10000))
+}
+
+/// Parse a span attribute such as `colspan`, which is at least one
+fn attr_span(attrs: &[Attribute], name: &str) -> usize {
+    match attr_value(attrs, name).and_then(|value| value.parse::<usize>().ok()) {
+        Some(span) => cmp::min(cmp::max(span, 1), 1000),
+        None => 1
+    }
+}
+
+/// Add preformatted text as runs of text split by line breaks, keeping its spaces and expanding its tabs
+pub fn preformatted(text: &str, style: &Style, link: Option<&String>, boxes: &mut Vec<LayoutBox>) {
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            let mut break_box = LayoutBox::new(BoxKind::LineBreak, style.clone());
+            break_box.link = link.cloned();
+            boxes.push(break_box);
+        }
+
+        let mut string = String::new();
+        let mut column = 0;
+        for c in line.chars() {
+            match c {
+                '\t' => {
+                    string.push(' ');
+                    column += 1;
+                    while column % 8 != 0 {
+                        string.push(' ');
+                        column += 1;
+                    }
+                },
+                '\r' => (),
+                _ => {
+                    string.push(c);
+                    column += 1;
+                }
+            }
+        }
+
+        if ! string.is_empty() {
+            let mut text_box = LayoutBox::new(BoxKind::Text(string), style.clone());
+            text_box.link = link.cloned();
+            boxes.push(text_box);
+        }
+    }
+}
+
+/// The number of a list item, counting the items before it from the `start` of its list.
+/// A `value` on an item renumbers it and the items after it.
+fn list_ordinal(handle: &Handle) -> i32 {
+    let parent = match handle.borrow().parent.as_ref().and_then(|parent| parent.upgrade()) {
+        Some(parent) => parent,
+        None => return 1
+    };
+    let parent = parent.borrow();
+
+    let mut ordinal = match parent.node {
+        Element(_, _, ref attrs) => attr_value(attrs, "start").and_then(|value| value.parse::<i32>().ok()).unwrap_or(1),
+        _ => 1
+    };
+    for child in parent.children.iter() {
+        if let Element(ref name, _, ref attrs) = child.borrow().node {
+            if &*name.local == "li" {
+                if let Some(value) = attr_value(attrs, "value").and_then(|value| value.parse::<i32>().ok()) {
+                    ordinal = value;
+                }
+                if Rc::ptr_eq(child, handle) {
+                    return ordinal;
+                }
+                ordinal += 1;
+            }
+        }
+    }
+    ordinal
+}
+
+/// The marker of a list item with the given number
+fn list_marker(list_style: ListStyle, ordinal: i32) -> Option<String> {
+    match list_style {
+        ListStyle::None => None,
+        ListStyle::Disc => Some("\u{2022}".to_string()),
+        ListStyle::Circle => Some("\u{25E6}".to_string()),
+        ListStyle::Square => Some("\u{25AA}".to_string()),
+        ListStyle::Decimal => Some(format!("{}.", ordinal)),
+        ListStyle::LowerAlpha => Some(format!("{}.", alphabetic(ordinal).to_lowercase())),
+        ListStyle::UpperAlpha => Some(format!("{}.", alphabetic(ordinal))),
+        ListStyle::LowerRoman => Some(format!("{}.", roman(ordinal).to_lowercase())),
+        ListStyle::UpperRoman => Some(format!("{}.", roman(ordinal))),
+    }
+}
+
+/// Number with letters, going A to Z and then AA, AB and so on
+fn alphabetic(ordinal: i32) -> String {
+    if ordinal < 1 {
+        return ordinal.to_string();
+    }
+
+    let mut letters = Vec::new();
+    let mut n = ordinal;
+    while n > 0 {
+        n -= 1;
+        letters.push((b'A' + (n % 26) as u8) as char);
+        n /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+/// Number with Roman numerals, which only go from 1 to 3999
+fn roman(ordinal: i32) -> String {
+    if ordinal < 1 || ordinal > 3999 {
+        return ordinal.to_string();
+    }
+
+    let numerals = [(1000, "M"), (900, "CM"), (500, "D"), (400, "CD"), (100, "C"), (90, "XC"),
+                    (50, "L"), (40, "XL"), (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I")];
+    let mut string = String::new();
+    let mut n = ordinal;
+    for &(value, numeral) in numerals.iter() {
+        while n >= value {
+            string.push_str(numeral);
+            n -= value;
+        }
+    }
+    string
+}
+
+/// Build the layout tree of a document, computing the style of every element from the stylesheets
+pub fn build(handle: Handle, url: &Url, stylesheets: &[Stylesheet]) -> LayoutBox {
+    let mut document = LayoutBox::new(BoxKind::Block, Style::default());
+    build_node(handle, url, stylesheets, &Style::default(), None, &mut Vec::new(), &mut FormBuilder::new(), &mut document.children);
+    document.wrap_inline_children();
+    document
+}
+
+fn build_node(handle: Handle, url: &Url, stylesheets: &[Stylesheet], parent_style: &Style, link: Option<&String>, ancestors: &mut Vec<ElementData>, forms: &mut FormBuilder, boxes: &mut Vec<LayoutBox>) {
+    let node = handle.borrow();
+
+    match node.node {
+        Document => {
+            for child in node.children.iter() {
+                build_node(child.clone(), url, stylesheets, parent_style, link, ancestors, forms, boxes);
+            }
+        },
+
+        Doctype(..) | Comment(_) => (),
+
+        Text(ref text) => {
+            if parent_style.white_space == WhiteSpace::Pre {
+                let first = boxes.len();
+                preformatted(text, parent_style, link, boxes);
+                for text_box in boxes[first..].iter_mut() {
+                    text_box.node = Some(inspector::node_id(&handle));
+                }
+                return;
+            }
+
+            let mut string = String::new();
+            let mut whitespace = false;
+            for c in text.chars() {
+                match c {
+                    ' ' | '\n' | '\r' | '\t' => if ! whitespace {
+                        whitespace = true;
+                        string.push(' ');
+                    },
+                    _ => {
+                        whitespace = false;
+                        string.push(c);
+                    }
+                }
+            }
+
+            if ! string.is_empty() {
+                let mut text_box = LayoutBox::new(BoxKind::Text(string), parent_style.clone());
+                text_box.link = link.cloned();
+                text_box.node = Some(inspector::node_id(&handle));
+                boxes.push(text_box);
+            }
+        },
+
+        Element(ref name, _, ref attrs) => {
+            let element = ElementData::new(&*name.local, attrs);
+
+            let mut inline_style = None;
+            for attr in attrs.iter() {
+                if &*attr.name.local == "style" {
+                    inline_style = Some(attr.value.to_string());
+                }
+            }
+
+            let hints = css::hints(&*name.local, attrs);
+            let style = css::cascade(stylesheets, &element, ancestors, &hints, inline_style.as_ref().map(|s| s.as_str()), parent_style);
+            if style.display == Display::None {
+                return;
+            }
+
+            let mut alt_box = None;
+            let kind = match &*name.local {
+                "br" => BoxKind::LineBreak,
+                "img" => {
+                    let mut src_opt = None;
+                    let mut alt_opt = None;
+                    for attr in attrs.iter() {
+                        match &*attr.name.local {
+                            "src" => src_opt = Some(attr.value.to_string()),
+                            "alt" => alt_opt = Some(attr.value.to_string()),
+                            _ => ()
+                        }
+                    }
+
+                    match src_opt.and_then(|src| url.join(&src).ok()) {
+                        Some(img_url) => {
+                            alt_box = alt_opt.map(|alt| LayoutBox::new(BoxKind::Text(alt), style.clone()));
+                            BoxKind::Pending {
+                                url: img_url,
+                                width: attr_pixels(attrs, "width"),
+                                height: attr_pixels(attrs, "height"),
+                            }
+                        },
+                        None => match alt_opt {
+                            Some(alt) => BoxKind::Text(alt),
+                            None => return
+                        }
+                    }
+                },
+                "input" | "select" | "textarea" | "button" => match forms.control(&*name.local, attrs, &node.children) {
+                    Some(control) => BoxKind::Control(control),
+                    None => return
+                },
+                _ => match style.display {
+                    Display::Block | Display::ListItem => BoxKind::Block,
+                    Display::Table => {
+                        // Any border on a table also draws a 1px border around each cell
+                        let cell_border = match attr_value(attrs, "border") {
+                            Some(value) => if value.parse::<i32>().unwrap_or(1) > 0 { 1 } else { 0 },
+                            None => 0
+                        };
+
+                        BoxKind::Table {
+                            cell_padding: attr_value(attrs, "cellpadding").and_then(|value| value.parse::<i32>().ok()),
+                            cell_border: cell_border,
+                        }
+                    },
+                    Display::TableRow => BoxKind::TableRow,
+                    Display::TableCell => BoxKind::TableCell {
+                        colspan: attr_span(attrs, "colspan"),
+                        rowspan: attr_span(attrs, "rowspan"),
+                    },
+                    Display::TableRowGroup => {
+                        // Row groups only order rows, so their rows join the table directly
+                        ancestors.push(element);
+                        for child in node.children.iter() {
+                            build_node(child.clone(), url, stylesheets, &style, link, ancestors, forms, boxes);
+                        }
+                        ancestors.pop();
+                        return;
+                    },
+                    Display::Inline | Display::None => BoxKind::Inline,
+                }
+            };
+
+            let marker = if style.display == Display::ListItem {
+                list_marker(style.list_style, list_ordinal(&handle))
+            } else {
+                None
+            };
+
+            let mut element_box = LayoutBox::new(kind, style);
+            element_box.link = link.cloned();
+            element_box.marker = marker;
+            element_box.node = Some(inspector::node_id(&handle));
+            if let Some(mut alt_box) = alt_box {
+                alt_box.link = link.cloned();
+                alt_box.node = element_box.node;
+                element_box.children.push(alt_box);
+            }
+
+            for attr in attrs.iter() {
+                match &*attr.name.local {
+                    "id" => element_box.anchors.push(attr.value.to_string()),
+                    "name" if &*name.local == "a" => element_box.anchors.push(attr.value.to_string()),
+                    "href" if &*name.local == "a" => element_box.link = Some(attr.value.to_string()),
+                    _ => ()
+                }
+            }
+
+            let container = match element_box.kind {
+                BoxKind::Block | BoxKind::Inline | BoxKind::Table { .. } | BoxKind::TableRow | BoxKind::TableCell { .. } => true,
+                _ => false
+            };
+            let table = match element_box.kind {
+                BoxKind::Table { .. } | BoxKind::TableRow => true,
+                _ => false
+            };
+
+            // Controls inside a form element belong to it
+            let outer_form = if &*name.local == "form" {
+                Some(forms.begin(url, attrs))
+            } else {
+                None
+            };
+
+            if container {
+                ancestors.push(element);
+                let child_link = element_box.link.clone();
+                for child in node.children.iter() {
+                    build_node(child.clone(), url, stylesheets, &element_box.style, child_link.as_ref(), ancestors, forms, &mut element_box.children);
+                }
+                ancestors.pop();
+
+                if table {
+                    element_box.wrap_table_children();
+                } else {
+                    // An inline element holding blocks is laid out as a block
+                    if element_box.children.iter().any(|child| child.is_block()) {
+                        element_box.kind = BoxKind::Block;
+                    }
+
+                    element_box.wrap_inline_children();
+                }
+            }
+
+            if let Some(previous) = outer_form {
+                forms.end(previous);
+            }
+
+            boxes.push(element_box);
+        }
+    }
+}
+
+/// Lay out the document at the given width, with its text and images scaled by the zoom, producing positioned blocks, and return its height
+pub fn layout<'a>(document: &LayoutBox, width: i32, fonts: &'a Fonts, zoom: f32, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) -> i32 {
+    layout_block(document, 0, 0, width, fonts, zoom, anchors, blocks)
+}
+
+/// Lay out a block box with its top at `y` inside a containing block at `x` of the given width, returning its height
+fn layout_block<'a>(block: &LayoutBox, x: i32, y: i32, containing_width: i32, fonts: &'a Fonts, zoom: f32, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) -> i32 {
+    let style = &block.style;
+
+    let table = match block.kind {
+        BoxKind::Table { cell_padding, cell_border } => Some(Table::new(block, cell_padding, cell_border, fonts, zoom)),
+        _ => None
+    };
+
+    let border = style.border(zoom);
+    let padding: Vec<i32> = style.padding.iter().map(|length| length.resolve(containing_width, zoom)).collect();
+    let mut margin_left = style.margin[3].resolve(containing_width, zoom);
+    let margin_right = style.margin[1].resolve(containing_width, zoom);
+
+    let mut width = match style.width {
+        Length::Auto => containing_width - margin_left - margin_right - padding[1] - padding[3] - 2 * border,
+        length => length.resolve(containing_width, zoom),
+    };
+    if style.max_width != Length::Auto {
+        width = cmp::min(width, style.max_width.resolve(containing_width, zoom));
+    }
+    if let Some(ref table) = table {
+        // Tables shrink to fit their cells, but never below the width of their widest words
+        if style.width == Length::Auto {
+            width = cmp::min(width, table.max_width());
+        }
+        width = cmp::max(width, table.min_width());
+    }
+    width = cmp::max(0, width);
+
+    let outer_width = border + padding[3] + width + padding[1] + border;
+
+    // Auto margins share the space left over by a narrower block
+    let leftover = containing_width - margin_left - outer_width - margin_right;
+    if leftover > 0 {
+        match (style.margin[3] == Length::Auto, style.margin[1] == Length::Auto) {
+            (true, true) => margin_left += leftover / 2,
+            (true, false) => margin_left += leftover,
+            _ => ()
+        }
+    }
+
+    let border_x = x + margin_left;
+    let content_x = border_x + border + padding[3];
+    let content_y = y + border + padding[0];
+
+    // When an anchor is used twice, the first one counts
+    for anchor in block.anchors.iter() {
+        anchors.entry(anchor.clone()).or_insert(y);
+    }
+
+    let start = blocks.len();
+
+    // The marker of a list item hangs to the left of its content
+    if let Some(ref marker) = block.marker {
+        let text = render_word(marker, style, fonts, zoom);
+        let gap = (style.font_size * zoom / 2.0) as i32;
+        blocks.push(Block {
+            x: content_x - gap - text.width() as i32,
+            y: content_y,
+            w: text.width() as i32,
+            h: text.height() as i32,
+            color: style.color,
+            string: marker.clone(),
+            link: None,
+            image: None,
+            text: Some(text),
+            background: None,
+            control: None,
+            node: block.node,
+        });
+    }
+
+    let content_height = match table {
+        Some(ref table) => layout_table(table, content_x, content_y, width, fonts, zoom, anchors, blocks),
+        None => layout_children(block, content_x, content_y, width, fonts, zoom, anchors, blocks)
+    };
+
+    let height = border + padding[0] + content_height + padding[2] + border;
+
+    if let Some(background) = style.background_color {
+        let mut background_block = rect_block(border_x, y, outer_width, height, background);
+        background_block.node = block.node;
+        blocks.insert(start, background_block);
+    }
+
+    if border > 0 {
+        let first = blocks.len();
+        border_blocks(border_x, y, outer_width, height, border, style.border_color(), blocks);
+        set_node(&mut blocks[first..], block.node);
+    }
+
+    height
+}
+
+/// Lay out the children of a block in its content box, returning the height of the content
+fn layout_children<'a>(block: &LayoutBox, x: i32, y: i32, width: i32, fonts: &'a Fonts, zoom: f32, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) -> i32 {
+    if block.children.iter().any(|child| child.is_block()) {
+        let mut cursor = y;
+        let mut pending_margin = 0;
+        for child in block.children.iter() {
+            // Adjoining margins of siblings collapse into the larger one
+            let margin_top = child.style.margin[0].resolve(width, zoom);
+            cursor += cmp::max(pending_margin, margin_top);
+            cursor += layout_block(child, x, cursor, width, fonts, zoom, anchors, blocks);
+            pending_margin = child.style.margin[2].resolve(width, zoom);
+        }
+        cursor + pending_margin - y
+    } else {
+        layout_inline(&block.children, x, y, width, block.style.text_align, fonts, zoom, anchors, blocks)
+    }
+}
+
+fn rect_block<'a>(x: i32, y: i32, w: i32, h: i32, color: Color) -> Block<'a> {
+    Block {
+        x: x,
+        y: y,
+        w: w,
+        h: h,
+        color: color,
+        string: String::new(),
+        link: None,
+        image: None,
+        text: None,
+        background: Some(color),
+        control: None,
+        node: None,
+    }
+}
+
+/// Mark blocks as produced by a document node
+fn set_node(blocks: &mut [Block], node: Option<usize>) {
+    for block in blocks.iter_mut() {
+        block.node = node;
+    }
+}
+
+/// Draw a border of the given width just inside a rectangle
+fn border_blocks<'a>(x: i32, y: i32, w: i32, h: i32, border: i32, color: Color, blocks: &mut Vec<Block<'a>>) {
+    blocks.push(rect_block(x, y, w, border, color));
+    blocks.push(rect_block(x, y + h - border, w, border, color));
+    blocks.push(rect_block(x, y + border, border, h - 2 * border, color));
+    blocks.push(rect_block(x + w - border, y + border, border, h - 2 * border, color));
+}
+
+/// How many columns a table may have, so that a few cells cannot make it allocate without bound
+const MAX_COLUMNS: usize = 1000;
+
+/// Place the cells of the rows of a table, given by their colspan and rowspan, in a grid, returning the column,
+/// colspan and rowspan of every cell. Spans stop at the last row and the last column, and a cell with no free
+/// column left is not placed.
+fn place_cells(rows: &[Vec<(usize, usize)>]) -> Vec<Vec<Option<(usize, usize, usize)>>> {
+    // Slots already covered by cells spanning down from earlier rows
+    let mut occupied: Vec<Vec<bool>> = vec![Vec::new(); rows.len()];
+    let mut places = Vec::new();
+    for (row, spans) in rows.iter().enumerate() {
+        let mut column = 0;
+        let mut row_places = Vec::new();
+        for &(colspan, rowspan) in spans.iter() {
+            while occupied[row].get(column).cloned().unwrap_or(false) {
+                column += 1;
+            }
+            if column >= MAX_COLUMNS {
+                row_places.push(None);
+                continue;
+            }
+
+            let colspan = cmp::min(colspan, MAX_COLUMNS - column);
+            let rowspan = cmp::min(rowspan, rows.len() - row);
+            for slots in occupied[row..row + rowspan].iter_mut() {
+                while slots.len() < column + colspan {
+                    slots.push(false);
+                }
+                for slot in slots[column..column + colspan].iter_mut() {
+                    *slot = true;
+                }
+            }
+
+            row_places.push(Some((column, colspan, rowspan)));
+            column += colspan;
+        }
+        places.push(row_places);
+    }
+    places
+}
+
+/// A cell placed in the grid of a table
+struct GridCell<'b> {
+    cell: &'b LayoutBox,
+    row: usize,
+    column: usize,
+    colspan: usize,
+    rowspan: usize,
+}
+
+/// A table with its cells placed in rows and columns, and the width range of every column
+struct Table<'b> {
+    captions: Vec<&'b LayoutBox>,
+    cells: Vec<GridCell<'b>>,
+    rows: usize,
+    min: Vec<i32>,
+    max: Vec<i32>,
+    caption_min: i32,
+    caption_max: i32,
+    spacing: i32,
+    cell_padding: Option<i32>,
+    cell_border: i32,
+}
+
+impl<'b> Table<'b> {
+    fn new(table: &'b LayoutBox, cell_padding: Option<i32>, cell_border: i32, fonts: &Fonts, zoom: f32) -> Table<'b> {
+        let mut captions = Vec::new();
+        let mut table_rows = Vec::new();
+        for child in table.children.iter() {
+            match child.kind {
+                BoxKind::TableRow => table_rows.push(child),
+                _ => captions.push(child)
+            }
+        }
+
+        let spans: Vec<Vec<(usize, usize)>> = table_rows.iter().map(|table_row| table_row.children.iter().map(|cell| match cell.kind {
+            BoxKind::TableCell { colspan, rowspan } => (colspan, rowspan),
+            _ => (1, 1)
+        }).collect()).collect();
+
+        let mut cells = Vec::new();
+        for (row, (table_row, places)) in table_rows.iter().zip(place_cells(&spans)).enumerate() {
+            for (cell, place) in table_row.children.iter().zip(places) {
+                if let Some((column, colspan, rowspan)) = place {
+                    cells.push(GridCell {
+                        cell: cell,
+                        row: row,
+                        column: column,
+                        colspan: colspan,
+                        rowspan: rowspan,
+                    });
+                }
+            }
+        }
+
+        let rows = table_rows.len();
+        let columns = cells.iter().map(|grid_cell| grid_cell.column + grid_cell.colspan).max().unwrap_or(0);
+        let spacing = zoomed(table.style.border_spacing, zoom);
+        let cell_padding = cell_padding.map(|padding| zoomed(padding, zoom));
+        let cell_border = if cell_border > 0 { cmp::max(1, zoomed(cell_border, zoom)) } else { 0 };
+
+        // Cells in a single column set its widths first, then spanning cells widen the columns they cover
+        let mut min = vec![0; columns];
+        let mut max = vec![0; columns];
+        let mut spanning = Vec::new();
+        for grid_cell in cells.iter() {
+            let (padding, border, _) = cell_edges(grid_cell.cell, cell_padding, cell_border, zoom);
+            let edges = padding[1] + padding[3] + 2 * border;
+            let (content_min, content_max) = children_widths(grid_cell.cell, fonts, zoom);
+            let cell_min = content_min + edges;
+            let cell_max = match grid_cell.cell.style.width {
+                Length::Px(px) => cmp::max(cell_min, (px * zoom) as i32 + edges),
+                _ => content_max + edges
+            };
+
+            if grid_cell.colspan == 1 {
+                min[grid_cell.column] = cmp::max(min[grid_cell.column], cell_min);
+                max[grid_cell.column] = cmp::max(max[grid_cell.column], cell_max);
+            } else {
+                spanning.push((grid_cell.column, grid_cell.colspan, cell_min, cell_max));
+            }
+        }
+
+        for &(column, colspan, cell_min, cell_max) in spanning.iter() {
+            let gaps = spacing * (colspan as i32 - 1);
+            widen(&mut min[column..column + colspan], cell_min - gaps);
+            widen(&mut max[column..column + colspan], cell_max - gaps);
+        }
+
+        for column in 0..columns {
+            max[column] = cmp::max(max[column], min[column]);
+        }
+
+        let mut caption_min = 0;
+        let mut caption_max = 0;
+        for caption in captions.iter() {
+            let (min, max) = content_widths(caption, fonts, zoom);
+            caption_min = cmp::max(caption_min, min);
+            caption_max = cmp::max(caption_max, max);
+        }
+
+        Table {
+            captions: captions,
+            cells: cells,
+            rows: rows,
+            min: min,
+            max: max,
+            caption_min: caption_min,
+            caption_max: caption_max,
+            spacing: spacing,
+            cell_padding: cell_padding,
+            cell_border: cell_border,
+        }
+    }
+
+    fn grid_width(&self, columns: &[i32]) -> i32 {
+        if columns.is_empty() {
+            0
+        } else {
+            columns.iter().sum::<i32>() + self.spacing * (columns.len() as i32 + 1)
+        }
+    }
+
+    /// The narrowest the table can be without overflowing its cells
+    fn min_width(&self) -> i32 {
+        cmp::max(self.grid_width(&self.min), self.caption_min)
+    }
+
+    /// The width the table takes without wrapping any cell
+    fn max_width(&self) -> i32 {
+        cmp::max(self.grid_width(&self.max), self.caption_max)
+    }
+
+    /// Share the given table width between the columns
+    fn column_widths(&self, width: i32) -> Vec<i32> {
+        let available = width - self.spacing * (self.min.len() as i32 + 1);
+        let min_sum: i32 = self.min.iter().sum();
+        let max_sum: i32 = self.max.iter().sum();
+
+        if available <= min_sum {
+            self.min.clone()
+        } else if available < max_sum {
+            // Each column grows from its minimum in proportion to how much more it could use
+            let extra = available - min_sum;
+            let range = max_sum - min_sum;
+            self.min.iter().zip(self.max.iter())
+                .map(|(&min, &max)| min + ((max - min) as i64 * extra as i64 / range as i64) as i32)
+                .collect()
+        } else {
+            // A table wider than its contents grows every column in proportion to its width
+            let extra = available - max_sum;
+            let mut widths: Vec<i32> = if max_sum > 0 {
+                self.max.iter().map(|&max| max + (max as i64 * extra as i64 / max_sum as i64) as i32).collect()
+            } else {
+                let columns = self.max.len() as i32;
+                self.max.iter().map(|&max| max + extra / columns).collect()
+            };
+            let total: i32 = widths.iter().sum();
+            if let Some(last) = widths.last_mut() {
+                *last += available - total;
+            }
+            widths
+        }
+    }
+}
+
+/// Add whatever width the columns are missing to hold a spanning cell, evenly between them
+fn widen(columns: &mut [i32], needed: i32) {
+    let current: i32 = columns.iter().sum();
+    if needed > current && ! columns.is_empty() {
+        let extra = needed - current;
+        let count = columns.len() as i32;
+        for (i, column) in columns.iter_mut().enumerate() {
+            *column += extra / count;
+            if (i as i32) < extra % count {
+                *column += 1;
+            }
+        }
+    }
+}
+
+/// The padding, border width and border color of a cell, where the attributes of the table, already zoomed, override its style
+fn cell_edges(cell: &LayoutBox, cell_padding: Option<i32>, cell_border: i32, zoom: f32) -> ([i32; 4], i32, Color) {
+    let padding = match cell_padding {
+        Some(padding) => [padding; 4],
+        None => [
+            cell.style.padding[0].resolve(0, zoom),
+            cell.style.padding[1].resolve(0, zoom),
+            cell.style.padding[2].resolve(0, zoom),
+            cell.style.padding[3].resolve(0, zoom),
+        ]
+    };
+
+    if cell.style.border(zoom) > 0 {
+        (padding, cell.style.border(zoom), cell.style.border_color())
+    } else {
+        (padding, cell_border, Color::rgb(128, 128, 128))
+    }
+}
+
+/// Lay out the captions and then the grid of a table, returning its height
+fn layout_table<'a>(table: &Table, x: i32, y: i32, width: i32, fonts: &'a Fonts, zoom: f32, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) -> i32 {
+    let mut cursor = y;
+    for caption in table.captions.iter() {
+        cursor += layout_block(caption, x, cursor, width, fonts, zoom, anchors, blocks);
+    }
+
+    if table.cells.is_empty() {
+        return cursor - y;
+    }
+
+    let spacing = table.spacing;
+    let mut column_x = vec![x + spacing];
+    for column_w in table.column_widths(width) {
+        let next = column_x[column_x.len() - 1] + column_w + spacing;
+        column_x.push(next);
+    }
+
+    // The content of each cell is laid out on its own first, as the heights of the rows depend on it
+    let mut contents = Vec::new();
+    for grid_cell in table.cells.iter() {
+        let (padding, border, _) = cell_edges(grid_cell.cell, table.cell_padding, table.cell_border, zoom);
+        let cell_w = column_x[grid_cell.column + grid_cell.colspan] - spacing - column_x[grid_cell.column];
+        let content_w = cmp::max(0, cell_w - padding[1] - padding[3] - 2 * border);
+
+        let mut cell_anchors = BTreeMap::new();
+        let mut cell_blocks = Vec::new();
+        let content_h = layout_children(grid_cell.cell, 0, 0, content_w, fonts, zoom, &mut cell_anchors, &mut cell_blocks);
+        contents.push((content_h, cell_anchors, cell_blocks));
+    }
+
+    let mut heights = vec![0; table.rows];
+    for (grid_cell, &(content_h, _, _)) in table.cells.iter().zip(contents.iter()) {
+        if grid_cell.rowspan == 1 {
+            let (padding, border, _) = cell_edges(grid_cell.cell, table.cell_padding, table.cell_border, zoom);
+            let cell_h = content_h + padding[0] + padding[2] + 2 * border;
+            heights[grid_cell.row] = cmp::max(heights[grid_cell.row], cell_h);
+        }
+    }
+
+    // A cell spanning rows that are too short for it makes the last of them taller
+    for (grid_cell, &(content_h, _, _)) in table.cells.iter().zip(contents.iter()) {
+        if grid_cell.rowspan > 1 {
+            let (padding, border, _) = cell_edges(grid_cell.cell, table.cell_padding, table.cell_border, zoom);
+            let cell_h = content_h + padding[0] + padding[2] + 2 * border;
+            let last = grid_cell.row + grid_cell.rowspan - 1;
+            let spanned = heights[grid_cell.row..last + 1].iter().sum::<i32>() + spacing * (grid_cell.rowspan as i32 - 1);
+            if cell_h > spanned {
+                heights[last] += cell_h - spanned;
+            }
+        }
+    }
+
+    let mut row_y = vec![cursor + spacing];
+    for row_h in heights.iter() {
+        let next = row_y[row_y.len() - 1] + row_h + spacing;
+        row_y.push(next);
+    }
+
+    for (grid_cell, (content_h, cell_anchors, cell_blocks)) in table.cells.iter().zip(contents.into_iter()) {
+        let (padding, border, border_color) = cell_edges(grid_cell.cell, table.cell_padding, table.cell_border, zoom);
+        let cell_x = column_x[grid_cell.column];
+        let cell_y = row_y[grid_cell.row];
+        let cell_w = column_x[grid_cell.column + grid_cell.colspan] - spacing - cell_x;
+        let cell_h = row_y[grid_cell.row + grid_cell.rowspan] - spacing - cell_y;
+
+        if let Some(background) = grid_cell.cell.style.background_color {
+            let mut background_block = rect_block(cell_x, cell_y, cell_w, cell_h, background);
+            background_block.node = grid_cell.cell.node;
+            blocks.push(background_block);
+        }
+
+        // Content is centered vertically in a cell taller than it
+        let inner_h = cell_h - padding[0] - padding[2] - 2 * border;
+        let dx = cell_x + border + padding[3];
+        let dy = cell_y + border + padding[0] + cmp::max(0, inner_h - content_h) / 2;
+
+        for anchor in grid_cell.cell.anchors.iter() {
+            anchors.entry(anchor.clone()).or_insert(cell_y);
+        }
+        for (anchor, anchor_y) in cell_anchors {
+            anchors.entry(anchor).or_insert(anchor_y + dy);
+        }
+
+        for mut cell_block in cell_blocks {
+            cell_block.x += dx;
+            cell_block.y += dy;
+            blocks.push(cell_block);
+        }
+
+        if border > 0 {
+            let first = blocks.len();
+            border_blocks(cell_x, cell_y, cell_w, cell_h, border, border_color, blocks);
+            set_node(&mut blocks[first..], grid_cell.cell.node);
+        }
+    }
+
+    row_y[row_y.len() - 1] - y
+}
+
+/// The narrowest a box can be without overflowing, and the width it takes without wrapping, including its margins
+fn content_widths(layout_box: &LayoutBox, fonts: &Fonts, zoom: f32) -> (i32, i32) {
+    let style = &layout_box.style;
+    let edges = style.margin[1].resolve(0, zoom) + style.margin[3].resolve(0, zoom)
+        + style.padding[1].resolve(0, zoom) + style.padding[3].resolve(0, zoom)
+        + 2 * style.border(zoom);
+
+    match style.width {
+        Length::Px(px) => ((px * zoom) as i32 + edges, (px * zoom) as i32 + edges),
+        _ => {
+            let (min, max) = children_widths(layout_box, fonts, zoom);
+            (min + edges, max + edges)
+        }
+    }
+}
+
+/// The narrowest the content of a box can be without overflowing, and the width it takes without wrapping
+fn children_widths(layout_box: &LayoutBox, fonts: &Fonts, zoom: f32) -> (i32, i32) {
+    match layout_box.kind {
+        BoxKind::Table { cell_padding, cell_border } => {
+            let table = Table::new(layout_box, cell_padding, cell_border, fonts, zoom);
+            (table.min_width(), table.max_width())
+        },
+        _ => if layout_box.children.iter().any(|child| child.is_block()) {
+            let mut min = 0;
+            let mut max = 0;
+            for child in layout_box.children.iter() {
+                let (child_min, child_max) = content_widths(child, fonts, zoom);
+                min = cmp::max(min, child_min);
+                max = cmp::max(max, child_max);
+            }
+            (min, max)
+        } else {
+            inline_widths(&layout_box.children, fonts, zoom)
+        }
+    }
+}
+
+/// The widest word or image, and the widest line when lines only break at forced breaks
+fn inline_widths(boxes: &[LayoutBox], fonts: &Fonts, zoom: f32) -> (i32, i32) {
+    let mut items = Vec::new();
+    inline_items(boxes, &mut items);
+
+    let mut min = 0;
+    let mut max = 0;
+    let mut line_w = 0;
+    let mut space = 0;
+    for item in items.iter() {
+        let w = match *item {
+            InlineItem::Word(word, inline) => render_word(word, &inline.style, fonts, zoom).width() as i32,
+            InlineItem::Image(image, _) => image_size(image, zoom).0,
+            InlineItem::Control(control, inline) => control_size(control, &inline.style, fonts, zoom).0,
+            InlineItem::Space(inline) => {
+                if line_w > 0 && space == 0 {
+                    space = (inline.style.font_size * zoom / 2.0) as i32;
+                }
+                continue;
+            },
+            InlineItem::Break(_) => {
+                line_w = 0;
+                space = 0;
+                continue;
+            },
+            InlineItem::Anchor(_) => continue
+        };
+
+        min = cmp::max(min, w);
+        line_w += space + w;
+        space = 0;
+        max = cmp::max(max, line_w);
+    }
+
+    (min, max)
+}
+
+fn render_word<'a>(word: &str, style: &Style, fonts: &'a Fonts, zoom: f32) -> orbfont::Text<'a> {
+    fonts.get(style).render(word, style.font_size * zoom)
+}
+
+/// A size in pixels at the zoom of the page
+fn zoomed(px: i32, zoom: f32) -> i32 {
+    (px as f32 * zoom).round() as i32
+}
+
+/// The size an image is shown at, which is never less than a pixel
+fn image_size(image: &Image, zoom: f32) -> (i32, i32) {
+    (cmp::max(1, zoomed(image.width() as i32, zoom)), cmp::max(1, zoomed(image.height() as i32, zoom)))
+}
+
+/// A copy of an image at the size it is shown, scaled when the page is zoomed
+fn scale_image(image: &Image, w: i32, h: i32) -> Image {
+    if w == image.width() as i32 && h == image.height() as i32 {
+        image.clone()
+    } else {
+        image.resize(w as u32, h as u32, ResizeType::Triangle).unwrap_or_else(|_| image.clone())
+    }
+}
+
+/// An inline box flattened into a piece of a line
+enum InlineItem<'b> {
+    Word(&'b str, &'b LayoutBox),
+    Space(&'b LayoutBox),
+    Image(&'b Image, &'b LayoutBox),
+    Break(&'b LayoutBox),
+    Anchor(&'b String),
+    Control(&'b Control, &'b LayoutBox),
+}
+
+fn inline_items<'b>(boxes: &'b [LayoutBox], items: &mut Vec<InlineItem<'b>>) {
+    for inline in boxes.iter() {
+        for anchor in inline.anchors.iter() {
+            items.push(InlineItem::Anchor(anchor));
+        }
+
+        match inline.kind {
+            // Preformatted text is a single piece, with its spaces kept
+            BoxKind::Text(ref text) => if inline.style.white_space == WhiteSpace::Pre {
+                items.push(InlineItem::Word(text, inline));
+            } else {
+                for (i, word) in text.split(' ').enumerate() {
+                    if i > 0 {
+                        items.push(InlineItem::Space(inline));
+                    }
+                    if ! word.is_empty() {
+                        items.push(InlineItem::Word(word, inline));
+                    }
+                }
+            },
+            BoxKind::Image(ref image) => items.push(InlineItem::Image(image, inline)),
+            BoxKind::LineBreak => items.push(InlineItem::Break(inline)),
+            BoxKind::Control(ref control) => if control.kind != ControlKind::Hidden {
+                items.push(InlineItem::Control(control, inline));
+            },
+            BoxKind::Inline | BoxKind::Block | BoxKind::Table { .. } | BoxKind::TableRow | BoxKind::TableCell { .. } | BoxKind::Pending { .. } => inline_items(&inline.children, items),
+        }
+    }
+}
+
+/// A word or image placed on a line, with `x` relative to the start of the line
+struct Piece<'a, 'b> {
+    x: i32,
+    w: i32,
+    h: i32,
+    inline: &'b LayoutBox,
+    string: &'b str,
+    text: Option<orbfont::Text<'a>>,
+    image: Option<&'b Image>,
+    control: Option<&'b Control>,
+}
+
+/// Flow inline boxes into line boxes of the given width, returning the total height of the lines
+fn layout_inline<'a>(boxes: &[LayoutBox], x: i32, y: i32, width: i32, text_align: TextAlign, fonts: &'a Fonts, zoom: f32, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) -> i32 {
+    let mut items = Vec::new();
+    inline_items(boxes, &mut items);
+
+    let mut line_y = y;
+    let mut line: Vec<Piece> = Vec::new();
+    let mut line_w = 0;
+    let mut space = None;
+
+    for item in items.iter() {
+        let piece = match *item {
+            InlineItem::Word(word, inline) => {
+                let text = render_word(word, &inline.style, fonts, zoom);
+
+                Piece {
+                    x: 0,
+                    w: text.width() as i32,
+                    h: text.height() as i32,
+                    inline: inline,
+                    string: word,
+                    text: Some(text),
+                    image: None,
+                    control: None,
+                }
+            },
+            InlineItem::Image(image, inline) => {
+                let (w, h) = image_size(image, zoom);
+                Piece {
+                    x: 0,
+                    w: w,
+                    h: h,
+                    inline: inline,
+                    string: "",
+                    text: None,
+                    image: Some(image),
+                    control: None,
+                }
+            },
+            InlineItem::Control(control, inline) => {
+                let (w, h) = control_size(control, &inline.style, fonts, zoom);
+                Piece {
+                    x: 0,
+                    w: w,
+                    h: h,
+                    inline: inline,
+                    string: "",
+                    text: None,
+                    image: None,
+                    control: Some(control),
+                }
+            },
+            InlineItem::Space(inline) => {
+                // Spaces collapse together and are dropped at the start of a line
+                if ! line.is_empty() && space.is_none() {
+                    space = Some((inline.style.font_size * zoom / 2.0) as i32);
+                }
+                continue;
+            },
+            InlineItem::Break(inline) => {
+                let empty_height = (inline.style.font_size * zoom).ceil() as i32;
+                line_y += finish_line(&mut line, line_w, x, line_y, width, empty_height, text_align, fonts, zoom, blocks);
+                line_w = 0;
+                space = None;
+                continue;
+            },
+            InlineItem::Anchor(anchor) => {
+                anchors.entry(anchor.clone()).or_insert(line_y);
+                continue;
+            }
+        };
+
+        // Lines of preformatted text only break where the text does
+        let mut space_w = space.take().unwrap_or(0);
+        if ! line.is_empty() && line_w + space_w + piece.w > width && piece.inline.style.white_space == WhiteSpace::Normal {
+            line_y += finish_line(&mut line, line_w, x, line_y, width, 0, text_align, fonts, zoom, blocks);
+            line_w = 0;
+            space_w = 0;
+        }
+
+        let mut piece = piece;
+        piece.x = line_w + space_w;
+        line_w = piece.x + piece.w;
+        line.push(piece);
+    }
+
+    if ! line.is_empty() {
+        line_y += finish_line(&mut line, line_w, x, line_y, width, 0, text_align, fonts, zoom, blocks);
+    }
+
+    line_y - y
+}
+
+/// Emit the pieces of a line as blocks, aligned within the width, and return the line height
+fn finish_line<'a, 'b>(line: &mut Vec<Piece<'a, 'b>>, line_w: i32, x: i32, y: i32, width: i32, empty_height: i32, text_align: TextAlign, fonts: &'a Fonts, zoom: f32, blocks: &mut Vec<Block<'a>>) -> i32 {
+    let height = line.iter().map(|piece| piece.h).max().unwrap_or(empty_height);
+
+    let shift = match text_align {
+        TextAlign::Left => 0,
+        TextAlign::Center => (width - line_w) / 2,
+        TextAlign::Right => width - line_w,
+    };
+
+    // The end of the last piece, so that a line under or through a run of text also covers its spaces
+    let mut previous: Option<(*const LayoutBox, i32)> = None;
+    for piece in line.drain(..) {
+        let style = &piece.inline.style;
+        let first = blocks.len();
+        if piece.text.is_some() && (style.underline || style.line_through) {
+            let start = match previous {
+                Some((inline, end)) if inline == piece.inline as *const LayoutBox => end,
+                _ => piece.x
+            };
+            let top = y + height - piece.h;
+            let thickness = cmp::max(1, (style.font_size * zoom / 16.0) as i32);
+            if style.underline {
+                blocks.push(rect_block(x + shift + start, top + piece.h * 7 / 8, piece.x + piece.w - start, thickness, style.color));
+            }
+            if style.line_through {
+                blocks.push(rect_block(x + shift + start, top + piece.h * 11 / 20, piece.x + piece.w - start, thickness, style.color));
+            }
+            set_node(&mut blocks[first..], piece.inline.node);
+        }
+        previous = Some((piece.inline as *const LayoutBox, piece.x + piece.w));
+
+        // Pieces of different heights share a bottom edge
+        if let Some(control) = piece.control {
+            control_blocks(control, &piece.inline.style, x + shift + piece.x, y + height - piece.h, piece.w, piece.h, fonts, zoom, blocks);
+            set_node(&mut blocks[first..], piece.inline.node);
+            continue;
+        }
+
+        blocks.push(Block {
+            x: x + shift + piece.x,
+            y: y + height - piece.h,
+            w: piece.w,
+            h: piece.h,
+            color: piece.inline.style.color,
+            string: piece.string.to_string(),
+            link: piece.inline.link.clone(),
+            image: piece.image.map(|image| scale_image(image, piece.w, piece.h)),
+            text: piece.text,
+            background: piece.inline.style.background_color,
+            control: None,
+            node: piece.inline.node,
+        });
+    }
+
+    height
+}
+
+/// The size of a form control, from its number of characters or lines, or the text it shows
+fn control_size(control: &Control, style: &Style, fonts: &Fonts, zoom: f32) -> (i32, i32) {
+    let char_w = (style.font_size * zoom / 2.0).ceil() as i32;
+    let line_h = (style.font_size * zoom).ceil() as i32;
+
+    match control.kind {
+        ControlKind::Text | ControlKind::Password => ((control.size as i32).saturating_mul(char_w).saturating_add(8), line_h + 8),
+        ControlKind::TextArea => {
+            ((control.size as i32).saturating_mul(char_w).saturating_add(8), (control.rows as i32).saturating_mul(line_h).saturating_add(8))
+        },
+        ControlKind::Checkbox | ControlKind::Radio => {
+            let side = cmp::max(8, line_h * 3 / 4);
+            (side, side)
+        },
+        ControlKind::Submit | ControlKind::Reset | ControlKind::Button => {
+            (render_word(&control.label, style, fonts, zoom).width() as i32 + 16, line_h + 8)
+        },
+        ControlKind::Select => {
+            let widest = control.options.iter()
+                .map(|&(ref label, _)| render_word(label, style, fonts, zoom).width() as i32)
+                .max()
+                .unwrap_or(0);
+            (widest + 2 * char_w + 12, line_h + 8)
+        },
+        ControlKind::Hidden => (0, 0)
+    }
+}
+
+/// A block of text inside a control, which clicks on the control pass through
+fn control_text<'a>(control: &Control, x: i32, y: i32, string: &str, color: Color, style: &Style, fonts: &'a Fonts, zoom: f32) -> Block<'a> {
+    let text = render_word(string, style, fonts, zoom);
+    Block {
+        x: x,
+        y: y,
+        w: text.width() as i32,
+        h: text.height() as i32,
+        color: color,
+        string: string.to_string(),
+        link: None,
+        image: None,
+        text: Some(text),
+        background: None,
+        control: Some(control.index),
+        node: None,
+    }
+}
+
+/// Draw a form control with its frame, its current value, and a caret when it has the focus
+fn control_blocks<'a>(control: &Control, style: &Style, x: i32, y: i32, w: i32, h: i32, fonts: &'a Fonts, zoom: f32, blocks: &mut Vec<Block<'a>>) {
+    let background = match control.kind {
+        ControlKind::Submit | ControlKind::Reset | ControlKind::Button => Color::rgb(221, 221, 221),
+        _ => Color::rgb(255, 255, 255)
+    };
+    let mut back = rect_block(x, y, w, h, background);
+    back.control = Some(control.index);
+    blocks.push(back);
+
+    let frame = if control.focused {
+        Color::rgb(0, 0, 255)
+    } else {
+        Color::rgb(128, 128, 128)
+    };
+    border_blocks(x, y, w, h, 1, frame, blocks);
+
+    let color = if control.disabled {
+        Color::rgb(128, 128, 128)
+    } else {
+        style.color
+    };
+    let line_h = (style.font_size * zoom).ceil() as i32;
+
+    match control.kind {
+        ControlKind::Text | ControlKind::Password => {
+            let shown: String = if control.kind == ControlKind::Password {
+                control.value.chars().map(|_| '*').collect()
+            } else {
+                control.value.clone()
+            };
+
+            // A value too long for the control scrolls so that its end stays visible
+            let mut start = 0;
+            while start < shown.len() && render_word(&shown[start..], style, fonts, zoom).width() as i32 > w - 8 {
+                start += shown[start..].chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+            }
+
+            let text = control_text(control, x + 4, y + (h - line_h) / 2, &shown[start..], color, style, fonts, zoom);
+            let caret_x = text.x + text.w;
+            blocks.push(text);
+
+            if control.focused {
+                blocks.push(rect_block(caret_x, y + 4, 1, h - 8, color));
+            }
+        },
+        ControlKind::TextArea => {
+            // Only the last lines that fit are shown, so that typing stays visible
+            let lines: Vec<&str> = control.value.split('\n').collect();
+            let first = lines.len().saturating_sub(control.rows);
+            let mut caret = (x + 4, y + 4);
+            for (i, line) in lines[first..].iter().enumerate() {
+                let text = control_text(control, x + 4, y + 4 + i as i32 * line_h, line, color, style, fonts, zoom);
+                caret = (text.x + text.w, text.y);
+                blocks.push(text);
+            }
+
+            if control.focused {
+                blocks.push(rect_block(caret.0, caret.1, 1, line_h, color));
+            }
+        },
+        ControlKind::Checkbox | ControlKind::Radio => if control.checked {
+            blocks.push(rect_block(x + 3, y + 3, w - 6, h - 6, color));
+        },
+        ControlKind::Submit | ControlKind::Reset | ControlKind::Button => {
+            let text = render_word(&control.label, style, fonts, zoom).width() as i32;
+            blocks.push(control_text(control, x + (w - text) / 2, y + (h - line_h) / 2, &control.label, color, style, fonts, zoom));
+        },
+        ControlKind::Select => {
+            if let Some(&(ref label, _)) = control.options.get(control.selected) {
+                blocks.push(control_text(control, x + 4, y + (h - line_h) / 2, label, color, style, fonts, zoom));
+            }
+
+            let char_w = (style.font_size * zoom / 2.0).ceil() as i32;
+            blocks.push(control_text(control, x + w - char_w - 4, y + (h - line_h) / 2, "v", color, style, fonts, zoom));
+        },
+        ControlKind::Hidden => ()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{place_cells, MAX_COLUMNS};
+
+    #[test]
+    fn table_grid() {
+        // A cell spanning down from the row above takes its columns in the rows below
+        let places = place_cells(&[vec![(1, 2), (2, 1)], vec![(1, 1), (1, 1)], vec![(1, 1)]]);
+        assert_eq!(places, vec![
+            vec![Some((0, 1, 2)), Some((1, 2, 1))],
+            vec![Some((1, 1, 1)), Some((2, 1, 1))],
+            vec![Some((0, 1, 1))],
+        ]);
+
+        // Cells skip every column covered from above, not only the first
+        let places = place_cells(&[vec![(1, 1), (1, 2), (1, 2)], vec![(1, 1), (1, 1)]]);
+        assert_eq!(places[1], vec![Some((0, 1, 1)), Some((3, 1, 1))]);
+
+        assert!(place_cells(&[]).is_empty());
+        assert_eq!(place_cells(&[vec![]]), vec![vec![]]);
+    }
+
+    #[test]
+    fn table_grid_limits() {
+        // A rowspan stops at the last row
+        assert_eq!(place_cells(&[vec![(1, 1000)], vec![(1, 1)]]), vec![vec![Some((0, 1, 2))], vec![Some((1, 1, 1))]]);
+
+        // A colspan stops at the last column, and cells past it are left out
+        let places = place_cells(&[vec![(600, 1), (600, 1), (1, 1)], vec![(1, 1)]]);
+        assert_eq!(places, vec![vec![Some((0, 600, 1)), Some((600, MAX_COLUMNS - 600, 1)), None], vec![Some((0, 1, 1))]]);
+
+        let spans = vec![(1000, 1000); 1000];
+        let places = place_cells(&[spans.clone(), spans]);
+        assert_eq!(places[0][0], Some((0, MAX_COLUMNS, 2)));
+        assert!(places[0][1..].iter().chain(places[1].iter()).all(|place| place.is_none()));
+    }
+}
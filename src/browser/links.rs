@@ -0,0 +1,183 @@
+use orbclient::{Color, Renderer, Window, K_BKSP, K_ESC};
+use orbfont::Font;
+
+use super::Block;
+
+/// The keys hint labels are made of, on the home row
+const HINT_KEYS: &'static str = "asdfghjkl";
+
+const FONT_SIZE: f32 = 12.0;
+
+/// A place the keyboard focus can move to, as the blocks it covers
+#[derive(Clone, Copy, PartialEq)]
+pub struct Stop {
+    pub first: usize,
+    pub last: usize,
+    /// The index of the form control, or `None` for a link
+    pub control: Option<usize>,
+}
+
+/// The links and form controls of a page in document order. The words of a link form one stop,
+/// which text outside the link ends.
+pub fn stops(blocks: &[Block]) -> Vec<Stop> {
+    let mut stops: Vec<Stop> = Vec::new();
+    let mut open = false;
+    for (i, block) in blocks.iter().enumerate() {
+        if let Some(control) = block.control {
+            if ! stops.iter().any(|stop| stop.control == Some(control)) {
+                stops.push(Stop {
+                    first: i,
+                    last: i,
+                    control: Some(control),
+                });
+            }
+            open = false;
+        } else if block.text.is_some() || block.image.is_some() {
+            match block.link {
+                Some(ref link) => {
+                    let extends = open && stops.last().map_or(false, |stop| blocks[stop.first].link.as_ref() == Some(link));
+                    if extends {
+                        if let Some(stop) = stops.last_mut() {
+                            stop.last = i;
+                        }
+                    } else {
+                        stops.push(Stop {
+                            first: i,
+                            last: i,
+                            control: None,
+                        });
+                    }
+                    open = true;
+                },
+                None => open = false
+            }
+        }
+    }
+    stops
+}
+
+/// The link stops of a page, without the controls
+pub fn links(blocks: &[Block]) -> Vec<Stop> {
+    stops(blocks).into_iter().filter(|stop| stop.control.is_none()).collect()
+}
+
+/// Draw a ring around each word of the focused link
+pub fn draw_ring(window: &mut Window, blocks: &[Block], stop: &Stop, offset: (i32, i32)) {
+    let color = Color::rgb(0, 0, 255);
+    for block in blocks[stop.first .. stop.last + 1].iter() {
+        if block.text.is_none() && block.image.is_none() {
+            continue;
+        }
+
+        let x = block.x - offset.0 - 1;
+        let y = block.y - offset.1 - 1;
+        let w = block.w + 2;
+        let h = block.h + 2;
+        window.rect(x, y, w as u32, 1, color);
+        window.rect(x, y + h - 1, w as u32, 1, color);
+        window.rect(x, y, 1, h as u32, color);
+        window.rect(x + w - 1, y, 1, h as u32, color);
+    }
+}
+
+/// What a key press in hint mode does
+pub enum HintInput {
+    Changed,
+    Cancel,
+    /// Follow the link starting at the block
+    Follow(usize),
+}
+
+/// Hint mode, which labels every link in view with a few keys, and follows a link when its keys are typed
+pub struct Hints {
+    /// The label of each link, with its first block
+    labels: Vec<(String, usize)>,
+    typed: String,
+}
+
+impl Hints {
+    /// Label the links in view, or return `None` if there are none
+    pub fn new(blocks: &[Block], offset: (i32, i32), view_w: i32, view_h: i32) -> Option<Hints> {
+        let visible: Vec<usize> = links(blocks).iter()
+            .map(|stop| stop.first)
+            .filter(|&first| {
+                let block = &blocks[first];
+                block.x + block.w > offset.0 && block.x < offset.0 + view_w && block.y + block.h > offset.1 && block.y < offset.1 + view_h
+            })
+            .collect();
+        if visible.is_empty() {
+            return None;
+        }
+
+        // Every label has the same length, so that none is the start of another
+        let keys: Vec<char> = HINT_KEYS.chars().collect();
+        let mut length = 1;
+        let mut count = keys.len();
+        while count < visible.len() {
+            length += 1;
+            count *= keys.len();
+        }
+
+        let labels = visible.iter().enumerate().map(|(i, &first)| {
+            let mut label = Vec::new();
+            let mut n = i;
+            for _ in 0..length {
+                label.push(keys[n % keys.len()]);
+                n /= keys.len();
+            }
+            (label.into_iter().rev().collect(), first)
+        }).collect();
+
+        Some(Hints {
+            labels: labels,
+            typed: String::new(),
+        })
+    }
+
+    pub fn key(&mut self, character: char, scancode: u8) -> HintInput {
+        match scancode {
+            K_ESC => return HintInput::Cancel,
+            K_BKSP => {
+                self.typed.pop();
+                return HintInput::Changed;
+            },
+            _ => ()
+        }
+
+        let character = character.to_lowercase().next().unwrap_or(character);
+        if HINT_KEYS.contains(character) {
+            self.typed.push(character);
+            if let Some(&(_, first)) = self.labels.iter().find(|&&(ref label, _)| *label == self.typed) {
+                return HintInput::Follow(first);
+            }
+
+            // Keys that no label goes on with are ignored
+            if ! self.labels.iter().any(|&(ref label, _)| label.starts_with(&self.typed)) {
+                self.typed.pop();
+            }
+        }
+        HintInput::Changed
+    }
+
+    /// Draw the labels that still match what has been typed, at the start of their links
+    pub fn draw(&self, window: &mut Window, font: &Font, blocks: &[Block], offset: (i32, i32)) {
+        for &(ref label, first) in self.labels.iter() {
+            if ! label.starts_with(&self.typed) {
+                continue;
+            }
+
+            let block = match blocks.get(first) {
+                Some(block) => block,
+                None => continue
+            };
+            let text = font.render(&label.to_uppercase(), FONT_SIZE);
+            let x = block.x - offset.0;
+            let y = block.y - offset.1;
+            let w = text.width() as i32 + 4;
+            let h = text.height() as i32 + 2;
+            window.rect(x, y, w as u32, h as u32, Color::rgb(128, 96, 0));
+            window.rect(x + 1, y + 1, (w - 2) as u32, (h - 2) as u32, Color::rgb(255, 220, 64));
+            text.draw(window, x + 2, y + 1, Color::rgb(0, 0, 0));
+        }
+    }
+}
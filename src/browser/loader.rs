@@ -0,0 +1,217 @@
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use orbimage::{Image, ResizeType};
+use url::Url;
+
+use content;
+use layout::LayoutBox;
+use super::{download, url_parse, Head};
+
+/// How many images of a page are fetched at the same time
+const IMAGE_THREADS: usize = 4;
+
+/// The size icons are shown at in the chrome
+const ICON_SIZE: u32 = 16;
+
+/// What a load reports back to the window
+pub enum Message {
+    /// A description of what the load is doing, for the status bar
+    Progress(String),
+    /// The page has moved to another URL, which the load continues from
+    Redirect(Url),
+    /// The page, with its images still pending, and what its head says about it
+    Document(LayoutBox, Head),
+    /// The icon of the page, scaled for the chrome
+    Icon(Image),
+    /// An image of the page, to be patched into the layout
+    Image(Url, Image),
+    /// The page is a file to save rather than show, with its content if all of it has arrived,
+    /// and otherwise for the downloads manager to fetch
+    Download(Url, Option<Vec<u8>>),
+    /// The page and all of its images have been fetched
+    Done,
+}
+
+/// Lets a worker thread report progress and find out whether its load was cancelled
+#[derive(Clone)]
+pub struct Progress {
+    sender: Sender<Message>,
+    cancelled: Arc<AtomicBool>,
+    revalidate: bool,
+}
+
+impl Progress {
+    pub fn report(&self, status: String) {
+        let _ = self.sender.send(Message::Progress(status));
+    }
+
+    pub fn redirect(&self, url: Url) {
+        let _ = self.sender.send(Message::Redirect(url));
+    }
+
+    pub fn download(&self, url: Url, data: Option<Vec<u8>>) {
+        let _ = self.sender.send(Message::Download(url, data));
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Whether the page has to be requested even if it is fresh in the cache, because it is being reloaded
+    pub fn revalidate(&self) -> bool {
+        self.revalidate
+    }
+}
+
+/// A page being loaded on worker threads. Dropping it cancels the load.
+pub struct Load {
+    receiver: Receiver<Message>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Load {
+    /// Start loading a page, posting the body if there is one, and checking a cached copy with the server if asked to
+    pub fn start(url: Url, body: Option<String>, revalidate: bool) -> Load {
+        let (sender, receiver) = channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let progress = Progress {
+            sender: sender,
+            cancelled: cancelled.clone(),
+            revalidate: revalidate,
+        };
+        thread::spawn(move || load(url, body, progress));
+
+        Load {
+            receiver: receiver,
+            cancelled: cancelled,
+        }
+    }
+
+    /// Collect the messages that have arrived without waiting, ending with `Done` if a worker has gone away
+    pub fn messages(&self) -> Vec<Message> {
+        let mut messages = Vec::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(message) => messages.push(message),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    messages.push(Message::Done);
+                    break;
+                }
+            }
+        }
+        messages
+    }
+}
+
+impl Drop for Load {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+fn load(url: Url, body: Option<String>, progress: Progress) {
+    progress.report(format!("Loading {}", url));
+
+    let mut head = Head::new();
+    let document = url_parse(&url, body.as_ref().map(|body| body.as_str()), Some(&progress), &mut head);
+    if progress.cancelled() {
+        return;
+    }
+
+    // Sites without an icon link usually still have one in the conventional place
+    let icon_url = match head.icon {
+        Some(ref icon_url) => Some(icon_url.clone()),
+        None => if url.scheme() == "http" || url.scheme() == "https" {
+            url.join("/favicon.ico").ok()
+        } else {
+            None
+        }
+    };
+
+    let mut images = Vec::new();
+    document.pending_images(&mut images);
+    let _ = progress.sender.send(Message::Document(document, head));
+
+    if let Some(icon) = icon_url.and_then(|icon_url| icon_download(&icon_url)) {
+        let _ = progress.sender.send(Message::Icon(icon));
+    }
+
+    // Images are fetched in parallel, in document order
+    let count = images.len();
+    images.reverse();
+    let queue = Arc::new(Mutex::new(images));
+    let loaded = Arc::new(AtomicUsize::new(0));
+
+    let mut threads = Vec::new();
+    for _ in 0..cmp::min(IMAGE_THREADS, count) {
+        let queue = queue.clone();
+        let loaded = loaded.clone();
+        let progress = progress.clone();
+        threads.push(thread::spawn(move || {
+            while ! progress.cancelled() {
+                let next = queue.lock().unwrap().pop();
+                match next {
+                    Some(image_url) => {
+                        if let Some(image) = image_download(&image_url) {
+                            let _ = progress.sender.send(Message::Image(image_url, image));
+                        }
+
+                        let done = loaded.fetch_add(1, Ordering::SeqCst) + 1;
+                        progress.report(format!("Loaded {} of {} images", done, count));
+                    },
+                    None => break
+                }
+            }
+        }));
+    }
+
+    for thread in threads {
+        let _ = thread.join();
+    }
+
+    let _ = progress.sender.send(Message::Done);
+}
+
+/// Fetch an image of a page, going by the type its server gives and then by its first bytes,
+/// so that images are found whatever their address ends in
+fn image_download(url: &Url) -> Option<Image> {
+    let (headers, data) = match download(url) {
+        Ok(response) => response,
+        Err(_) => return None
+    };
+
+    let (media_type, _) = content::content_type(&headers);
+    match content::parse_image(&media_type, &data) {
+        Some(Ok(image)) => Some(image),
+        // Servers often send images with a generic or wrong type
+        _ => match content::parse_image(content::sniff(&data), &data) {
+            Some(Ok(image)) => Some(image),
+            _ => None
+        }
+    }
+}
+
+/// Fetch the icon of a page, whatever the type its server claims, and scale it to the size of the chrome
+fn icon_download(url: &Url) -> Option<Image> {
+    let data = match download(url) {
+        Ok((_headers, data)) => data,
+        Err(_) => return None
+    };
+
+    let icon = match content::parse_image(content::sniff(&data), &data) {
+        Some(Ok(icon)) => icon,
+        _ => return None
+    };
+
+    if icon.width() == ICON_SIZE && icon.height() == ICON_SIZE {
+        Some(icon)
+    } else {
+        icon.resize(ICON_SIZE, ICON_SIZE, ResizeType::Lanczos3).ok()
+    }
+}
@@ -1,8 +1,8 @@
 #![deny(warnings)]
 
+extern crate gif;
 extern crate html5ever_atoms;
 extern crate html5ever;
-//extern crate mime_guess;
 extern crate orbclient;
 extern crate orbfont;
 extern crate orbimage;
@@ -10,25 +10,72 @@ extern crate tendril;
 extern crate url;
 extern crate hyper;
 extern crate hyper_rustls;
+extern crate rustls;
 
 
-use std::{cmp, env, str};
+use std::{env, fs, str};
 use std::collections::BTreeMap;
 use std::default::Default;
 use std::ffi::OsStr;
 use std::fs::File;
-use std::io::{stderr, Read, Write};
+use std::io::{self, stderr, Read, Write};
+use std::path::Path;
+use std::process;
 use std::string::String;
+use std::thread;
+use std::time::Duration;
 
 use html5ever::parse_document;
-use html5ever::rcdom::{Document, Doctype, Text, Comment, Element, RcDom, Handle};
-use orbclient::{Color, EventOption, Renderer, Window, WindowFlag, K_BKSP, K_ESC, K_LEFT, K_RIGHT, K_DOWN, K_PGDN, K_UP, K_PGUP};
+use html5ever::rcdom::{Text, Element, RcDom, Handle};
+use orbclient::{Color, EventOption, Renderer, Window, WindowFlag, K_0, K_1, K_9, K_B, K_BKSP, K_C, K_D, K_CTRL, K_EQUALS, K_ESC, K_F, K_F3, K_F12, K_J, K_LEFT, K_LEFT_SHIFT, K_MINUS, K_RIGHT, K_RIGHT_SHIFT, K_DOWN, K_ENTER, K_PGDN, K_UP, K_PGUP, K_T, K_TAB, K_U, K_W};
 use orbfont::Font;
 use tendril::TendrilSink;
-use url::Url;
+use url::{form_urlencoded, Url};
+use hyper::client::RedirectPolicy;
 use hyper::header::{self, Headers};
-use hyper::Client;
-use hyper::net::HttpsConnector;
+use hyper::status::StatusCode;
+
+use bookmarks::Bookmarks;
+use content::escape;
+use css::{Style, Stylesheet, WhiteSpace};
+use downloads::Downloads;
+use error::RequestError;
+use find::FindBar;
+use form::Input;
+use history::History;
+use inspector::{Inspection, Inspector};
+use layout::{BoxKind, LayoutBox};
+use links::{HintInput, Hints};
+use loader::Progress;
+use selection::Selection;
+use settings::Settings;
+use tab::{StripAction, Tab};
+use toolbar::{Action, Toolbar};
+
+mod auth;
+mod bookmarks;
+mod cache;
+mod connection;
+mod content;
+mod cookies;
+mod css;
+mod downloads;
+mod error;
+mod find;
+mod form;
+mod headless;
+mod history;
+mod inspector;
+mod layout;
+mod links;
+mod loader;
+mod selection;
+mod settings;
+mod tab;
+mod toolbar;
+
+/// The height of the toolbar and the tab strip above the page
+const CHROME_HEIGHT: i32 = toolbar::HEIGHT + tab::HEIGHT;
 
 struct Block<'a> {
     x: i32,
@@ -40,6 +87,10 @@ struct Block<'a> {
     link: Option<String>,
     image: Option<orbimage::Image>,
     text: Option<orbfont::Text<'a>>,
+    background: Option<Color>,
+    control: Option<usize>,
+    /// The document node that produced the block, for the inspector
+    node: Option<usize>,
 }
 
 impl<'a> Block<'a> {
@@ -50,10 +101,20 @@ impl<'a> Block<'a> {
         m_x >= x && m_x < x + self.w && m_y >= y && m_y < y + self.h
     }
 
-    fn draw(&self, window: &mut Window, offset: (i32, i32)) {
+    /// Whether the block is a word of the page, which can be selected and searched
+    fn is_text(&self) -> bool {
+        self.text.is_some() && self.control.is_none()
+    }
+
+    /// Draw the block, with a highlight in place of its background if it is selected or found
+    fn draw<R: Renderer>(&self, window: &mut R, offset: (i32, i32), highlight: Option<Color>) {
         let x = self.x - offset.0;
         let y = self.y - offset.1;
         if x + self.w > 0 && x < window.width() as i32 && y + self.h > 0 && y < window.height() as i32 {
+            if let Some(background) = highlight.or(self.background) {
+                window.rect(x, y, self.w as u32, self.h as u32, background);
+            }
+
             if let Some(ref image) = self.image {
                 image.draw(window, x, y);
             }
@@ -65,282 +126,195 @@ impl<'a> Block<'a> {
     }
 }
 
-fn text_block<'a>(string: &str, x: &mut i32, y: &mut i32, size: f32, bold: bool, color: Color, link: Option<String>, font: &'a Font, font_bold: &'a Font, blocks: &mut Vec<Block<'a>>) {
-    let trimmed_left = string.trim_left();
-    let left_margin = string.len() as i32 - trimmed_left.len() as i32;
-    let trimmed_right = trimmed_left.trim_right();
-    let right_margin = trimmed_left.len() as i32 - trimmed_right.len() as i32;
+/// The link of the block under a point of the window, if any
+fn link_at(blocks: &[Block], x: i32, y: i32, offset: (i32, i32)) -> Option<String> {
+    blocks.iter()
+        .find(|block| block.link.is_some() && block.contains(x, y, offset))
+        .and_then(|block| block.link.clone())
+}
 
-    //let escaped_text = escape_default(&trimmed_right);
-    //println!("#text: block {} at {}, {}: '{}'", blocks.len(), *x, *y, escaped_text);
+/// The faces pages are drawn in. The styles other than the regular one are optional,
+/// and text falls back to the closest face that was found.
+pub struct Fonts {
+    regular: Font,
+    bold: Option<Font>,
+    italic: Option<Font>,
+    bold_italic: Option<Font>,
+    monospace: Option<Font>,
+    monospace_bold: Option<Font>,
+}
 
-    *x += left_margin * 8;
+/// Find a face of a typeface from the settings, which may name a family after a slash
+fn find_font(name: &str, style: Option<&str>) -> Result<Font, String> {
+    let mut parts = name.splitn(2, '/');
+    let typeface = parts.next();
+    let family = parts.next();
+    Font::find(typeface, family, style)
+}
 
-    for (word_i, word) in trimmed_right.split(' ').enumerate() {
-        if word_i > 0 {
-            *x += 8;
-        }
+impl Fonts {
+    fn find(settings: &Settings) -> Result<Fonts, String> {
+        let regular = match find_font(&settings.font, None) {
+            Ok(font) => font,
+            Err(_) => Font::find(None, None, None)?
+        };
+
+        Ok(Fonts {
+            regular: regular,
+            bold: find_font(&settings.bold_font, Some("Bold")).or_else(|_| Font::find(None, None, Some("Bold"))).ok(),
+            italic: find_font(&settings.font, Some("Italic")).ok(),
+            bold_italic: find_font(&settings.bold_font, Some("BoldItalic")).ok(),
+            monospace: find_font(&settings.monospace_font, None).ok(),
+            monospace_bold: find_font(&settings.monospace_font, Some("Bold")).ok(),
+        })
+    }
 
-        let text = if bold {
-            font_bold.render(word, size)
+    /// The face for text of a style
+    pub fn get(&self, style: &Style) -> &Font {
+        let font = if style.monospace {
+            if style.bold {
+                self.monospace_bold.as_ref().or(self.monospace.as_ref())
+            } else {
+                self.monospace.as_ref()
+            }
         } else {
-            font.render(word, size)
+            match (style.bold, style.italic) {
+                (true, true) => self.bold_italic.as_ref().or(self.bold.as_ref()),
+                (true, false) => self.bold.as_ref(),
+                (false, true) => self.italic.as_ref(),
+                (false, false) => None
+            }
         };
+        font.unwrap_or(&self.regular)
+    }
+}
 
-        let w = text.width() as i32;
-        let h = text.height() as i32;
+/// What the head of a document says about it, shown in the chrome
+pub struct Head {
+    pub title: String,
+    pub description: String,
+    pub icon: Option<Url>,
+    /// The headers and document tree the page arrived as, shown in the inspector
+    pub inspection: Inspection,
+}
 
-        if *x + w >= 800 && *x > 0 {
-            *x = 0;
-            *y += size.ceil() as i32;
+impl Head {
+    pub fn new() -> Head {
+        Head {
+            title: String::new(),
+            description: String::new(),
+            icon: None,
+            inspection: Inspection::new(),
         }
-
-        blocks.push(Block {
-            x: *x,
-            y: *y,
-            w: w,
-            h: h,
-            color: color,
-            string: word.to_string(),
-            link: link.clone(),
-            image: None,
-            text: Some(text)
-        });
-
-        *x += w;
     }
-
-    *x += right_margin * 8;
 }
 
-fn walk<'a>(handle: Handle, indent: usize, x: &mut i32, y: &mut i32, mut size: f32, mut bold: bool, mut color: Color, mut ignore: bool, whitespace: &mut bool, mut link: Option<String>, url: &Url, font: &'a Font, font_bold: &'a Font, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) {
+/// Find the title, description and icon of a document
+fn find_head(handle: Handle, url: &Url, head: &mut Head) {
     let node = handle.borrow();
 
-    let mut new_line = false;
-
-    //print!("{}", repeat(" ").take(indent).collect::<String>());
-    match node.node {
-        Document
-            => {
-                //println!("#Document")
-            },
-
-        Doctype(ref _name, ref _public, ref _system)
-            => {
-                //println!("<!DOCTYPE {} \"{}\" \"{}\">", *name, *public, *system);
-            },
-
-        Text(ref text)
-            => {
-                let mut string = String::new();
-
-                for c in text.chars() {
-                    match c {
-                        ' ' | '\n' | '\r' => if *whitespace {
-                            // Ignore
-                        } else {
-                            // Set whitespace
-                            *whitespace = true;
-                            string.push(' ');
-                        },
-                        _ => {
-                            if *whitespace {
-                                *whitespace = false;
-                            }
-                            string.push(c);
-                        }
+    if let Element(ref name, _, ref attrs) = node.node {
+        match &*name.local {
+            "title" => if head.title.is_empty() {
+                let mut text = String::new();
+                for child in node.children.iter() {
+                    if let Text(ref child_text) = child.borrow().node {
+                        text.push_str(child_text);
                     }
                 }
-
-                if ! string.is_empty() {
-                    if ignore {
-                        //println!("#text: ignored");
-                    } else {
-                        text_block(&string, x, y, size, bold, color, link.clone(), font, font_bold, blocks);
+                head.title = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+                return;
+            },
+            "meta" => {
+                let mut meta_name = String::new();
+                let mut content = String::new();
+                for attr in attrs.iter() {
+                    match &*attr.name.local {
+                        "name" => meta_name = attr.value.to_lowercase(),
+                        "content" => content = attr.value.to_string(),
+                        _ => ()
                     }
-                } else {
-                    //println!("#text: empty");
+                }
+                if meta_name == "description" && head.description.is_empty() {
+                    head.description = content.split_whitespace().collect::<Vec<&str>>().join(" ");
                 }
             },
-
-        Comment(ref _text)
-            => {
-                //println!("<!-- {} -->", escape_default(text))
-            },
-
-        Element(ref name, _, ref attrs) => {
-            /*
-            assert!(name.ns == ns!(html));
-            //print!("<{}", name.local);
-            for attr in attrs.iter() {
-                assert!(attr.name.ns == ns!());
-                //print!(" {}=\"{}\"", attr.name.local, attr.value);
-            }
-            //println!(">");
-            */
-
-            match &*name.local {
-                "a" => {
-                    color = Color::rgb(0, 0, 255);
-                    for attr in attrs.iter() {
-                        match &*attr.name.local {
-                            "name" => {
-                                anchors.insert(attr.value.to_string(), *y);
-                            },
-                            "href" => {
-                                link = Some(attr.value.to_string());
-                            },
-                            _ => ()
-                        }
+            "link" => {
+                let mut rel = String::new();
+                let mut href_opt = None;
+                for attr in attrs.iter() {
+                    match &*attr.name.local {
+                        "rel" => rel = attr.value.to_lowercase(),
+                        "href" => href_opt = Some(attr.value.to_string()),
+                        _ => ()
                     }
-                },
-                "b" => {
-                    bold = true;
-                },
-                "br" => {
-                    ignore = true;
-                    new_line = true;
-                },
-                "div" => {
-                    new_line = true;
-                },
-                "h1" => {
-                    size = 32.0;
-                    bold = true;
-                    new_line = true;
-                },
-                "h2" => {
-                    size = 24.0;
-                    bold = true;
-                    new_line = true;
-                },
-                "h3" => {
-                    size = 18.0;
-                    bold = true;
-                    new_line = true;
-                }
-                "h4" => {
-                    size = 16.0;
-                    bold = true;
-                    new_line = true;
                 }
-                "h5" => {
-                    size = 14.0;
-                    bold = true;
-                    new_line = true;
+                if head.icon.is_none() && rel.split_whitespace().any(|part| part == "icon") {
+                    head.icon = href_opt.and_then(|href| url.join(&href).ok());
                 }
-                "h6" => {
-                    size = 10.0;
-                    bold = true;
-                    new_line = true;
-                },
-                "hr" => {
-                    new_line = true;
-                },
-                "img" => {
-                    if ! ignore {
-                        let mut src_opt = None;
-                        let mut alt_opt = None;
-                        for attr in attrs.iter() {
-                            match &*attr.name.local {
-                                "src" => src_opt = Some(attr.value.to_string()),
-                                "alt" => alt_opt = Some(attr.value.to_string()),
-                                _ => ()
-                            }
-                        }
+            },
+            _ => ()
+        }
+    }
 
-                        let mut use_alt = true;
-                        if let Some(src) = src_opt {
-                            if src.ends_with(".jpg") || src.ends_with(".jpeg") {
-                                let img_url = url.join(&src).unwrap();
-                                if let Ok((_img_headers, img_data)) = http_download(&img_url) {
-                                    if let Ok(img) = orbimage::parse_jpg(&img_data) {
-                                        use_alt = false;
-
-                                        let w = img.width() as i32;
-                                        let h = img.height() as i32;
-
-                                        blocks.push(Block {
-                                            x: *x,
-                                            y: *y,
-                                            w: w,
-                                            h: h,
-                                            color: color,
-                                            string: String::new(),
-                                            link: link.clone(),
-                                            image: Some(img),
-                                            text: None
-                                        });
-
-                                        *y += h;
-                                    }
-                                }
-                            } else if src.ends_with(".png") {
-                                let img_url = url.join(&src).unwrap();
-                                if let Ok((_img_headers, img_data)) = http_download(&img_url) {
-                                    if let Ok(img) = orbimage::parse_png(&img_data) {
-                                        use_alt = false;
-
-                                        let w = img.width() as i32;
-                                        let h = img.height() as i32;
-
-                                        blocks.push(Block {
-                                            x: *x,
-                                            y: *y,
-                                            w: w,
-                                            h: h,
-                                            color: color,
-                                            string: String::new(),
-                                            link: link.clone(),
-                                            image: Some(img),
-                                            text: None
-                                        });
-
-                                        *y += h;
-                                    }
-                                }
-                            }
-                        }
+    for child in node.children.iter() {
+        find_head(child.clone(), url, head);
+    }
+}
+
+/// Collect the `style` elements and linked stylesheets of a document, in document order
+fn find_stylesheets(handle: Handle, url: &Url, stylesheets: &mut Vec<Stylesheet>) {
+    let node = handle.borrow();
+
+    if let Element(ref name, _, ref attrs) = node.node {
+        let mut rel = String::new();
+        let mut href_opt = None;
+        let mut media = String::from("all");
+        for attr in attrs.iter() {
+            match &*attr.name.local {
+                "rel" => rel = attr.value.to_lowercase(),
+                "href" => href_opt = Some(attr.value.to_string()),
+                "media" => media = attr.value.to_lowercase(),
+                _ => ()
+            }
+        }
+
+        if ! media.contains("all") && ! media.contains("screen") {
+            return;
+        }
 
-                        if use_alt {
-                            if let Some(alt) = alt_opt {
-                                text_block(&alt, x, y, size, bold, color, link.clone(), font, font_bold, blocks);
+        match &*name.local {
+            "style" => {
+                let mut text = String::new();
+                for child in node.children.iter() {
+                    if let Text(ref child_text) = child.borrow().node {
+                        text.push_str(child_text);
+                    }
+                }
+                stylesheets.push(Stylesheet::parse(&text));
+                return;
+            },
+            "link" => if rel.split_whitespace().any(|part| part == "stylesheet") {
+                if let Some(href) = href_opt {
+                    match url.join(&href) {
+                        Ok(href_url) => match download(&href_url) {
+                            Ok((_headers, data)) => stylesheets.push(Stylesheet::parse(&String::from_utf8_lossy(&data))),
+                            Err(err) => {
+                                let _ = write!(stderr(), "* Failed to load stylesheet {}: {}\n", href_url, err);
                             }
+                        },
+                        Err(err) => {
+                            let _ = write!(stderr(), "* Invalid stylesheet URL {}: {}\n", href, err);
                         }
                     }
-
-                    ignore = true;
-                    new_line = true;
-                },
-                "li" => {
-                    new_line = true;
-                },
-                "p" => {
-                    new_line = true;
-                },
-                "tr" => {
-                    new_line = true;
                 }
-
-                "head" => ignore = true,
-                "title" => ignore = true, //TODO: Grab title
-                "link" => ignore = true,
-                "meta" => ignore = true,
-                "script" => ignore = true,
-                "style" => ignore = true,
-                _ => ()
-            }
+            },
+            _ => ()
         }
     }
 
     for child in node.children.iter() {
-        walk(child.clone(), indent + 4, x, y, size, bold, color, ignore, whitespace, link.clone(), url, font, font_bold, anchors, blocks);
-    }
-
-    if new_line {
-        *whitespace = true;
-        *x = 0;
-        *y += size.ceil() as i32;
+        find_stylesheets(child.clone(), url, stylesheets);
     }
 }
 
@@ -349,260 +323,592 @@ pub fn escape_default(s: &str) -> String {
     s.chars().flat_map(|c| c.escape_default()).collect()
 }
 
+/// How many redirects a request follows before giving up
+const MAX_REDIRECTS: usize = 20;
+
+/// The response to a request, from the URL its redirects ended at
+pub struct Response {
+    pub url: Url,
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub data: Vec<u8>,
+}
+
+/// Fetch a resource referenced by a page, which only succeeds if the server sends it
 fn http_download(url: &Url) -> Result<(Headers, Vec<u8>), String> {
-    write!(stderr(), "* Requesting {}\n", url).map_err(|err| format!("{}", err))?;
+    let response = http_request(url, None, None).map_err(|err| err.to_string())?;
+    if response.status.is_success() {
+        Ok((response.headers, response.data))
+    } else {
+        Err(format!("{}: {}", response.url, response.status))
+    }
+}
+
+/// Send a GET request, or a POST request when there is a form body, following redirects
+/// and reporting how much of the response has arrived
+fn http_request(url: &Url, body: Option<&str>, progress: Option<&Progress>) -> Result<Response, RequestError> {
+    let mut url = url.clone();
+    let mut body = body.map(|body| body.to_string());
+    let mut redirects = 0;
+    loop {
+        let response = http_fetch(&url, body.as_ref().map(|body| body.as_str()), progress)?;
+
+        let redirect = match response.status {
+            StatusCode::MovedPermanently | StatusCode::Found | StatusCode::SeeOther | StatusCode::TemporaryRedirect | StatusCode::PermanentRedirect => true,
+            _ => false
+        };
+        let location = match response.headers.get::<header::Location>() {
+            Some(location) if redirect => url.join(location).ok(),
+            _ => None
+        };
+
+        // Redirects never lead out of the web, to local files or pages of the browser
+        let mut next = match location {
+            Some(next) => if next.scheme() == "http" || next.scheme() == "https" {
+                next
+            } else {
+                return Ok(response);
+            },
+            None => return Ok(response)
+        };
+
+        redirects += 1;
+        if redirects > MAX_REDIRECTS {
+            return Err(RequestError::Redirects(url));
+        }
+
+        // Only 307 and 308 repeat a POST, the others turn it into a GET
+        if response.status != StatusCode::TemporaryRedirect && response.status != StatusCode::PermanentRedirect {
+            body = None;
+        }
+
+        // A redirect without a fragment keeps the one of the original URL
+        if next.fragment().is_none() {
+            next.set_fragment(url.fragment());
+        }
+
+        let _ = write!(stderr(), "* Redirected to {}\n", next);
+        if let Some(progress) = progress {
+            progress.redirect(next.clone());
+        }
+        url = next;
+    }
+}
+
+/// Send a single request, without following a redirect
+fn http_fetch(url: &Url, body: Option<&str>, progress: Option<&Progress>) -> Result<Response, RequestError> {
+    // Only GET requests are answered from the cache, and a stale entry is revalidated, as is any entry of a page being reloaded
+    let entry_opt = match body {
+        Some(_) => None,
+        None => cache::get(url)
+    };
+    let revalidate = progress.map_or(false, |progress| progress.revalidate());
+    if let Some(ref entry) = entry_opt {
+        if entry.is_fresh() && ! revalidate {
+            let _ = write!(stderr(), "* Cached {}\n", url);
+            return Ok(Response {
+                url: url.clone(),
+                status: StatusCode::Ok,
+                headers: entry.headers.clone(),
+                data: entry.data.clone(),
+            });
+        }
+    }
+
+    let _ = write!(stderr(), "* Requesting {}\n", url);
+
+    let mut request_headers = Headers::new();
+    if let Some(ref entry) = entry_opt {
+        entry.validators(&mut request_headers);
+    }
+    cookies::request(url, &mut request_headers);
+    auth::request(url, &mut request_headers);
+
+    let mut client = connection::client(url);
+    client.set_redirect_policy(RedirectPolicy::FollowNone);
+    let request = match body {
+        Some(body) => {
+            request_headers.set(header::ContentType::form_url_encoded());
+            client.post(url.clone()).headers(request_headers).body(body)
+        },
+        None => client.get(url.clone()).headers(request_headers)
+    };
+    let mut res = request.send().map_err(|err| match err {
+        hyper::Error::Ssl(err) => RequestError::Tls(format!("{}", err)),
+        // Rustls reports failed handshakes and certificates as invalid data
+        hyper::Error::Io(ref err) if url.scheme() == "https" && err.kind() == io::ErrorKind::InvalidData => RequestError::Tls(format!("{}", err)),
+        hyper::Error::Io(err) => RequestError::Network(format!("{}", err)),
+        err => RequestError::Other(format!("Failed to send request: {}", err))
+    })?;
+    cookies::response(url, &res.headers);
+
+    if res.status == StatusCode::NotModified {
+        if let Some(entry) = entry_opt {
+            let _ = write!(stderr(), "* Not modified {}\n", url);
+            let entry = cache::revalidate(url, entry, &res.headers);
+            return Ok(Response {
+                url: url.clone(),
+                status: StatusCode::Ok,
+                headers: entry.headers,
+                data: entry.data,
+            });
+        }
+    }
 
-    let client = Client::with_connector(HttpsConnector::new(hyper_rustls::TlsClient::new()));
-    let mut res = client.get(url.clone()).send().map_err(|err| format!("Failed to send request: {}", err))?;
+    // A page that cannot be shown is left unread, for the downloads manager to save
+    if let Some(progress) = progress {
+        if body.is_none() && res.status == StatusCode::Ok && content::is_download(&res.headers) {
+            let _ = write!(stderr(), "* Handing {} to downloads\n", url);
+            progress.download(url.clone(), None);
+            return Err(RequestError::Download);
+        }
+    }
+
+    let length = res.headers.get::<header::ContentLength>().map(|length| length.0);
     let mut data = Vec::new();
-    res.read_to_end(&mut data).map_err(|err| format!("Failed to read response: {}", err))?;
+    let mut buf = [0; 16384];
+    loop {
+        if progress.map_or(false, |progress| progress.cancelled()) {
+            return Err(RequestError::Other(format!("Cancelled")));
+        }
+
+        let count = res.read(&mut buf).map_err(|err| RequestError::Network(format!("Failed to read response: {}", err)))?;
+        if count == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..count]);
+
+        if let Some(progress) = progress {
+            progress.report(match length {
+                Some(length) => format!("Received {} of {} bytes", data.len(), length),
+                None => format!("Received {} bytes", data.len())
+            });
+        }
+    }
+
+    let _ = write!(stderr(), "* Received {} bytes\n", data.len());
+
+    if body.is_none() && res.status == StatusCode::Ok {
+        cache::put(url, &res.headers, &data);
+    }
 
-    write!(stderr(), "* Received {} bytes\n", data.len()).map_err(|err| format!("{}", err))?;
+    Ok(Response {
+        url: url.clone(),
+        status: res.status,
+        headers: res.headers.clone(),
+        data: data,
+    })
+}
 
-    Ok((res.headers.clone(), data))
+/// Download a resource referenced by a page, such as a stylesheet, from a file, data or HTTP URL
+fn download(url: &Url) -> Result<(Headers, Vec<u8>), String> {
+    if url.scheme() == "data" {
+        content::data_url(url)
+    } else if url.scheme() == "file" {
+        let path = url.to_file_path().map_err(|_| format!("Invalid file URL: {}", url))?;
+        let mut file = File::open(&path).map_err(|err| format!("Failed to open {}: {}", path.display(), err))?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+        Ok((Headers::new(), data))
+    } else {
+        http_download(url)
+    }
 }
 
-fn read_parse<'a, R: Read>(headers: Headers, r: &mut R, url: &Url, font: &'a Font, font_bold: &'a Font, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) {
-    let content_type = headers.get_raw("content-type").and_then(|x| str::from_utf8(x[0].as_slice()).ok()).unwrap_or("text/plain");
-    let media_type = content_type.split(";").next().unwrap_or("");
-
-    match media_type {
-        "text/plain" => {
-            let mut string = String::new();
-            match r.read_to_string(&mut string) {
-                Ok(_) => {
-                    let mut y = 0;
-                    for line in string.lines() {
-                        text_block(line, &mut 0, &mut y, 12.0, false, Color::rgb(0, 0, 0), None, font, font_bold, blocks);
-                        y += 12;
-                    }
-                },
-                Err(err) => {
-                    let error = format!("Text data not readable: {}", err);
-                    text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, blocks);
-                }
-            }
+fn read_parse(headers: Headers, data: Vec<u8>, url: &Url, progress: Option<&Progress>, head: &mut Head) -> LayoutBox {
+    head.inspection.set_headers(&headers);
+    let (media_type, charset) = content::content_type(&headers);
+
+    // Requests that are not posted leave attachments unread, so only the response to a form gets here with one
+    if content::is_download(&headers) {
+        return pass_download(url, &media_type, data, progress);
+    }
+
+    let media_type = content::effective_type(&media_type, &data);
+
+    let error_style = Style {
+        bold: true,
+        .. Style::default()
+    };
+
+    match &*media_type {
+        "text/html" | "application/xhtml+xml" => {
+            let charset = charset.or_else(|| content::html_charset(&data));
+            let text = content::decode(&data, charset.as_ref().map(|charset| charset.as_str()));
+
+            let dom = parse_document(RcDom::default(), Default::default()).one(text);
+
+            find_head(dom.document.clone(), url, head);
+
+            let mut stylesheets = vec![Stylesheet::user_agent()];
+            find_stylesheets(dom.document.clone(), url, &mut stylesheets);
+
+            head.inspection.add_tree(dom.document.clone(), 0);
+            head.inspection.errors = dom.errors.iter().map(|err| err.to_string()).collect();
+
+            layout::build(dom.document, url, &stylesheets)
         },
-        "text/html" => {
-            match parse_document(RcDom::default(), Default::default()).from_utf8().read_from(r) {
-                Ok(dom) => {
-                    let mut x = 0;
-                    let mut y = 0;
-                    let mut whitespace = false;
-                    walk(dom.document, 0, &mut x, &mut y, 16.0, false, Color::rgb(0, 0, 0), false, &mut whitespace, None, url, font, font_bold, anchors, blocks);
-
-                    if !dom.errors.is_empty() {
-                        /*
-                        println!("\nParse errors:");
-                        for err in dom.errors.into_iter() {
-                            println!("    {}", err);
-                        }
-                        */
-                    }
-                },
-                Err(err) => {
-                    let error = format!("HTML data not readable: {}", err);
-                    text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, blocks);
-                }
-            }
+        _ if content::is_text(&media_type) => {
+            let string = content::decode(&data, charset.as_ref().map(|charset| charset.as_str()));
+
+            let plain_style = Style {
+                font_size: 12.0,
+                monospace: true,
+                white_space: WhiteSpace::Pre,
+                .. Style::default()
+            };
+
+            let mut document = LayoutBox::new(BoxKind::Block, Style::default());
+            layout::preformatted(&string, &plain_style, None, &mut document.children);
+            document
         },
-        "image/jpeg" => {
-            let mut data = Vec::new();
-            match r.read_to_end(&mut data) {
-                Ok(_) => match orbimage::parse_jpg(&data) {
-                    Ok(img) => {
-                        blocks.push(Block {
-                            x: 0,
-                            y: 0,
-                            w: img.width() as i32,
-                            h: img.height() as i32,
-                            color: Color::rgb(0, 0, 0),
-                            string: String::new(),
-                            link: None,
-                            image: Some(img),
-                            text: None
-                        });
-                    },
-                    Err(err) => {
-                        let error = format!("JPG data not readable: {}", err);
-                        text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, blocks);
-                    }
-                },
+        _ => match content::parse_image(&media_type, &data) {
+            Some(Ok(img)) => LayoutBox::image(img),
+            Some(Err(err)) => {
+                let error = format!("Image data not readable: {}", err);
+                LayoutBox::text(&error, error_style)
+            },
+            None => pass_download(url, &media_type, data, progress)
+        }
+    }
+}
+
+/// Hand a response that cannot be shown to the downloads manager, along with the bytes that have arrived,
+/// or say it is unsupported when there is no manager to take it
+fn pass_download(url: &Url, media_type: &str, data: Vec<u8>, progress: Option<&Progress>) -> LayoutBox {
+    match progress {
+        // A local file is already on disk, so there is nothing to save
+        Some(progress) if url.scheme() != "file" => {
+            progress.download(url.clone(), Some(data));
+            LayoutBox::text(&format!("Saving {} to the downloads", url), Style::default())
+        },
+        _ => {
+            let error_style = Style {
+                bold: true,
+                .. Style::default()
+            };
+            LayoutBox::text(&format!("Unsupported content type: {}", media_type), error_style)
+        }
+    }
+}
+
+/// A page listing the entries of a directory, directories first, each linking to its entry
+fn directory_page(path: &Path) -> Result<String, String> {
+    let entries = fs::read_dir(path).map_err(|err| format!("{} not readable: {}", path.display(), err))?;
+
+    // Links follow symbolic links, so entries are listed as what they point to
+    let mut listing = Vec::new();
+    for entry in entries {
+        if let Ok(entry) = entry {
+            let entry_path = entry.path();
+            let metadata = fs::metadata(&entry_path).or_else(|_| entry.metadata());
+            let is_dir = metadata.as_ref().map(|metadata| metadata.is_dir()).unwrap_or(false);
+            let size = metadata.map(|metadata| metadata.len()).unwrap_or(0);
+            let name = entry.file_name().to_string_lossy().into_owned();
+            listing.push((! is_dir, name.to_lowercase(), name, entry_path, size));
+        }
+    }
+    listing.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+
+    let title = format!("Index of {}", path.display());
+    let mut html = format!("<html><head><title>{}</title></head><body><h1>{}</h1><table><tr><th>Name</th><th>Size</th></tr>", escape(&title), escape(&title));
+    if let Some(parent_url) = path.parent().and_then(|parent| Url::from_directory_path(parent).ok()) {
+        html.push_str(&format!("<tr><td><a href=\"{}\">../</a></td><td></td></tr>", escape(parent_url.as_str())));
+    }
+    for &(is_file, _, ref name, ref entry_path, size) in listing.iter() {
+        let (entry_url, label, size) = if is_file {
+            (Url::from_file_path(entry_path), name.clone(), content::format_size(size))
+        } else {
+            (Url::from_directory_path(entry_path), format!("{}/", name), String::new())
+        };
+        if let Ok(entry_url) = entry_url {
+            html.push_str(&format!("<tr><td><a href=\"{}\">{}</a></td><td>{}</td></tr>", escape(entry_url.as_str()), escape(&label), size));
+        }
+    }
+    html.push_str("</table></body></html>");
+    Ok(html)
+}
+
+fn file_parse(url: &Url, head: &mut Head) -> LayoutBox {
+    if let Ok(path) = url.to_file_path() {
+        if path.is_dir() {
+            return match directory_page(&path) {
+                Ok(html) => html_parse(html, url, head),
                 Err(err) => {
-                    let error = format!("JPG stream not readable: {}", err);
-                    text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, blocks);
+                    let error_style = Style {
+                        bold: true,
+                        .. Style::default()
+                    };
+                    LayoutBox::text(&err, error_style)
                 }
-            }
-        },
-        "image/png" => {
+            };
+        }
+
+        if let Ok(mut file) = File::open(&path) {
             let mut data = Vec::new();
-            match r.read_to_end(&mut data){
-                Ok(_) => match orbimage::parse_png(&data) {
-                    Ok(img) => {
-                        blocks.push(Block {
-                            x: 0,
-                            y: 0,
-                            w: img.width() as i32,
-                            h: img.height() as i32,
-                            color: Color::rgb(0, 0, 0),
-                            string: String::new(),
-                            link: None,
-                            image: Some(img),
-                            text: None
-                        });
-                    },
-                    Err(err) => {
-                        let error = format!("PNG data not readable: {}", err);
-                        text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, blocks);
-                    }
-                },
-                Err(err) => {
-                    let error = format!("PNG stream not readable: {}", err);
-                    text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, blocks);
+            if let Err(err) = file.read_to_end(&mut data) {
+                let error_style = Style {
+                    bold: true,
+                    .. Style::default()
+                };
+                return LayoutBox::text(&format!("{} not readable: {}", path.display(), err), error_style);
+            }
+
+            // Files with an unknown extension are left without a type, so that their contents are sniffed
+            let mut headers = Headers::new();
+            let mime_type = match path.extension().unwrap_or(OsStr::new("")).to_str().unwrap_or("").to_lowercase().as_str() {
+                "html" | "htm" => Some("text/html"),
+                "xhtml" => Some("application/xhtml+xml"),
+                "txt" => Some("text/plain"),
+                "jpg" | "jpeg" => Some("image/jpeg"),
+                "png" => Some("image/png"),
+                "gif" => Some("image/gif"),
+                "bmp" => Some("image/x-ms-bmp"),
+                _ => None,
+            };
+            if let Some(mime_type) = mime_type {
+                headers.set(header::ContentType(mime_type.parse().unwrap()));
+            }
+
+            read_parse(headers, data, url, None, head)
+        } else {
+            println!("{} not found", path.display());
+            LayoutBox::text(&format!("{} not found", path.display()), Style::default())
+        }
+    } else {
+        LayoutBox::new(BoxKind::Block, Style::default())
+    }
+}
+
+/// Parse a page generated by the browser itself
+fn html_parse(html: String, url: &Url, head: &mut Head) -> LayoutBox {
+    let mut headers = Headers::new();
+    headers.set(header::ContentType("text/html; charset=utf-8".parse().unwrap()));
+    read_parse(headers, html.into_bytes(), url, None, head)
+}
+
+fn http_parse(url: &Url, body: Option<&str>, progress: Option<&Progress>, head: &mut Head) -> LayoutBox {
+    match http_request(url, body, progress) {
+        Ok(response) => {
+            if response.status == StatusCode::Unauthorized {
+                if let Some(realm) = auth::challenge(&response.headers) {
+                    return html_parse(auth::page(&response.url, &realm), &response.url, head);
                 }
             }
+
+            if response.status.is_client_error() || response.status.is_server_error() {
+                html_parse(error::status_page(&response.url, response.status), &response.url, head)
+            } else {
+                read_parse(response.headers, response.data, &response.url, progress, head)
+            }
         },
-        "image/x-ms-bmp" => {
-            let mut data = Vec::new();
-            match r.read_to_end(&mut data) {
-                Ok(_) => match orbimage::parse_bmp(&data) {
-                    Ok(img) => {
-                        blocks.push(Block {
-                            x: 0,
-                            y: 0,
-                            w: img.width() as i32,
-                            h: img.height() as i32,
-                            color: Color::rgb(0, 0, 0),
-                            string: String::new(),
-                            link: None,
-                            image: Some(img),
-                            text: None
-                        });
-                    },
-                    Err(err) => {
-                        let error = format!("BMP data not readable: {}", err);
-                        text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, blocks);
+        Err(err) => html_parse(error::request_page(url, &err), url, head)
+    }
+}
+
+/// Show a page generated by the browser itself
+fn about_parse(url: &Url, body: Option<&str>, progress: Option<&Progress>, head: &mut Head) -> LayoutBox {
+    let page = match url.path() {
+        "blank" => return LayoutBox::new(BoxKind::Block, Style::default()),
+        "bookmarks" => Some(bookmarks::page()),
+        "history" => Some(history::page()),
+        // The sign in page posts here, and the page that asked is loaded again with the credentials
+        "login" => {
+            let mut fields = BTreeMap::new();
+            for (name, value) in form_urlencoded::parse(body.unwrap_or("").as_bytes()) {
+                fields.insert(name.into_owned(), value.into_owned());
+            }
+            let field = |name: &str| fields.get(name).map(|value| value.as_str()).unwrap_or("");
+
+            match auth::login(field("token"), field("username"), field("password")) {
+                Some(target) => {
+                    if let Some(progress) = progress {
+                        progress.redirect(target.clone());
                     }
+                    return http_parse(&target, None, progress, head);
                 },
-                Err(err) => {
-                    let error = format!("BMP stream not readable: {}", err);
-                    text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, blocks);
-                }
+                None => Some(String::from("<html><head><title>Sign in</title></head><body><h1>Sign in</h1><p>This sign in page has expired. Go back and load the page again.</p></body></html>"))
             }
         },
-        _ => {
-            let error = format!("Unsupported content type: {}", content_type);
-            text_block(&error, &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, blocks);
-        }
+        _ => None
+    };
+
+    match page {
+        Some(html) => html_parse(html, url, head),
+        None => LayoutBox::text(&format!("{} not found", url), Style::default())
     }
 }
 
-fn file_parse<'a>(url: &Url, font: &'a Font, font_bold: &'a Font, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) {
-    if let Ok(path) = url.to_file_path() {
-        if let Ok(mut file) = File::open(&path) {
-            let mut headers = Headers::new();
+/// Show the source of a page as it was received, with line numbers
+fn source_parse(url: &Url, progress: Option<&Progress>, head: &mut Head) -> LayoutBox {
+    let error_style = Style {
+        bold: true,
+        .. Style::default()
+    };
 
-            let mime_type = match path.extension().unwrap_or(OsStr::new("")).to_str().unwrap_or("") {
-                "html" => "text/html",
-                "jpg" | "jpeg" => "image/jpeg",
-                "png" => "image/png",
-                "bmp" => "image/x-ms-bmp",
-                _ => "text/plain",
-            };
+    // The address of the page follows the scheme, and may have a fragment of its own
+    let source = url.as_str()["view-source:".len() ..].split('#').next().unwrap_or("");
+    let source_url = match Url::parse(source) {
+        Ok(source_url) => source_url,
+        Err(err) => return LayoutBox::text(&format!("Invalid address {}: {}", source, err), error_style)
+    };
+    head.title = format!("Source of {}", source_url);
+
+    let result = match source_url.scheme() {
+        "http" | "https" => http_request(&source_url, None, progress)
+            .map(|response| (response.headers, response.data))
+            .map_err(|err| err.to_string()),
+        "file" => download(&source_url),
+        scheme => Err(format!("The source of {} pages cannot be shown", scheme))
+    };
+    let (headers, data) = match result {
+        Ok(response) => response,
+        Err(err) => return LayoutBox::text(&err, error_style)
+    };
+    head.inspection.set_headers(&headers);
 
-            /* TODO {
-                let extension = path.extension().unwrap_or(OsStr::new("")).to_str().unwrap_or("");
-                let mime_type = mime_guess::get_mime_type_str(extension).unwrap_or("application/octet-stream");
-                println!("{:?}", mime_type);
-            } */
+    let (_, charset) = content::content_type(&headers);
+    let charset = charset.or_else(|| content::html_charset(&data));
+    let text = content::decode(&data, charset.as_ref().map(|charset| charset.as_str()));
 
-            headers.set(header::ContentType(mime_type.parse().unwrap()));
+    let source_style = Style {
+        font_size: 12.0,
+        monospace: true,
+        white_space: WhiteSpace::Pre,
+        .. Style::default()
+    };
+    let number_style = Style {
+        color: Color::rgb(128, 128, 128),
+        .. source_style.clone()
+    };
 
-            read_parse(headers, &mut file, url, &font, &font_bold, anchors, blocks);
-        } else {
-            println!("{} not found", path.display());
-        }
+    let mut lines: Vec<&str> = text.split('\n').collect();
+    if lines.len() > 1 && lines[lines.len() - 1].is_empty() {
+        lines.pop();
     }
-}
+    let digits = lines.len().to_string().len();
 
-fn http_parse<'a>(url: &Url, font: &'a Font, font_bold: &'a Font, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) {
-    match http_download(url) {
-        Ok((headers, response)) => {
-            read_parse(headers, &mut response.as_slice(), url, font, font_bold, anchors, blocks);
-        },
-        Err(err) => {
-            let mut headers = Headers::new();
-            headers.set(header::ContentType("text/plain".parse().unwrap()));
-            let response = format!("{}", err).into_bytes();
-            read_parse(headers, &mut response.as_slice(), url, &font, &font_bold, anchors, blocks);
+    let mut document = LayoutBox::new(BoxKind::Block, Style::default());
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            document.children.push(LayoutBox::new(BoxKind::LineBreak, source_style.clone()));
         }
+        layout::preformatted(&format!("{:>width$}  ", i + 1, width = digits), &number_style, None, &mut document.children);
+        layout::preformatted(line, &source_style, None, &mut document.children);
     }
+    document
 }
 
-fn url_parse<'a>(url: &Url, font: &'a Font, font_bold: &'a Font, anchors: &mut BTreeMap<String, i32>, blocks: &mut Vec<Block<'a>>) {
+/// Load and parse a page, filling in what its head says about it
+fn url_parse(url: &Url, body: Option<&str>, progress: Option<&Progress>, head: &mut Head) -> LayoutBox {
     if url.scheme() == "http" || url.scheme() == "https" {
-        http_parse(url, font, font_bold, anchors, blocks)
+        http_parse(url, body, progress, head)
     } else if url.scheme() == "file" {
-        file_parse(url, font, font_bold, anchors, blocks)
+        file_parse(url, head)
+    } else if url.scheme() == "about" {
+        about_parse(url, body, progress, head)
+    } else if url.scheme() == "view-source" {
+        source_parse(url, progress, head)
+    } else if url.scheme() == "data" {
+        match content::data_url(url) {
+            Ok((headers, data)) => read_parse(headers, data, url, progress, head),
+            Err(err) => {
+                let error_style = Style {
+                    bold: true,
+                    .. Style::default()
+                };
+                LayoutBox::text(&err, error_style)
+            }
+        }
     } else {
         println!("{} scheme not found", url.scheme());
+        LayoutBox::text(&format!("{} scheme not found", url.scheme()), Style::default())
     }
 }
 
-fn main_window(arg: &str, font: &Font, font_bold: &Font) {
-    let mut history = vec![];
-
-    let mut url = Url::parse(arg).unwrap();
+fn main_window(arg: &str, fonts: &Fonts) {
+    let font = &fonts.regular;
+    let home = Url::parse(arg).unwrap();
 
-    let window_w = 800;
-    let window_h = 600;
+    let mut window_w = 800;
+    let mut window_h = 600;
     let mut window = Window::new_flags(
-        -1, -1, window_w as u32, window_h as u32,  "Browser", &[WindowFlag::Resizable]
+        -1, -1, window_w as u32, window_h as u32,  "Browser", &[WindowFlag::Async, WindowFlag::Resizable]
     ).unwrap();
 
-    let mut anchors = BTreeMap::new();
-    let mut blocks = Vec::new();
-
-    let mut offset = (0, 0);
-    let mut max_offset = (0, 0);
+    let mut tabs = vec![Tab::new(home.clone())];
+    let mut current = 0;
 
     let mut mouse_down = false;
-
-    let mut reload = true;
+    // Where the left button went down, to tell a click from a drag that selects words
+    let mut mouse_start = (0, 0);
+    let mut dragging = false;
+    let mut middle_down = false;
+    let mut ctrl = false;
+    let mut shift = false;
+    let mut toolbar = Toolbar::new();
+    let mut find = FindBar::new();
+    let mut inspector = Inspector::new();
+    let mut downloads = Downloads::new();
+    // Where the pointer last was, so that scrolling over the inspector scrolls it rather than the page
+    let mut pointer_y = 0;
+    // Labels on the links in view while hint mode is on
+    let mut hints: Option<Hints> = None;
+    let mut history = History::load();
+    let mut bookmarks = Bookmarks::load();
+
+    let mut window_title = String::new();
+    // The tab under the pointer, whose description is shown
+    let mut hover: Option<usize> = None;
+
+    // Whether the address field has to show the current tab again
+    let mut retitle = true;
     let mut redraw = true;
     loop {
-        if reload {
-            reload = false;
-
-            window.set_title(&format!("{} - Browser", url));
+        let mut idle = true;
+
+        // Every tab keeps loading in the background, but only the current one is laid out
+        for (i, tab) in tabs.iter_mut().enumerate() {
+            if tab.reload {
+                tab.start_load();
+                if i == current {
+                    retitle = true;
+                }
+            }
 
-            anchors.clear();
-            blocks.clear();
-            text_block("Loading...", &mut 0, &mut 0, 16.0, true, Color::rgb(0, 0, 0), None, font, font_bold, &mut blocks);
+            if tab.poll(&mut history, &mut downloads) {
+                idle = false;
+                if i == current {
+                    redraw = true;
+                }
+            }
+        }
 
-            {
-                window.set(Color::rgb(255, 255, 255));
+        if downloads.poll() {
+            idle = false;
+            if downloads.open {
+                redraw = true;
+            }
+        }
 
-                for block in blocks.iter() {
-                    block.draw(&mut window, (0, 0));
-                }
+        if retitle {
+            retitle = false;
 
-                window.sync();
+            toolbar.set_url(&tabs[current].url);
+            if find.open {
+                find.search(&tabs[current].blocks);
             }
+            redraw = true;
+        }
 
-            anchors.clear();
-            blocks.clear();
-            url_parse(&url, &font, &font_bold, &mut anchors, &mut blocks);
+        if tabs[current].relayout {
+            tabs[current].layout(window_w, window_h - CHROME_HEIGHT, fonts);
 
-            offset = (0, 0);
-            max_offset = (0, 0);
-            for block in blocks.iter() {
-                if block.x + block.w > max_offset.0 {
-                    max_offset.0 = block.x + block.w;
-                }
-                if block.y + block.h > max_offset.1 {
-                    max_offset.1 = block.y + block.h;
-                }
+            // The blocks a match or a hint refers to have been replaced
+            dragging = false;
+            hints = None;
+            if find.open {
+                find.search(&tabs[current].blocks);
             }
 
             redraw = true;
@@ -611,101 +917,531 @@ fn main_window(arg: &str, font: &Font, font_bold: &Font) {
         if redraw {
             redraw = false;
 
+            let tab = &tabs[current];
+
+            // The title of a page only arrives with the page
+            let title = format!("{} - Browser", tab.title());
+            if title != window_title {
+                window.set_title(&title);
+                window_title = title;
+            }
+
             window.set(Color::rgb(255, 255, 255));
 
-            for block in blocks.iter() {
-                block.draw(&mut window, offset);
+            for (i, block) in tab.blocks.iter().enumerate() {
+                let highlight = if ! block.is_text() {
+                    None
+                } else if tab.selection.as_ref().map_or(false, |selection| selection.contains(i)) {
+                    Some(Color::rgb(179, 215, 255))
+                } else {
+                    find.highlight(i)
+                };
+                block.draw(&mut window, (tab.offset.0, tab.offset.1 - CHROME_HEIGHT), highlight);
+            }
+
+            if let Some(stop) = tab.focused_link() {
+                links::draw_ring(&mut window, &tab.blocks, &stop, (tab.offset.0, tab.offset.1 - CHROME_HEIGHT));
+            }
+            if let Some(ref hints) = hints {
+                hints.draw(&mut window, font, &tab.blocks, (tab.offset.0, tab.offset.1 - CHROME_HEIGHT));
+            }
+            if inspector.open {
+                inspector.draw_outline(&mut window, &tab.blocks, (tab.offset.0, tab.offset.1 - CHROME_HEIGHT));
+            }
+
+            // The address changes without a load when following a link within the page
+            toolbar.set_url(&tab.url);
+
+            // The suggestions of the address field hang over the tab strip
+            tab::draw_strip(&mut window, font, &tabs, current, toolbar::HEIGHT);
+            toolbar.draw(&mut window, font, ! tab.history.is_empty(), ! tab.forward.is_empty(), tab.loading.is_some(), bookmarks.contains(&tab.url));
+
+            let mut bottom = window_h;
+            if find.open {
+                find.draw(&mut window, font);
+                bottom -= find::HEIGHT;
+            }
+            if inspector.open {
+                inspector.draw(&mut window, font, &tab.inspection, &tab.blocks, bottom);
+                bottom -= inspector::HEIGHT;
+            }
+            if downloads.open {
+                downloads.draw(&mut window, font, bottom);
+                bottom -= downloads::HEIGHT;
+            }
+            if let Some(hovered) = hover.and_then(|i| tabs.get(i)) {
+                if hovered.description.is_empty() {
+                    toolbar::draw_status(&mut window, font, &hovered.title(), bottom);
+                } else {
+                    toolbar::draw_status(&mut window, font, &format!("{}: {}", hovered.title(), hovered.description), bottom);
+                }
+            } else if tab.loading.is_some() {
+                toolbar::draw_status(&mut window, font, &tab.status, bottom);
             }
 
             window.sync();
         }
 
         for event in window.events() {
-            match event.to_option() {
-                EventOption::Key(key_event) => if key_event.pressed {
-                    match key_event.scancode {
-                        K_ESC => return,
-                        K_LEFT => {
-                            redraw = true;
-                            offset.0 = cmp::max(0, offset.0 - 60);
-                        },
-                        K_RIGHT => {
+            idle = false;
+            let mut action_opt = None;
+            let mut strip_opt = None;
+            // A link to open in a new tab, and whether to switch to it
+            let mut open_opt: Option<(Url, bool)> = None;
+
+            let tabs_len = tabs.len();
+            let mut resized = false;
+            {
+                let tab = &mut tabs[current];
+                let view_h = window_h - CHROME_HEIGHT;
+                // The inspector sits above the find bar when both are open
+                let inspector_bottom = if find.open { window_h - find::HEIGHT } else { window_h };
+                let inspector_open = inspector.open;
+                let in_inspector = |y: i32| inspector_open && y >= inspector_bottom - inspector::HEIGHT && y < inspector_bottom;
+                // The downloads sit above the inspector
+                let downloads_bottom = if inspector_open { inspector_bottom - inspector::HEIGHT } else { inspector_bottom };
+                let downloads_open = downloads.open;
+                let in_downloads = |y: i32| downloads_open && y >= downloads_bottom - downloads::HEIGHT && y < downloads_bottom;
+                // A block to bring into view, such as the match the find bar moved to
+                let mut scroll_opt = None;
+
+                match event.to_option() {
+                    EventOption::Key(key_event) => if key_event.scancode == K_CTRL {
+                        ctrl = key_event.pressed;
+                    } else if key_event.scancode == K_LEFT_SHIFT || key_event.scancode == K_RIGHT_SHIFT {
+                        shift = key_event.pressed;
+                    } else if key_event.pressed && ctrl && key_event.scancode == K_C {
+                        if let Some(ref selection) = tab.selection {
+                            window.set_clipboard(&selection.text(&tab.blocks));
+                        }
+                    } else if key_event.pressed && ctrl && key_event.scancode == K_F {
+                        toolbar.editing = false;
+                        toolbar.set_url(&tab.url);
+                        tab.set_focus(None);
+
+                        find.open();
+                        find.search(&tab.blocks);
+                        scroll_opt = find.current();
+                        redraw = true;
+                    } else if key_event.pressed && ctrl && key_event.scancode == K_D {
+                        action_opt = Some(Action::Bookmark);
+                    } else if key_event.pressed && ctrl && key_event.scancode == K_B {
+                        action_opt = Url::parse("about:bookmarks").ok().map(Action::Go);
+                    } else if key_event.pressed && ctrl && key_event.scancode == K_T {
+                        open_opt = Some((home.clone(), true));
+                    } else if key_event.pressed && ctrl && key_event.scancode == K_W {
+                        strip_opt = Some(StripAction::Close(current));
+                    } else if key_event.pressed && ctrl && key_event.scancode == K_TAB {
+                        // Ctrl+Tab cycles forward through the tabs, and Ctrl+Shift+Tab backward
+                        strip_opt = Some(StripAction::Select(if shift {
+                            (current + tabs_len - 1) % tabs_len
+                        } else {
+                            (current + 1) % tabs_len
+                        }));
+                    } else if key_event.pressed && ctrl && key_event.scancode >= K_1 && key_event.scancode <= K_9 {
+                        let index = (key_event.scancode - K_1) as usize;
+                        if index < tabs_len {
+                            strip_opt = Some(StripAction::Select(index));
+                        }
+                    } else if key_event.pressed && ctrl && key_event.scancode == K_EQUALS {
+                        tab.zoom(1);
+                    } else if key_event.pressed && ctrl && key_event.scancode == K_MINUS {
+                        tab.zoom(-1);
+                    } else if key_event.pressed && ctrl && key_event.scancode == K_0 {
+                        tab.zoom(0);
+                    } else if key_event.pressed && ctrl && key_event.scancode == K_U {
+                        if tab.url.scheme() != "view-source" {
+                            open_opt = Url::parse(&format!("view-source:{}", tab.url)).ok().map(|source_url| (source_url, true));
+                        }
+                    } else if key_event.pressed && key_event.scancode == K_F3 && find.open {
+                        scroll_opt = find.step(shift);
+                        redraw = true;
+                    } else if key_event.pressed && key_event.scancode == K_F12 {
+                        inspector.toggle();
+                        redraw = true;
+                    } else if key_event.pressed && ctrl && key_event.scancode == K_J {
+                        downloads.toggle();
+                        redraw = true;
+                    } else if key_event.pressed && hints.is_some() {
+                        let input = match hints {
+                            Some(ref mut hints) => hints.key(key_event.character, key_event.scancode),
+                            None => HintInput::Cancel
+                        };
+                        match input {
+                            HintInput::Changed => (),
+                            HintInput::Cancel => hints = None,
+                            // Shift opens the link in a new tab behind the current one
+                            HintInput::Follow(first) => {
+                                hints = None;
+                                let link_url = tab.blocks.get(first).and_then(|block| block.link.as_ref()).and_then(|link| tab.url.join(link).ok());
+                                if let Some(link_url) = link_url {
+                                    if shift {
+                                        open_opt = Some((link_url, false));
+                                    } else {
+                                        tab.navigate(link_url, None);
+                                    }
+                                }
+                            }
+                        }
+                        redraw = true;
+                    } else if key_event.pressed && toolbar.editing {
+                        let text = toolbar.text.clone();
+                        action_opt = toolbar.key(key_event.character, key_event.scancode);
+                        if toolbar.editing && toolbar.text != text {
+                            toolbar.set_suggestions(history.complete(&bookmarks, &toolbar.text));
+                        }
+                        toolbar.set_url(&tab.url);
+                        redraw = true;
+                    } else if key_event.pressed && find.editing {
+                        scroll_opt = find.key(&tab.blocks, key_event.character, key_event.scancode, shift);
+                        redraw = true;
+                    } else if key_event.pressed {
+                        // The focused control takes typing, Tab moves to the next link or control, and Escape leaves it
+                        let mut input = Input::Ignored;
+                        if let Some(index) = tab.focus {
+                            input = match key_event.scancode {
+                                K_ESC => {
+                                    tab.set_focus(None);
+                                    Input::Changed
+                                },
+                                K_TAB => {
+                                    scroll_opt = tab.step_focus(shift);
+                                    Input::Changed
+                                },
+                                _ => form::key(&mut tab.document, index, key_event.character, key_event.scancode)
+                            };
+                        }
+
+                        match input {
+                            Input::Ignored => match key_event.scancode {
+                                // Escape closes the find bar, leaves the focused link, cancels a load, and quits when there is none of them
+                                K_ESC => if find.open {
+                                    find.close();
+                                    redraw = true;
+                                } else if tab.focus_link.is_some() {
+                                    tab.focus_link = None;
+                                    redraw = true;
+                                } else if tab.loading.is_some() {
+                                    action_opt = Some(Action::Stop);
+                                } else {
+                                    return;
+                                },
+                                K_LEFT => {
+                                    let offset = tab.offset;
+                                    tab.scroll_to(offset.0 - 60, offset.1, window_w, view_h);
+                                    redraw = true;
+                                },
+                                K_RIGHT => {
+                                    let offset = tab.offset;
+                                    tab.scroll_to(offset.0 + 60, offset.1, window_w, view_h);
+                                    redraw = true;
+                                },
+                                K_UP => {
+                                    let offset = tab.offset;
+                                    tab.scroll_to(offset.0, offset.1 - 60, window_w, view_h);
+                                    redraw = true;
+                                },
+                                K_PGUP => {
+                                    let offset = tab.offset;
+                                    tab.scroll_to(offset.0, offset.1 - view_h, window_w, view_h);
+                                    redraw = true;
+                                },
+                                K_DOWN => {
+                                    let offset = tab.offset;
+                                    tab.scroll_to(offset.0, offset.1 + 60, window_w, view_h);
+                                    redraw = true;
+                                },
+                                K_PGDN => {
+                                    let offset = tab.offset;
+                                    tab.scroll_to(offset.0, offset.1 + view_h, window_w, view_h);
+                                    redraw = true;
+                                },
+                                K_BKSP => action_opt = Some(Action::Back),
+                                K_TAB => {
+                                    scroll_opt = tab.step_focus(shift);
+                                    redraw = true;
+                                },
+                                // Enter follows the focused link, or opens it in a new tab with Ctrl
+                                K_ENTER => if let Some(stop) = tab.focused_link() {
+                                    let link_url = tab.blocks[stop.first].link.as_ref().and_then(|link| tab.url.join(link).ok());
+                                    if let Some(link_url) = link_url {
+                                        if ctrl {
+                                            open_opt = Some((link_url, false));
+                                        } else {
+                                            tab.navigate(link_url, None);
+                                        }
+                                    }
+                                },
+                                // F labels the links in view for following them with a few keys
+                                K_F => {
+                                    hints = Hints::new(&tab.blocks, tab.offset, window_w, view_h);
+                                    redraw = true;
+                                },
+                                _ => ()
+                            },
+                            Input::Changed => tab.relayout = true,
+                            Input::Submit(submission) => tab.navigate(submission.url, submission.body)
+                        }
+                    },
+                    EventOption::Mouse(mouse_event) => {
+                        pointer_y = mouse_event.y;
+
+                        // Hovering over a tab shows its title and description
+                        let hovered = if mouse_event.y >= toolbar::HEIGHT && mouse_event.y < CHROME_HEIGHT {
+                            tab::tab_at(mouse_event.x, tabs_len, window_w)
+                        } else {
+                            None
+                        };
+                        if hovered != hover {
+                            hover = hovered;
                             redraw = true;
-                            offset.0 = cmp::min(cmp::max(0, max_offset.0 - window_w), offset.0 + 60);
-                        },
-                        K_UP => {
+                        }
+
+                        if mouse_event.middle_button {
+                            middle_down = true;
+                        } else if middle_down {
+                            // Middle-clicking a link opens it in a new tab behind the current one
+                            middle_down = false;
+                            if mouse_event.y >= CHROME_HEIGHT {
+                                if let Some(link) = link_at(&tab.blocks, mouse_event.x, mouse_event.y, (tab.offset.0, tab.offset.1 - CHROME_HEIGHT)) {
+                                    if let Ok(link_url) = tab.url.join(&link) {
+                                        open_opt = Some((link_url, false));
+                                    }
+                                }
+                            }
+                        } else if mouse_event.left_button && ! mouse_down {
+                            mouse_down = true;
+                            mouse_start = (mouse_event.x, mouse_event.y);
+                            if tab.selection.is_some() {
+                                tab.selection = None;
+                                redraw = true;
+                            }
+                        } else if mouse_event.left_button {
+                            // Dragging over the page selects the words between where the button went down and the pointer
+                            let in_page = mouse_start.1 >= CHROME_HEIGHT && ! (find.open && mouse_start.1 >= window_h - find::HEIGHT) && ! in_inspector(mouse_start.1) && ! in_downloads(mouse_start.1);
+                            let moved = (mouse_event.x - mouse_start.0).abs() > 4 || (mouse_event.y - mouse_start.1).abs() > 4;
+                            if ! dragging && in_page && moved {
+                                if let Some(index) = selection::text_at(&tab.blocks, mouse_start.0 + tab.offset.0, mouse_start.1 + tab.offset.1 - CHROME_HEIGHT) {
+                                    tab.selection = Some(Selection::new(index));
+                                    dragging = true;
+                                }
+                            }
+
+                            if dragging {
+                                if let Some(index) = selection::text_at(&tab.blocks, mouse_event.x + tab.offset.0, mouse_event.y + tab.offset.1 - CHROME_HEIGHT) {
+                                    if let Some(ref mut selection) = tab.selection {
+                                        selection.extend(index);
+                                    }
+                                    redraw = true;
+                                }
+                            }
+                        } else if dragging {
+                            // The end of a drag is not a click
+                            mouse_down = false;
+                            dragging = false;
+                        } else if mouse_down && mouse_event.y < toolbar.suggestions_bottom() {
+                            mouse_down = false;
+
+                            action_opt = toolbar.click(mouse_event.x, mouse_event.y);
+                            toolbar.set_url(&tab.url);
+                            if toolbar.editing {
+                                tab.set_focus(None);
+                            }
+                            find.editing = false;
                             redraw = true;
-                            offset.1 = cmp::max(0, offset.1 - 60);
-                        },
-                        K_PGUP => {
+                        } else if mouse_down && mouse_event.y < CHROME_HEIGHT {
+                            mouse_down = false;
+
+                            strip_opt = tab::strip_click(mouse_event.x, tabs_len, window_w);
+                        } else if mouse_down && find.open && mouse_event.y >= window_h - find::HEIGHT {
+                            mouse_down = false;
+
+                            // Clicking the find bar types into it again
+                            find.editing = true;
+                            if toolbar.editing {
+                                toolbar.editing = false;
+                                toolbar.set_url(&tab.url);
+                            }
+                            tab.set_focus(None);
                             redraw = true;
-                            offset.1 = cmp::max(0, offset.1 - 600);
-                        },
-                        K_DOWN => {
+                        } else if mouse_down && in_inspector(mouse_event.y) {
+                            mouse_down = false;
+
+                            // Clicking a node of the tree outlines its blocks and brings the first of them into view
+                            scroll_opt = inspector.click(&tab.inspection, &tab.blocks, mouse_event.y - (inspector_bottom - inspector::HEIGHT));
                             redraw = true;
-                            offset.1 = cmp::min(cmp::max(0, max_offset.1 - window_h), offset.1 + 60);
-                        },
-                        K_PGDN => {
+                        } else if mouse_down && in_downloads(mouse_event.y) {
+                            mouse_down = false;
+
+                            downloads.click(mouse_event.x, mouse_event.y - (downloads_bottom - downloads::HEIGHT), window_w);
                             redraw = true;
-                            offset.1 = cmp::min(cmp::max(0, max_offset.1 - window_h), offset.1 + 600);
-                        },
-                        K_BKSP => if let Some(last_url) = history.pop() {
-                            url = last_url;
-                            reload = true;
-                        },
-                        _ => ()
-                    }
-                },
-                EventOption::Mouse(mouse_event) => if mouse_event.left_button {
-                    mouse_down = true;
-                } else if mouse_down {
-                    mouse_down = false;
+                        } else if mouse_down {
+                            mouse_down = false;
 
-                    let mut link_opt = None;
-                    for block in blocks.iter() {
-                        if block.contains(mouse_event.x, mouse_event.y, offset) {
-                            println!("Click {}", block.string);
-                            if let Some(ref link) = block.link {
-                                link_opt = Some(link.clone());
-                                break;
+                            if toolbar.editing {
+                                toolbar.editing = false;
+                                toolbar.set_url(&tab.url);
+                                redraw = true;
                             }
-                        }
-                    }
-
-                    if let Some(link) = link_opt {
-                        if link.starts_with('#') {
-                            if let Some(anchor) = anchors.get(&link[1..]) {
-                                println!("Anchor {}: {}", link, *anchor);
-                                offset.0 = 0;
-                                offset.1 = *anchor;
+                            if find.editing {
+                                find.editing = false;
+                                redraw = true;
+                            }
+                            if tab.focus_link.is_some() {
+                                tab.focus_link = None;
                                 redraw = true;
-                            } else {
-                                println!("Anchor {} not found", link);
                             }
-                        } else {
-                            history.push(url.clone());
 
-                            url = url.join(&link).unwrap();
+                            let mut link_opt = None;
+                            let mut control_opt = None;
+                            for block in tab.blocks.iter() {
+                                if block.contains(mouse_event.x, mouse_event.y, (tab.offset.0, tab.offset.1 - CHROME_HEIGHT)) {
+                                    println!("Click {}", block.string);
+                                    if let Some(ref link) = block.link {
+                                        link_opt = Some(link.clone());
+                                        break;
+                                    }
+                                    if let Some(control) = block.control {
+                                        control_opt = Some(control);
+                                        break;
+                                    }
+                                }
+                            }
 
-                            println!("Navigate {}: {:#?}", link, url);
+                            // Clicking a control focuses it, and clicking anywhere else takes the focus away
+                            let clicked = control_opt.and_then(|index| if form::focusable(&tab.document, index) { Some(index) } else { None });
+                            tab.set_focus(clicked);
 
-                            reload = true;
+                            if let Some(index) = control_opt {
+                                match form::click(&mut tab.document, index) {
+                                    Input::Ignored => (),
+                                    Input::Changed => tab.relayout = true,
+                                    Input::Submit(submission) => tab.navigate(submission.url, submission.body)
+                                }
+                            } else if let Some(link) = link_opt {
+                                if let Ok(link_url) = tab.url.join(&link) {
+                                    println!("Navigate {}: {:#?}", link, link_url);
+
+                                    // Ctrl+Shift+click saves the link to the downloads, and Ctrl+click opens it in a new tab behind the current one
+                                    if ctrl && shift {
+                                        downloads.start(link_url, None);
+                                    } else if ctrl {
+                                        open_opt = Some((link_url, false));
+                                    } else {
+                                        tab.navigate(link_url, None);
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    EventOption::Scroll(scroll_event) => {
+                        if in_inspector(pointer_y) {
+                            inspector.scroll(&tab.inspection, -scroll_event.y * 3);
+                        } else if in_downloads(pointer_y) {
+                            downloads.scroll(-scroll_event.y);
+                        } else {
+                            let offset = tab.offset;
+                            tab.scroll_to(offset.0 - scroll_event.x * 48, offset.1 - scroll_event.y * 48, window_w, view_h);
                         }
+                        redraw = true;
+                    },
+                    EventOption::Resize(resize_event) => {
+                        window_w = resize_event.width as i32;
+                        window_h = resize_event.height as i32;
+                        resized = true;
+                    },
+                    EventOption::Quit(_) => return,
+                    _ => ()
+                }
+
+                let target = scroll_opt.and_then(|index| tab.blocks.get(index)).map(|block| (block.x, block.y, block.w));
+                if let Some((block_x, block_y, block_w)) = target {
+                    // The block is brought to a third of the way down the page, and across only if it is out of view
+                    let mut x = tab.offset.0;
+                    if block_x < x || block_x + block_w > x + window_w {
+                        x = block_x - window_w / 3;
+                    }
+                    tab.scroll_to(x, block_y - view_h / 3, window_w, view_h);
+                    redraw = true;
+                }
+
+                if let Some(action) = action_opt {
+                    match action {
+                        Action::Back => tab.back(),
+                        Action::Forward => tab.forward(),
+                        Action::Reload => {
+                            tab.reload = true;
+                            tab.revalidate = true;
+                        },
+                        Action::Stop => {
+                            tab.stop();
+                            redraw = true;
+                        },
+                        Action::Bookmark => {
+                            bookmarks.toggle(&tab.url, &tab.title);
+                            redraw = true;
+                        },
+                        Action::Go(new_url) => tab.navigate(new_url, None)
                     }
+                }
+            }
+
+            // Every tab is laid out again at the new size, the others once they are switched to
+            if resized {
+                for tab in tabs.iter_mut() {
+                    tab.relayout = true;
+                }
+            }
+
+            if let Some((new_url, switch)) = open_opt {
+                let index = current + 1;
+                tabs.insert(index, Tab::new(new_url));
+                if switch {
+                    strip_opt = Some(StripAction::Select(index));
+                }
+                redraw = true;
+            }
+
+            // Hints and the node picked in the inspector belong to the blocks of the tab they were made for
+            if strip_opt.is_some() {
+                hints = None;
+                inspector.clear();
+            }
+
+            match strip_opt {
+                Some(StripAction::Select(index)) => if index < tabs.len() && index != current {
+                    current = index;
+                    toolbar.editing = false;
+                    mouse_down = false;
+                    dragging = false;
+                    retitle = true;
                 },
-                EventOption::Scroll(scroll_event) => {
-                    offset.0 = cmp::max(0, cmp::min(cmp::max(0, max_offset.0 - window_w), offset.0 - scroll_event.x * 48));
-                    offset.1 = cmp::max(0, cmp::min(cmp::max(0, max_offset.1 - window_h), offset.1 - scroll_event.y * 48));
+                Some(StripAction::Close(index)) => if index < tabs.len() {
+                    // Closing the last tab closes the window
+                    if tabs.len() == 1 {
+                        return;
+                    }
 
-                    redraw = true;
+                    tabs.remove(index);
+                    if current > index || current == tabs.len() {
+                        current -= 1;
+                    }
+                    toolbar.editing = false;
+                    retitle = true;
                 },
-                EventOption::Resize(_) => {
-                    redraw = true;
+                Some(StripAction::New) => {
+                    tabs.push(Tab::new(home.clone()));
+                    current = tabs.len() - 1;
+                    toolbar.editing = false;
+                    retitle = true;
                 },
-                EventOption::Quit(_) => return,
-                _ => ()
+                None => ()
             }
         }
+
+        if idle && ! retitle && ! tabs[current].relayout && ! redraw && ! tabs.iter().any(|tab| tab.reload) {
+            thread::sleep(Duration::from_millis(10));
+        }
     }
 }
 
@@ -737,11 +1473,23 @@ fn main() {
         }
     };
 
-    match Font::find(None, None, None) {
-        Ok(font) => match Font::find(None, None, Some("Bold")) {
-            Ok(font_bold) => main_window(&env::args().nth(1).unwrap_or("https://www.redox-os.org".to_string()), &font, &font_bold),
-            Err(err) => err_window(&format!("{}", err))
-        },
+    // Options select the headless mode, which writes the page out instead of opening a window
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{}", headless::USAGE);
+        return;
+    }
+    if args.iter().any(|arg| arg.starts_with("--")) {
+        let result = Fonts::find(settings::get()).and_then(|fonts| headless::main(&args, &fonts));
+        if let Err(err) = result {
+            let _ = write!(stderr(), "browser: {}\n{}\n", err, headless::USAGE);
+            process::exit(1);
+        }
+        return;
+    }
+
+    match Fonts::find(settings::get()) {
+        Ok(fonts) => main_window(&args.get(0).cloned().unwrap_or("https://www.redox-os.org".to_string()), &fonts),
         Err(err) => err_window(&format!("{}", err))
     }
 }
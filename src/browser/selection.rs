@@ -0,0 +1,72 @@
+use std::cmp;
+
+use super::Block;
+
+/// A run of words selected by dragging, from the block the drag started on to the block it is over
+pub struct Selection {
+    anchor: usize,
+    end: usize,
+}
+
+impl Selection {
+    pub fn new(index: usize) -> Selection {
+        Selection {
+            anchor: index,
+            end: index,
+        }
+    }
+
+    pub fn extend(&mut self, index: usize) {
+        self.end = index;
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        index >= cmp::min(self.anchor, self.end) && index <= cmp::max(self.anchor, self.end)
+    }
+
+    /// The selected text, with words on the same line separated by spaces and lines by newlines
+    pub fn text(&self, blocks: &[Block]) -> String {
+        let first = cmp::min(self.anchor, self.end);
+        let last = cmp::min(cmp::max(self.anchor, self.end), blocks.len().saturating_sub(1));
+
+        let mut text = String::new();
+        let mut previous: Option<&Block> = None;
+        for block in blocks[first..last + 1].iter().filter(|block| block.is_text()) {
+            if let Some(previous) = previous {
+                if block.y >= previous.y + previous.h {
+                    text.push('\n');
+                } else {
+                    text.push(' ');
+                }
+            }
+            text.push_str(&block.string);
+            previous = Some(block);
+        }
+        text
+    }
+}
+
+/// The word under a point of the document, or the nearest word on the same line
+pub fn text_at(blocks: &[Block], x: i32, y: i32) -> Option<usize> {
+    let mut nearest = None;
+    let mut nearest_distance = 0;
+    for (i, block) in blocks.iter().enumerate() {
+        if ! block.is_text() || y < block.y || y >= block.y + block.h {
+            continue;
+        }
+
+        let distance = if x < block.x {
+            block.x - x
+        } else if x >= block.x + block.w {
+            x - (block.x + block.w) + 1
+        } else {
+            return Some(i);
+        };
+
+        if nearest.is_none() || distance < nearest_distance {
+            nearest = Some(i);
+            nearest_distance = distance;
+        }
+    }
+    nearest
+}
@@ -0,0 +1,104 @@
+use std::env;
+use std::fs::File;
+use std::io::{stderr, Read, Write};
+use std::path::PathBuf;
+use std::sync::{Once, ONCE_INIT};
+
+/// The fonts and text size pages are shown with, the certificates secure connections trust,
+/// and where downloads go, read once from the settings file
+pub struct Settings {
+    /// The typeface of text, such as Sans or Serif, which may name a family after a slash, as in Sans/Fira
+    pub font: String,
+    /// The typeface of bold text, in the same form
+    pub bold_font: String,
+    /// The typeface of code and preformatted text, in the same form
+    pub monospace_font: String,
+    /// The size of text in pixels, which the sizes of headings and other text are relative to
+    pub font_size: f32,
+    /// PEM files of certificate authorities to trust besides the usual ones, one per `certificates` line
+    pub certificates: Vec<String>,
+    /// The directory downloads are saved to, which is ~/Downloads unless set
+    pub downloads: Option<PathBuf>,
+}
+
+impl Settings {
+    fn load() -> Settings {
+        let mut settings = Settings {
+            font: String::from("Sans"),
+            bold_font: String::from("Sans"),
+            monospace_font: String::from("Mono"),
+            font_size: 16.0,
+            certificates: Vec::new(),
+            downloads: env::home_dir().map(|home| home.join("Downloads")),
+        };
+
+        let path = match settings_path() {
+            Some(path) => path,
+            None => return settings
+        };
+        let mut data = String::new();
+        match File::open(&path) {
+            Ok(mut file) => {
+                let _ = file.read_to_string(&mut data);
+            },
+            Err(_) => return settings
+        }
+
+        // Each line is a name and a value separated by an equals sign, and lines starting with # are comments
+        for line in data.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.splitn(2, '=');
+            let name = fields.next().unwrap_or("").trim();
+            let value = fields.next().unwrap_or("").trim();
+            match name {
+                "font" if ! value.is_empty() => settings.font = value.to_string(),
+                "bold_font" if ! value.is_empty() => settings.bold_font = value.to_string(),
+                "monospace_font" if ! value.is_empty() => settings.monospace_font = value.to_string(),
+                "font_size" => match value.parse::<f32>() {
+                    Ok(size) if size >= 6.0 && size <= 72.0 => settings.font_size = size,
+                    _ => {
+                        let _ = write!(stderr(), "* Invalid font size '{}' in {}\n", value, path.display());
+                    }
+                },
+                "certificates" if ! value.is_empty() => settings.certificates.push(value.to_string()),
+                "downloads" if ! value.is_empty() => settings.downloads = Some(PathBuf::from(value)),
+                _ => {
+                    let _ = write!(stderr(), "* Invalid setting '{}' in {}\n", line, path.display());
+                }
+            }
+        }
+
+        settings
+    }
+}
+
+fn settings_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("settings"))
+}
+
+/// The directory of the settings, bookmarks, history and cookies, which is ~/.config/browser
+pub fn config_dir() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(".config").join("browser"))
+}
+
+/// The directory of the page cache, which is ~/.cache/browser as everything in it can be fetched again
+pub fn cache_dir() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(".cache").join("browser"))
+}
+
+static SETTINGS_INIT: Once = ONCE_INIT;
+static mut SETTINGS: *const Settings = 0 as *const Settings;
+
+/// The settings, which are read from the file the first time they are needed
+pub fn get() -> &'static Settings {
+    unsafe {
+        SETTINGS_INIT.call_once(|| {
+            SETTINGS = Box::into_raw(Box::new(Settings::load()));
+        });
+        &*SETTINGS
+    }
+}
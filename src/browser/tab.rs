@@ -0,0 +1,540 @@
+use std::cmp;
+use std::collections::BTreeMap;
+
+use orbclient::{Color, Renderer, Window};
+use orbfont::Font;
+use orbimage::Image;
+use url::Url;
+use url::percent_encoding::percent_decode;
+
+use css::Style;
+use downloads::Downloads;
+use form;
+use history::History;
+use inspector::Inspection;
+use layout::{self, BoxKind, LayoutBox};
+use links::{self, Stop};
+use loader::{Load, Message};
+use selection::Selection;
+use super::{Block, Fonts};
+
+/// The height of the tab strip below the toolbar
+pub const HEIGHT: i32 = 24;
+
+const MAX_TAB_W: i32 = 200;
+const NEW_W: i32 = 24;
+const CLOSE_W: i32 = 16;
+const FONT_SIZE: f32 = 12.0;
+
+/// The zoom levels that zooming in and out steps through
+const ZOOM_LEVELS: [f32; 13] = [0.3, 0.5, 0.67, 0.8, 0.9, 1.0, 1.1, 1.25, 1.5, 1.75, 2.0, 2.5, 3.0];
+
+/// A page being browsed, with its own history, layout and scroll position
+pub struct Tab<'a> {
+    pub url: Url,
+    /// The title of the page, or empty if it has none
+    pub title: String,
+    pub description: String,
+    pub icon: Option<Image>,
+    pub history: Vec<Url>,
+    pub forward: Vec<Url>,
+    /// The address and session history of the page being shown, which the tab goes back to when a load
+    /// turns out to be a file to save
+    shown: Option<(Url, Vec<Url>, Vec<Url>)>,
+    /// The body of a form submitted with POST, sent with the next load only
+    pub post: Option<String>,
+    pub document: LayoutBox,
+    /// What the page arrived as, for the inspector
+    pub inspection: Inspection,
+    pub anchors: BTreeMap<String, i32>,
+    pub blocks: Vec<Block<'a>>,
+    pub offset: (i32, i32),
+    pub max_offset: (i32, i32),
+    /// How much text and images are scaled, which stays with the tab across pages
+    pub zoom: f32,
+    /// The form control with the keyboard focus
+    pub focus: Option<usize>,
+    /// The link with the keyboard focus, counted among the links of the page so that it survives a relayout
+    pub focus_link: Option<usize>,
+    pub selection: Option<Selection>,
+    pub loading: Option<Load>,
+    pub status: String,
+    /// Whether the page has to be fetched again
+    pub reload: bool,
+    /// Whether the next fetch asks the server even if the page is fresh in the cache, as reloading does
+    pub revalidate: bool,
+    /// Whether the page has to be laid out again before it is drawn
+    pub relayout: bool,
+    /// Whether laying the page out scrolls to the fragment of the URL, which lasts until the page has loaded
+    jump: bool,
+}
+
+impl<'a> Tab<'a> {
+    pub fn new(url: Url) -> Tab<'a> {
+        Tab {
+            url: url,
+            title: String::new(),
+            description: String::new(),
+            icon: None,
+            history: Vec::new(),
+            forward: Vec::new(),
+            shown: None,
+            post: None,
+            document: LayoutBox::new(BoxKind::Block, Style::default()),
+            inspection: Inspection::new(),
+            anchors: BTreeMap::new(),
+            blocks: Vec::new(),
+            offset: (0, 0),
+            max_offset: (0, 0),
+            zoom: 1.0,
+            focus: None,
+            focus_link: None,
+            selection: None,
+            loading: None,
+            status: String::new(),
+            reload: true,
+            revalidate: false,
+            relayout: false,
+            jump: false,
+        }
+    }
+
+    /// The title of the page, or its address if it has none
+    pub fn title(&self) -> String {
+        if self.title.is_empty() {
+            self.url.to_string()
+        } else {
+            self.title.clone()
+        }
+    }
+
+    /// Go to a new page, posting the body if there is one, and forget the pages gone back from
+    pub fn navigate(&mut self, url: Url, post: Option<String>) {
+        self.history.push(self.url.clone());
+        self.forward.clear();
+        self.post = post;
+        self.go(url);
+    }
+
+    pub fn back(&mut self) {
+        if let Some(last_url) = self.history.pop() {
+            self.forward.push(self.url.clone());
+            self.go(last_url);
+        }
+    }
+
+    pub fn forward(&mut self) {
+        if let Some(next_url) = self.forward.pop() {
+            self.history.push(self.url.clone());
+            self.go(next_url);
+        }
+    }
+
+    /// Show a URL, only scrolling when it is the current page with another fragment
+    fn go(&mut self, url: Url) {
+        let same_document = self.post.is_none() && (url.fragment().is_some() || self.url.fragment().is_some()) && {
+            let mut page = url.clone();
+            page.set_fragment(None);
+            let mut current = self.url.clone();
+            current.set_fragment(None);
+            page == current
+        };
+
+        self.url = url;
+        if same_document && ! self.reload {
+            self.jump = true;
+            self.relayout = true;
+            self.show();
+        } else {
+            self.reload = true;
+        }
+    }
+
+    /// Remember that the page at the current address is the one being shown
+    fn show(&mut self) {
+        self.shown = Some((self.url.clone(), self.history.clone(), self.forward.clone()));
+    }
+
+    /// Go back to the address and session history of the page being shown, when the one loaded instead
+    /// is not going to be shown
+    fn return_to_shown(&mut self) {
+        if let Some((url, history, forward)) = self.shown.clone() {
+            self.url = url;
+            self.history = history;
+            self.forward = forward;
+        }
+    }
+
+    /// Scroll to the anchor the fragment of the URL names, or to the top if there is no fragment
+    fn jump_to_fragment(&mut self, view_w: i32, view_h: i32) {
+        let y = match self.url.fragment() {
+            Some(fragment) => {
+                let decoded = percent_decode(fragment.as_bytes()).decode_utf8_lossy().into_owned();
+                match self.anchors.get(fragment).or_else(|| self.anchors.get(&decoded)) {
+                    Some(&y) => y,
+                    None => return
+                }
+            },
+            None => 0
+        };
+        self.scroll_to(0, y, view_w, view_h);
+    }
+
+    /// Start fetching the page. Replacing a load that is still running cancels it.
+    pub fn start_load(&mut self) {
+        self.reload = false;
+        self.status = format!("Loading {}", self.url);
+        self.loading = Some(Load::start(self.url.clone(), self.post.take(), self.revalidate));
+        self.revalidate = false;
+    }
+
+    pub fn stop(&mut self) {
+        self.reload = false;
+        self.revalidate = false;
+        self.loading = None;
+    }
+
+    /// Apply what the load of the page has sent, recording the page in the history once it arrives
+    /// and handing a file to the downloads, and return whether anything arrived
+    pub fn poll(&mut self, history: &mut History, downloads: &mut Downloads) -> bool {
+        let messages = match self.loading {
+            Some(ref load) => load.messages(),
+            None => return false
+        };
+
+        let arrived = ! messages.is_empty();
+        for message in messages {
+            match message {
+                Message::Progress(progress) => self.status = progress,
+                Message::Redirect(url) => self.url = url,
+                Message::Document(document, head) => {
+                    history.visit(&self.url, &head.title);
+                    self.title = head.title;
+                    self.description = head.description;
+                    self.icon = None;
+                    self.document = document;
+                    self.inspection = head.inspection;
+                    self.focus = None;
+                    self.focus_link = None;
+                    self.offset = (0, 0);
+                    self.relayout = true;
+                    self.jump = self.url.fragment().is_some();
+                    self.show();
+                },
+                Message::Image(image_url, image) => if self.document.set_image(&image_url, &image) {
+                    self.relayout = true;
+                },
+                Message::Icon(icon) => self.icon = Some(icon),
+                // The tab stays on the page it was showing, and the error page the load goes on to make is dropped
+                Message::Download(url, data) => {
+                    downloads.start(url, data);
+                    self.return_to_shown();
+                    self.loading = None;
+                    break;
+                },
+                Message::Done => self.loading = None
+            }
+        }
+        arrived
+    }
+
+    /// Lay the page out at the width of the window, keeping the scroll position within the page
+    pub fn layout(&mut self, view_w: i32, view_h: i32, fonts: &'a Fonts) {
+        self.relayout = false;
+
+        self.anchors.clear();
+        self.blocks.clear();
+        layout::layout(&self.document, view_w, fonts, self.zoom, &mut self.anchors, &mut self.blocks);
+
+        self.max_offset = (0, 0);
+        for block in self.blocks.iter() {
+            if block.x + block.w > self.max_offset.0 {
+                self.max_offset.0 = block.x + block.w;
+            }
+            if block.y + block.h > self.max_offset.1 {
+                self.max_offset.1 = block.y + block.h;
+            }
+        }
+
+        // The blocks a selection refers to have been replaced
+        self.selection = None;
+
+        let offset = self.offset;
+        self.scroll_to(offset.0, offset.1, view_w, view_h);
+
+        // Images arriving while the page loads move the anchor, so it is scrolled to again until they are all in
+        if self.jump {
+            self.jump_to_fragment(view_w, view_h);
+            if self.loading.is_none() {
+                self.jump = false;
+            }
+        }
+    }
+
+    /// Move the keyboard focus to a form control, or away from every control
+    pub fn set_focus(&mut self, focus: Option<usize>) {
+        if focus != self.focus {
+            self.focus = focus;
+            form::focus(&mut self.document, focus);
+            self.relayout = true;
+        }
+    }
+
+    /// Move the keyboard focus to the next link or form control, or the previous one, wrapping around,
+    /// and return the block to scroll to
+    pub fn step_focus(&mut self, backwards: bool) -> Option<usize> {
+        let stops: Vec<Stop> = links::stops(&self.blocks).into_iter()
+            .filter(|stop| stop.control.map_or(true, |index| form::focusable(&self.document, index)))
+            .collect();
+        if stops.is_empty() {
+            return None;
+        }
+
+        let current = match (self.focus, self.focus_link) {
+            (Some(index), _) => stops.iter().position(|stop| stop.control == Some(index)),
+            (None, Some(link)) => stops.iter().enumerate()
+                .filter(|&(_, stop)| stop.control.is_none())
+                .nth(link)
+                .map(|(position, _)| position),
+            (None, None) => None
+        };
+
+        let count = stops.len();
+        let next = match current {
+            Some(position) => if backwards {
+                (position + count - 1) % count
+            } else {
+                (position + 1) % count
+            },
+            None => if backwards { count - 1 } else { 0 }
+        };
+
+        let stop = stops[next];
+        match stop.control {
+            Some(index) => {
+                self.focus_link = None;
+                self.set_focus(Some(index));
+            },
+            None => {
+                self.focus_link = Some(stops[.. next].iter().filter(|stop| stop.control.is_none()).count());
+                self.set_focus(None);
+            }
+        }
+        Some(stop.first)
+    }
+
+    /// The link with the keyboard focus
+    pub fn focused_link(&self) -> Option<Stop> {
+        self.focus_link.and_then(|link| links::links(&self.blocks).get(link).cloned())
+    }
+
+    /// Zoom in or out by a number of levels, or back to the normal size with 0, keeping the scroll position
+    /// at the same place in the page
+    pub fn zoom(&mut self, steps: i32) {
+        let zoom = if steps == 0 {
+            1.0
+        } else {
+            let last = ZOOM_LEVELS.len() as i32 - 1;
+            let current = ZOOM_LEVELS.iter().position(|&level| level >= self.zoom).unwrap_or(last as usize) as i32;
+            ZOOM_LEVELS[cmp::max(0, cmp::min(last, current + steps)) as usize]
+        };
+
+        if zoom != self.zoom {
+            self.offset.0 = (self.offset.0 as f32 * zoom / self.zoom) as i32;
+            self.offset.1 = (self.offset.1 as f32 * zoom / self.zoom) as i32;
+            self.zoom = zoom;
+            self.relayout = true;
+        }
+    }
+
+    /// Scroll to a position, kept within the page
+    pub fn scroll_to(&mut self, x: i32, y: i32, view_w: i32, view_h: i32) {
+        self.offset.0 = cmp::max(0, cmp::min(cmp::max(0, self.max_offset.0 - view_w), x));
+        self.offset.1 = cmp::max(0, cmp::min(cmp::max(0, self.max_offset.1 - view_h), y));
+    }
+}
+
+/// A request from the tab strip to the browser
+pub enum StripAction {
+    Select(usize),
+    Close(usize),
+    New,
+}
+
+fn tab_width(count: usize, width: i32) -> i32 {
+    cmp::min(MAX_TAB_W, (width - NEW_W - 4) / cmp::max(1, count as i32))
+}
+
+/// Draw the tabs, with the current one joined to the page, and a button to open a new tab
+pub fn draw_strip(window: &mut Window, font: &Font, tabs: &[Tab], current: usize, y: i32) {
+    let width = window.width() as i32;
+    window.rect(0, y, width as u32, HEIGHT as u32, Color::rgb(200, 200, 200));
+
+    let tab_w = tab_width(tabs.len(), width);
+    for (i, tab) in tabs.iter().enumerate() {
+        let x = i as i32 * tab_w;
+        let background = if i == current {
+            Color::rgb(255, 255, 255)
+        } else {
+            Color::rgb(221, 221, 221)
+        };
+        window.rect(x, y, (tab_w - 1) as u32, HEIGHT as u32, background);
+
+        let mut label_x = x + 4;
+        if let Some(ref icon) = tab.icon {
+            icon.draw(window, label_x, y + (HEIGHT - icon.height() as i32) / 2);
+            label_x += icon.width() as i32 + 4;
+        }
+
+        // A label too long for the tab is cut short
+        let label_w = x + tab_w - CLOSE_W - 4 - label_x;
+        let mut label: String = tab.title().chars().take(64).collect();
+        while ! label.is_empty() && font.render(&label, FONT_SIZE).width() as i32 > label_w {
+            label.pop();
+        }
+        let text = font.render(&label, FONT_SIZE);
+        text.draw(window, label_x, y + (HEIGHT - text.height() as i32) / 2, Color::rgb(0, 0, 0));
+
+        let close = font.render("x", FONT_SIZE);
+        close.draw(window, x + tab_w - CLOSE_W + (CLOSE_W - close.width() as i32) / 2 - 2, y + (HEIGHT - close.height() as i32) / 2, Color::rgb(96, 96, 96));
+    }
+
+    let new_x = tabs.len() as i32 * tab_w + 4;
+    window.rect(new_x, y + 2, NEW_W as u32, (HEIGHT - 4) as u32, Color::rgb(240, 240, 240));
+    let new = font.render("+", FONT_SIZE);
+    new.draw(window, new_x + (NEW_W - new.width() as i32) / 2, y + (HEIGHT - new.height() as i32) / 2, Color::rgb(0, 0, 0));
+
+    window.rect(0, y + HEIGHT - 1, width as u32, 1, Color::rgb(128, 128, 128));
+}
+
+/// The tab under a point of the tab strip
+pub fn tab_at(x: i32, count: usize, width: i32) -> Option<usize> {
+    let tab_w = tab_width(count, width);
+    if tab_w > 0 && x >= 0 && ((x / tab_w) as usize) < count {
+        Some((x / tab_w) as usize)
+    } else {
+        None
+    }
+}
+
+/// Handle a click in the tab strip, selecting or closing a tab or opening a new one
+pub fn strip_click(x: i32, count: usize, width: i32) -> Option<StripAction> {
+    let tab_w = tab_width(count, width);
+    if let Some(i) = tab_at(x, count, width) {
+        if x - i as i32 * tab_w >= tab_w - CLOSE_W - 2 {
+            Some(StripAction::Close(i))
+        } else {
+            Some(StripAction::Select(i))
+        }
+    } else {
+        let new_x = count as i32 * tab_w + 4;
+        if x >= new_x && x < new_x + NEW_W {
+            Some(StripAction::New)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::Tab;
+
+    fn url(text: &str) -> Url {
+        Url::parse(text).unwrap()
+    }
+
+    fn urls(urls: &[Url]) -> Vec<&str> {
+        urls.iter().map(|url| url.as_str()).collect()
+    }
+
+    #[test]
+    fn session_history() {
+        let mut tab = Tab::new(url("http://example.com/a"));
+        tab.reload = false;
+        tab.navigate(url("http://example.com/b"), None);
+        tab.navigate(url("http://example.com/c"), Some(String::from("q=1")));
+        assert!(tab.reload);
+        assert_eq!(tab.post, Some(String::from("q=1")));
+        assert_eq!(urls(&tab.history), vec!["http://example.com/a", "http://example.com/b"]);
+
+        tab.back();
+        tab.back();
+        assert_eq!(tab.url.as_str(), "http://example.com/a");
+        assert_eq!(urls(&tab.forward), vec!["http://example.com/c", "http://example.com/b"]);
+
+        // There is nothing before the first page
+        tab.back();
+        assert_eq!(tab.url.as_str(), "http://example.com/a");
+
+        tab.forward();
+        assert_eq!(tab.url.as_str(), "http://example.com/b");
+        assert_eq!(urls(&tab.history), vec!["http://example.com/a"]);
+
+        // Going somewhere new forgets the pages gone back from
+        tab.navigate(url("http://example.com/d"), None);
+        assert!(tab.forward.is_empty());
+        tab.forward();
+        assert_eq!(tab.url.as_str(), "http://example.com/d");
+    }
+
+    #[test]
+    fn fragments() {
+        let mut tab = Tab::new(url("http://example.com/page"));
+        tab.reload = false;
+
+        // Another fragment of the page being shown only scrolls
+        tab.navigate(url("http://example.com/page#section"), None);
+        assert!(tab.jump && ! tab.reload);
+        tab.jump = false;
+        tab.back();
+        assert!(tab.jump && ! tab.reload);
+        assert_eq!(tab.url.as_str(), "http://example.com/page");
+        tab.jump = false;
+
+        // Another page, or a form posted to the same one, is fetched
+        tab.navigate(url("http://example.com/other#section"), None);
+        assert!(tab.reload && ! tab.jump);
+        tab.reload = false;
+        tab.navigate(url("http://example.com/other#top"), Some(String::from("q=1")));
+        assert!(tab.reload && ! tab.jump);
+    }
+
+    #[test]
+    fn downloads_keep_the_page_shown() {
+        let mut tab = Tab::new(url("http://example.com/a"));
+        tab.reload = false;
+        tab.show();
+        tab.navigate(url("http://example.com/b"), None);
+        tab.show();
+
+        // Following a link to a file, or going back or forward to one, stays on the page shown
+        tab.navigate(url("http://example.com/file.zip"), None);
+        tab.return_to_shown();
+        assert_eq!((tab.url.as_str(), urls(&tab.history), urls(&tab.forward)), ("http://example.com/b", vec!["http://example.com/a"], vec![]));
+
+        tab.back();
+        tab.return_to_shown();
+        assert_eq!((tab.url.as_str(), urls(&tab.history), urls(&tab.forward)), ("http://example.com/b", vec!["http://example.com/a"], vec![]));
+
+        tab.back();
+        tab.show();
+        tab.forward();
+        tab.return_to_shown();
+        assert_eq!((tab.url.as_str(), urls(&tab.history), urls(&tab.forward)), ("http://example.com/a", vec![], vec!["http://example.com/b"]));
+
+        // Moving to another fragment of the page shown is remembered too
+        tab.reload = false;
+        tab.navigate(url("http://example.com/a#part"), None);
+        tab.navigate(url("http://example.com/file.zip"), None);
+        tab.return_to_shown();
+        assert_eq!((tab.url.as_str(), urls(&tab.history), urls(&tab.forward)), ("http://example.com/a#part", vec!["http://example.com/a"], vec![]));
+
+        // A tab that has not shown a page yet stays where it is
+        let mut tab = Tab::new(url("http://example.com/file.zip"));
+        tab.return_to_shown();
+        assert_eq!((tab.url.as_str(), tab.history.len(), tab.forward.len()), ("http://example.com/file.zip", 0, 0));
+    }
+}
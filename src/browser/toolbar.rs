@@ -0,0 +1,256 @@
+use std::cmp;
+
+use orbclient::{Color, Renderer, Window, K_BKSP, K_DOWN, K_ENTER, K_ESC, K_UP};
+use orbfont::Font;
+use url::Url;
+
+use history::Suggestion;
+
+/// The height of the toolbar strip above the content
+pub const HEIGHT: i32 = 28;
+
+const BUTTON_W: i32 = 24;
+const BUTTONS: [&'static str; 5] = ["<", ">", "R", "X", "*"];
+const FONT_SIZE: f32 = 14.0;
+const SUGGESTION_H: i32 = 36;
+
+/// A request from the toolbar to the browser
+pub enum Action {
+    Back,
+    Forward,
+    Reload,
+    Stop,
+    /// Bookmark the current page, or remove its bookmark
+    Bookmark,
+    /// Navigate to the address typed into the URL field
+    Go(Url),
+}
+
+/// The toolbar, with back, forward, reload, stop and bookmark buttons and an editable URL field
+pub struct Toolbar {
+    pub text: String,
+    pub editing: bool,
+    /// Pages from the bookmarks and history matching the typed address, shown below the field
+    pub suggestions: Vec<Suggestion>,
+    selected: Option<usize>,
+}
+
+impl Toolbar {
+    pub fn new() -> Toolbar {
+        Toolbar {
+            text: String::new(),
+            editing: false,
+            suggestions: Vec::new(),
+            selected: None,
+        }
+    }
+
+    /// Show the address of the current page, unless the user is typing a new one
+    pub fn set_url(&mut self, url: &Url) {
+        if ! self.editing {
+            self.text = url.to_string();
+            self.suggestions.clear();
+            self.selected = None;
+        }
+    }
+
+    /// Replace the suggestions after the typed address changed
+    pub fn set_suggestions(&mut self, suggestions: Vec<Suggestion>) {
+        self.suggestions = suggestions;
+        self.selected = None;
+    }
+
+    /// The bottom of the list of suggestions, which takes clicks while the field is edited
+    pub fn suggestions_bottom(&self) -> i32 {
+        if self.editing {
+            HEIGHT + self.suggestions.len() as i32 * SUGGESTION_H
+        } else {
+            HEIGHT
+        }
+    }
+
+    fn field_x() -> i32 {
+        4 + BUTTONS.len() as i32 * (BUTTON_W + 4)
+    }
+
+    pub fn draw(&self, window: &mut Window, font: &Font, can_back: bool, can_forward: bool, loading: bool, bookmarked: bool) {
+        let width = window.width() as i32;
+        window.rect(0, 0, width as u32, HEIGHT as u32, Color::rgb(221, 221, 221));
+        window.rect(0, HEIGHT - 1, width as u32, 1, Color::rgb(128, 128, 128));
+
+        for (i, label) in BUTTONS.iter().enumerate() {
+            let enabled = match i {
+                0 => can_back,
+                1 => can_forward,
+                3 => loading,
+                _ => true
+            };
+            let color = if i == 4 && bookmarked {
+                Color::rgb(224, 160, 0)
+            } else if enabled {
+                Color::rgb(0, 0, 0)
+            } else {
+                Color::rgb(160, 160, 160)
+            };
+
+            let x = 4 + i as i32 * (BUTTON_W + 4);
+            window.rect(x, 4, BUTTON_W as u32, (HEIGHT - 8) as u32, Color::rgb(240, 240, 240));
+            let text = font.render(label, FONT_SIZE);
+            text.draw(window, x + (BUTTON_W - text.width() as i32) / 2, (HEIGHT - text.height() as i32) / 2, color);
+        }
+
+        let field_x = Toolbar::field_x();
+        let field_w = width - field_x - 4;
+        if field_w <= 8 {
+            return;
+        }
+
+        let frame = if self.editing {
+            Color::rgb(0, 0, 255)
+        } else {
+            Color::rgb(128, 128, 128)
+        };
+        window.rect(field_x, 4, field_w as u32, (HEIGHT - 8) as u32, frame);
+        window.rect(field_x + 1, 5, (field_w - 2) as u32, (HEIGHT - 10) as u32, Color::rgb(255, 255, 255));
+
+        // An address too long for the field scrolls so that its end stays visible
+        let mut start = 0;
+        while start < self.text.len() && font.render(&self.text[start..], FONT_SIZE).width() as i32 > field_w - 8 {
+            start += self.text[start..].chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+        }
+
+        let text = font.render(&self.text[start..], FONT_SIZE);
+        let text_y = (HEIGHT - text.height() as i32) / 2;
+        text.draw(window, field_x + 4, text_y, Color::rgb(0, 0, 0));
+
+        if self.editing {
+            window.rect(field_x + 4 + text.width() as i32, 7, 1, (HEIGHT - 14) as u32, Color::rgb(0, 0, 0));
+            self.draw_suggestions(window, font, field_x, field_w);
+        }
+    }
+
+    /// Draw the suggestions below the URL field, each with its title above its address
+    fn draw_suggestions(&self, window: &mut Window, font: &Font, x: i32, w: i32) {
+        for (i, suggestion) in self.suggestions.iter().enumerate() {
+            let y = HEIGHT + i as i32 * SUGGESTION_H;
+            let background = if Some(i) == self.selected {
+                Color::rgb(179, 215, 255)
+            } else {
+                Color::rgb(255, 255, 255)
+            };
+            window.rect(x, y, w as u32, SUGGESTION_H as u32, Color::rgb(128, 128, 128));
+            window.rect(x + 1, y, (w - 2) as u32, (SUGGESTION_H - 1) as u32, background);
+
+            let title = if suggestion.title.is_empty() {
+                &suggestion.url
+            } else {
+                &suggestion.title
+            };
+            for (line, &(string, color)) in [(title, Color::rgb(0, 0, 0)), (&suggestion.url, Color::rgb(0, 102, 0))].iter().enumerate() {
+                // Lines too long for the list are cut short
+                let mut string: String = string.chars().take(120).collect();
+                while ! string.is_empty() && font.render(&string, FONT_SIZE).width() as i32 > w - 8 {
+                    string.pop();
+                }
+                let text = font.render(&string, FONT_SIZE);
+                text.draw(window, x + 4, y + 2 + line as i32 * (SUGGESTION_H / 2 - 1), color);
+            }
+        }
+    }
+
+    /// Handle a click in the toolbar, returning the action of a button or starting to edit the URL field
+    pub fn click(&mut self, x: i32, y: i32) -> Option<Action> {
+        // A click on a suggestion goes to its page
+        if self.editing && y >= HEIGHT {
+            let index = ((y - HEIGHT) / SUGGESTION_H) as usize;
+            self.editing = false;
+            return self.suggestions.get(index).and_then(|suggestion| Url::parse(&suggestion.url).ok()).map(Action::Go);
+        }
+
+        if x >= Toolbar::field_x() {
+            self.editing = true;
+            return None;
+        }
+
+        self.editing = false;
+        if x < 4 || (x - 4) % (BUTTON_W + 4) >= BUTTON_W {
+            return None;
+        }
+
+        match (x - 4) / (BUTTON_W + 4) {
+            0 => Some(Action::Back),
+            1 => Some(Action::Forward),
+            2 => Some(Action::Reload),
+            3 => Some(Action::Stop),
+            4 => Some(Action::Bookmark),
+            _ => None
+        }
+    }
+
+    /// Handle a key press while editing the URL field, going to the address or the chosen suggestion on Enter
+    pub fn key(&mut self, character: char, scancode: u8) -> Option<Action> {
+        match scancode {
+            K_ENTER => {
+                self.editing = false;
+                let chosen = self.selected.and_then(|i| self.suggestions.get(i)).and_then(|suggestion| Url::parse(&suggestion.url).ok());
+                match chosen.or_else(|| parse_address(&self.text)) {
+                    Some(url) => Some(Action::Go(url)),
+                    None => None
+                }
+            },
+            K_UP => {
+                self.selected = match self.selected {
+                    Some(0) | None => None,
+                    Some(i) => Some(i - 1)
+                };
+                None
+            },
+            K_DOWN => {
+                if ! self.suggestions.is_empty() {
+                    self.selected = Some(self.selected.map_or(0, |i| cmp::min(i + 1, self.suggestions.len() - 1)));
+                }
+                None
+            },
+            K_ESC => {
+                self.editing = false;
+                None
+            },
+            K_BKSP => {
+                self.text.pop();
+                None
+            },
+            _ => {
+                if character != '\0' && ! character.is_control() {
+                    self.text.push(character);
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Draw the progress of a load in a strip ending at `bottom`, along the bottom of the window
+pub fn draw_status(window: &mut Window, font: &Font, status: &str, bottom: i32) {
+    let text = font.render(status, FONT_SIZE);
+    let w = text.width() as i32 + 8;
+    let h = text.height() as i32 + 4;
+    let y = bottom - h;
+    window.rect(0, y, w as u32, h as u32, Color::rgb(221, 221, 221));
+    text.draw(window, 4, y + 2, Color::rgb(0, 0, 0));
+}
+
+/// Parse a typed address, assuming HTTP when no scheme was given
+fn parse_address(text: &str) -> Option<Url> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+
+    // Pages of the browser itself, inline data and page sources have no host to assume a scheme for
+    let hostless = ["about:", "data:", "view-source:"].iter().any(|scheme| text.starts_with(scheme));
+    if text.contains("://") || hostless {
+        Url::parse(text).ok()
+    } else {
+        Url::parse(&format!("http://{}", text)).ok()
+    }
+}